@@ -1,27 +1,51 @@
 use std::{
-    cell::Cell,
+    cell::{Cell, RefCell},
     cmp::Ordering,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs, io,
+    process::ExitCode,
+    rc::Rc,
+    time::{Duration, Instant},
 };
 
 use chrono::{DateTime, Utc};
+use clap::{ArgMatches, parser::ValueSource};
+use color_eyre::eyre::{WrapErr, bail};
 use config::Config;
 use ratatui::{
     DefaultTerminal,
-    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
-    layout::Offset,
+    crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind},
+    layout::{Offset, Position, Rect},
 };
 
 use crate::{
-    Args,
-    event::{AppEvent, Event, EventHandler},
-    scope::read_scope_file,
-    services::lib::{CounterEvent, DiscoveryEvent, LidPort, Node},
+    Args, clipboard,
+    event::{AppEvent, Event, EventHandler, SHUTDOWN_TIMEOUT},
+    export::{
+        export_nodes_json, export_ports_csv, format_node_summary, timestamped_path,
+        write_nodes_json, write_nodes_text,
+    },
+    logging::push_log_line,
+    names::read_name_map,
+    prometheus::{MetricsText, NodeMetrics, render_metrics, serve},
+    record::{CounterStream, Recorder, StreamNode},
+    scope::{is_json_scope_file, read_scope_snapshot, write_scope_file},
+    services::{
+        ibmad::{ERROR_COUNTERS, WRAPPING_COUNTERS},
+        lib::{
+            CounterEvent, CounterKey, CountersMap, DiscoveryEvent, DiscoveryStats, LidPort,
+            LinkStatus, Node, NodeType, QUERY_RETRIES_KEY, RequestId, is_query_failed,
+        },
+    },
+    state::{PersistedState, config_dir, load_state, save_state},
     ui::{
-        forms::{NodeDetailsForm, SearchForm},
+        forms::{SearchField, SearchForm, fuzzy_score},
         helpers::{
-            centered_rect_percent_w_lines_h, count_errors, get_bw, get_bw_loss, get_error_strings,
+            SAMPLE_INTERVAL_KEY, WRAPPED_KEY_SUFFIX, centered_rect_percent_w_lines_h, count_errors,
+            format_bw, format_guid, get_bw, get_bw_loss, get_error_strings, get_line_rate,
+            get_metric,
         },
+        theme::Theme,
     },
 };
 
@@ -32,11 +56,45 @@ pub const DETAILS_POPUP_PERCENT_WIDTH: u16 = 90;
 pub const DETAILS_POPUP_PERCENT_HEIGHT: u16 = 80;
 
 pub const AGG_COUNTERS_PORT: i32 = 255;
-pub const TICK_RESET_INTERVAL: usize = 30;
-pub const MAX_SORT_COLUMNS: i32 = 9;
+
+/// Rows scrolled per mouse wheel step
+pub const MOUSE_SCROLL_ROWS: usize = 3;
+
+/// Ticks each phase of the bandwidth alert row flash lasts (half a second at 30 FPS)
+pub const ALERT_FLASH_TICKS: usize = 15;
+
+pub const ALERTS_POPUP_PERCENT_WIDTH: u16 = 60;
+pub const ALERTS_POPUP_PERCENT_HEIGHT: u16 = 60;
+/// Number of bandwidth alerts kept for the alerts popup
+pub const MAX_BW_ALERTS: usize = 100;
+
+/// Counters older than this many update intervals are shown as stale
+pub const STALE_AFTER_INTERVALS: u32 = 2;
+
+/// Counters the METRIC column can show, in the order the metric popup lists them: the data
+/// counters, every one of `ERROR_COUNTERS`, then the discard and packet counters
+pub const METRIC_COUNTERS: [&str; ERROR_COUNTERS.len() + 6] = {
+    const BEFORE: [&str; 3] = ["rcv_bytes", "xmt_bytes", "xmit_waits"];
+    const AFTER: [&str; 3] = ["xmit_discards", "xmit_pkts", "rcv_pkts"];
+
+    let mut counters = [""; ERROR_COUNTERS.len() + 6];
+    let mut i = 0;
+    while i < counters.len() {
+        counters[i] = if i < BEFORE.len() {
+            BEFORE[i]
+        } else if i < BEFORE.len() + ERROR_COUNTERS.len() {
+            ERROR_COUNTERS[i - BEFORE.len()]
+        } else {
+            AFTER[i - BEFORE.len() - ERROR_COUNTERS.len()]
+        };
+        i += 1;
+    }
+    counters
+};
+pub const DEFAULT_METRIC_COUNTER: &str = "xmit_discards";
 
 /// Represents different modes for displaying counter data.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum CounterMode {
     /// Display raw counter values
     Whole,
@@ -44,6 +102,338 @@ pub enum CounterMode {
     Delta,
     /// Display values relative to a baseline
     Baseline,
+    /// Display per-second rates over the interval between the last two updates
+    Rate,
+}
+
+/// Which ports the details popup queries and lists.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DetailsPorts {
+    /// One row per port
+    #[default]
+    PerPort,
+    /// One row per port plus the node total (`AGG_COUNTERS_PORT`)
+    WithTotal,
+    /// Only the node total, a single query
+    TotalOnly,
+}
+
+impl DetailsPorts {
+    pub fn next(self) -> Self {
+        match self {
+            DetailsPorts::PerPort => DetailsPorts::WithTotal,
+            DetailsPorts::WithTotal => DetailsPorts::TotalOnly,
+            DetailsPorts::TotalOnly => DetailsPorts::PerPort,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DetailsPorts::PerPort => "ports",
+            DetailsPorts::WithTotal => "ports + total",
+            DetailsPorts::TotalOnly => "total",
+        }
+    }
+}
+
+/// What the details popup's port table is sorted by, in the order `s` cycles through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DetailsSortColumn {
+    #[default]
+    Pt,
+    State,
+    Node,
+    RecvBw,
+    SendBw,
+    BwLoss,
+    ErrCnt,
+    ErrStr,
+}
+
+impl DetailsSortColumn {
+    pub const ALL: [DetailsSortColumn; 8] = [
+        DetailsSortColumn::Pt,
+        DetailsSortColumn::State,
+        DetailsSortColumn::Node,
+        DetailsSortColumn::RecvBw,
+        DetailsSortColumn::SendBw,
+        DetailsSortColumn::BwLoss,
+        DetailsSortColumn::ErrCnt,
+        DetailsSortColumn::ErrStr,
+    ];
+
+    /// The column after this one, wrapping back to `Pt`.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&c| c == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// Direction the column is first sorted in, as for [`SortColumn::default_ascending`].
+    pub fn default_ascending(self) -> bool {
+        match self {
+            DetailsSortColumn::Pt
+            | DetailsSortColumn::State
+            | DetailsSortColumn::Node
+            | DetailsSortColumn::ErrStr => true,
+            DetailsSortColumn::RecvBw
+            | DetailsSortColumn::SendBw
+            | DetailsSortColumn::BwLoss
+            | DetailsSortColumn::ErrCnt => false,
+        }
+    }
+
+    /// Header name of the column.
+    pub fn name(self) -> &'static str {
+        match self {
+            DetailsSortColumn::Pt => "PT",
+            DetailsSortColumn::State => "STATE",
+            DetailsSortColumn::Node => "NODE",
+            DetailsSortColumn::RecvBw => "RECV_BW",
+            DetailsSortColumn::SendBw => "SEND_BW",
+            DetailsSortColumn::BwLoss => "BW_LOSS",
+            DetailsSortColumn::ErrCnt => "ERR_CNT",
+            DetailsSortColumn::ErrStr => "ERR_STR",
+        }
+    }
+
+    /// Compare two details popup rows by this column.
+    pub fn compare(self, a: &DetailsPortInfo, b: &DetailsPortInfo) -> Ordering {
+        match self {
+            DetailsSortColumn::Pt => a.port.cmp(&b.port),
+            DetailsSortColumn::State => a.link_state.cmp(&b.link_state),
+            DetailsSortColumn::Node => a.remote_description.cmp(&b.remote_description),
+            DetailsSortColumn::RecvBw => {
+                a.recv_bw.partial_cmp(&b.recv_bw).unwrap_or(Ordering::Equal)
+            }
+            DetailsSortColumn::SendBw => {
+                a.send_bw.partial_cmp(&b.send_bw).unwrap_or(Ordering::Equal)
+            }
+            DetailsSortColumn::BwLoss => {
+                a.bw_loss.partial_cmp(&b.bw_loss).unwrap_or(Ordering::Equal)
+            }
+            DetailsSortColumn::ErrCnt => a.error_count.cmp(&b.error_count),
+            DetailsSortColumn::ErrStr => a.error_strings.cmp(&b.error_strings),
+        }
+    }
+}
+
+/// Unit family used to display bandwidth.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum RateUnits {
+    /// Bits (Kbps, Mbps, Gbps, ...)
+    #[default]
+    Bits,
+    /// Bytes (KB/s, MB/s, GB/s, ...)
+    Bytes,
+}
+
+/// Which PerfMgt query reads a port's counters.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum CountersQuery {
+    /// PortCountersExtended, falling back to PortCounters for nodes that answer it with an
+    /// error, or with zero data counters where PortCounters has data
+    #[default]
+    Auto,
+    /// PortCountersExtended only (64-bit data counters)
+    Extended,
+    /// PortCounters only (32-bit data counters, which stop at their maximum)
+    Basic,
+}
+
+/// How `--once` prints the node table.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Aligned plain-text table
+    #[default]
+    Text,
+    /// JSON array, as written by the `e` export
+    Json,
+}
+
+/// Columns of the main node table, in display order.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum MainColumn {
+    Lid,
+    /// Node GUID; hidden by default
+    Guid,
+    Hca,
+    Node,
+    /// Switch or HCA
+    Type,
+    Pt,
+    RecvBw,
+    SendBw,
+    RecvTrend,
+    /// Recv/send utilization as a percentage of line rate
+    Util,
+    BwLoss,
+    ErrCnt,
+    ErrStr,
+    /// The counter picked in the metric popup
+    Metric,
+}
+
+impl MainColumn {
+    pub const ALL: [MainColumn; 14] = [
+        MainColumn::Lid,
+        MainColumn::Guid,
+        MainColumn::Hca,
+        MainColumn::Node,
+        MainColumn::Type,
+        MainColumn::Pt,
+        MainColumn::RecvBw,
+        MainColumn::SendBw,
+        MainColumn::RecvTrend,
+        MainColumn::Util,
+        MainColumn::BwLoss,
+        MainColumn::ErrCnt,
+        MainColumn::ErrStr,
+        MainColumn::Metric,
+    ];
+
+    /// The sort column that sorts by this column, if it is sortable.
+    pub fn sort_column(self) -> Option<SortColumn> {
+        match self {
+            MainColumn::Lid => Some(SortColumn::Lid),
+            MainColumn::Guid => Some(SortColumn::Guid),
+            MainColumn::Node => Some(SortColumn::Node),
+            MainColumn::Pt => Some(SortColumn::Pt),
+            MainColumn::RecvBw => Some(SortColumn::RecvBw),
+            MainColumn::SendBw => Some(SortColumn::SendBw),
+            MainColumn::BwLoss => Some(SortColumn::BwLoss),
+            MainColumn::ErrCnt => Some(SortColumn::ErrCnt),
+            MainColumn::ErrStr => Some(SortColumn::ErrStr),
+            MainColumn::Metric => Some(SortColumn::Metric),
+            MainColumn::Hca => Some(SortColumn::Hca),
+            MainColumn::Type => Some(SortColumn::Type),
+            MainColumn::RecvTrend | MainColumn::Util => None,
+        }
+    }
+}
+
+/// What the main table is sorted by, in the order `s` cycles through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortColumn {
+    /// Discovery order
+    #[default]
+    None,
+    Lid,
+    Node,
+    Pt,
+    RecvBw,
+    SendBw,
+    BwLoss,
+    ErrCnt,
+    ErrStr,
+    Metric,
+    /// Source HCA
+    Hca,
+    /// Node type, switches first
+    Type,
+    Guid,
+}
+
+impl SortColumn {
+    // New columns go last so the indexes in saved state files keep their meaning.
+    pub const ALL: [SortColumn; 13] = [
+        SortColumn::None,
+        SortColumn::Lid,
+        SortColumn::Node,
+        SortColumn::Pt,
+        SortColumn::RecvBw,
+        SortColumn::SendBw,
+        SortColumn::BwLoss,
+        SortColumn::ErrCnt,
+        SortColumn::ErrStr,
+        SortColumn::Metric,
+        SortColumn::Hca,
+        SortColumn::Type,
+        SortColumn::Guid,
+    ];
+
+    /// Position in `ALL`; this is what the state file stores.
+    pub fn index(self) -> i32 {
+        Self::ALL.iter().position(|&c| c == self).unwrap_or(0) as i32
+    }
+
+    /// Sort column at `index` in `ALL`, wrapping out-of-range values.
+    pub fn from_index(index: i32) -> Self {
+        Self::ALL[index.rem_euclid(Self::ALL.len() as i32) as usize]
+    }
+
+    /// The column after this one, wrapping back to `None`.
+    pub fn next(self) -> Self {
+        Self::from_index(self.index() + 1)
+    }
+
+    /// Direction the column is sorted in until the user flips it: identifiers read best
+    /// ascending, metrics descending so the busiest or most erroring nodes come first.
+    pub fn default_ascending(self) -> bool {
+        match self {
+            SortColumn::None
+            | SortColumn::Lid
+            | SortColumn::Node
+            | SortColumn::ErrStr
+            | SortColumn::Hca
+            | SortColumn::Type
+            | SortColumn::Guid => true,
+            SortColumn::Pt
+            | SortColumn::RecvBw
+            | SortColumn::SendBw
+            | SortColumn::BwLoss
+            | SortColumn::ErrCnt
+            | SortColumn::Metric => false,
+        }
+    }
+
+    /// Header name of the column.
+    pub fn name(self) -> &'static str {
+        match self {
+            SortColumn::None => "None",
+            SortColumn::Lid => "LID",
+            SortColumn::Node => "NODE",
+            SortColumn::Pt => "PT",
+            SortColumn::RecvBw => "RECV_BW",
+            SortColumn::SendBw => "SEND_BW",
+            SortColumn::BwLoss => "BW_LOSS",
+            SortColumn::ErrCnt => "ERR_CNT",
+            SortColumn::ErrStr => "ERR_STR",
+            SortColumn::Metric => "METRIC",
+            SortColumn::Hca => "HCA",
+            SortColumn::Type => "TYPE",
+            SortColumn::Guid => "GUID",
+        }
+    }
+
+    /// Compare two main table rows by this column.
+    fn compare(self, a: &MainNodeInfo, b: &MainNodeInfo) -> Ordering {
+        match self {
+            SortColumn::None => Ordering::Equal,
+            SortColumn::Lid => a.lid.cmp(&b.lid),
+            SortColumn::Node => a.name.cmp(&b.name),
+            SortColumn::Pt => a.ports.cmp(&b.ports),
+            SortColumn::RecvBw => a.recv_bw.partial_cmp(&b.recv_bw).unwrap_or(Ordering::Equal),
+            SortColumn::SendBw => a.send_bw.partial_cmp(&b.send_bw).unwrap_or(Ordering::Equal),
+            SortColumn::BwLoss => a.bw_loss.partial_cmp(&b.bw_loss).unwrap_or(Ordering::Equal),
+            SortColumn::ErrCnt => a.error_count.cmp(&b.error_count),
+            SortColumn::ErrStr => a.error_strings.cmp(&b.error_strings),
+            SortColumn::Metric => a.metric.partial_cmp(&b.metric).unwrap_or(Ordering::Equal),
+            SortColumn::Hca => a.hca.cmp(&b.hca),
+            SortColumn::Type => a.node_type.cmp(&b.node_type),
+            SortColumn::Guid => a.guid.cmp(&b.guid),
+        }
+    }
 }
 
 /// Represents the currently active popup dialog.
@@ -55,19 +445,204 @@ pub enum Popup {
     Search,
     /// Node details popup is active
     Details,
+    /// Bandwidth alerts popup is active
+    Alerts,
+    /// Column visibility popup is active
+    Columns,
+    /// Discovery warnings popup is active
+    Warnings,
+    /// Metric counter picker is active
+    Metric,
+    /// Raw counter inspector for a port of the details popup is active
+    Counters,
+    /// Log of recent warnings and errors is active
+    Log,
+    /// Confirmation prompt for clearing counters is active
+    ConfirmReset,
+}
+
+/// A node's bandwidth crossing `--bw-alert-gbps`.
+#[derive(Debug, Clone)]
+pub struct BwAlert {
+    pub timestamp: DateTime<Utc>,
+    pub lid: u16,
+    pub node_description: String,
+    /// The metric that tripped, e.g. `RECV_BW`
+    pub metric: &'static str,
+    pub gbps: f64,
 }
 
-#[derive(Debug, Default, serde::Deserialize, PartialEq, Clone)]
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize, PartialEq, Clone)]
 pub struct AppConfig {
     pub hca: String,
-    pub pkey: u32,
+    pub pkey: u16,
     pub threads: usize,
     pub service_type: String,
     pub update_interval: usize,
     pub include_hcas: bool,
+    pub skip_down_ports: bool,
+    pub counters_query: CountersQuery,
+    /// Up/Down in the details popup wrap around at the first and last port
+    pub wrap_around: bool,
     pub timeout: u32,
     pub retries: u32,
+    pub retry_backoff_ms: u64,
     pub scope_file: Option<String>,
+    pub name_map: Option<String>,
+    pub export_path: Option<String>,
+    pub line_rate_gbps: Option<f64>,
+    pub no_persist: bool,
+    pub error_threshold: Option<u64>,
+    pub bw_alert_gbps: Option<f64>,
+    pub history_len: usize,
+    pub record: Option<String>,
+    pub replay_file: Option<String>,
+    pub replay_fast: bool,
+    pub stream_discovery: bool,
+    pub theme: Option<String>,
+    pub units: RateUnits,
+    pub columns: Option<Vec<MainColumn>>,
+    pub error_counters: Option<Vec<String>>,
+    pub error_string_counters: Option<Vec<String>>,
+    pub extra_counters: Option<Vec<String>>,
+    pub once: bool,
+    pub format: OutputFormat,
+    pub prometheus_port: Option<u16>,
+    pub json_stream: Option<String>,
+}
+
+impl AppConfig {
+    /// Layer the settings from lowest to highest precedence: built-in defaults, the config
+    /// file (`--config`, else `$XDG_CONFIG_HOME/ibtop/config.toml` if present), `IBTOP_*`
+    /// environment variables, and options given on the command line.
+    pub fn load(args: Args, matches: &ArgMatches) -> color_eyre::Result<Self> {
+        let file = match &args.config {
+            Some(path) => Some(config::File::with_name(path).required(true)),
+            None => {
+                config_dir().map(|dir| config::File::from(dir.join("config.toml")).required(false))
+            }
+        };
+
+        let cli = AppConfig {
+            hca: args.hca.unwrap_or_default(),
+            timeout: args.timeout,
+            retries: args.retries,
+            retry_backoff_ms: args.retry_backoff_ms,
+            threads: args.threads,
+            pkey: args.pkey,
+            update_interval: args.update_interval,
+            include_hcas: args.include_hcas,
+            skip_down_ports: args.skip_down_ports,
+            counters_query: args.counters_query,
+            wrap_around: args.wrap_around,
+            service_type: args.service_type,
+            scope_file: args.scope_file,
+            name_map: args.name_map,
+            export_path: args.export_path,
+            line_rate_gbps: args.line_rate_gbps,
+            no_persist: args.no_persist,
+            error_threshold: args.error_threshold,
+            bw_alert_gbps: args.bw_alert_gbps,
+            history_len: args.history_len,
+            record: args.record,
+            replay_file: args.replay_file,
+            replay_fast: args.replay_fast,
+            stream_discovery: args.stream_discovery,
+            theme: args.theme,
+            units: args.units,
+            columns: args.columns,
+            error_counters: args.error_counters,
+            error_string_counters: args.error_string_counters,
+            extra_counters: args.extra_counters,
+            once: args.once,
+            format: args.format,
+            prometheus_port: args.prometheus_port,
+            json_stream: args.json_stream,
+        };
+
+        // Only options actually typed on the command line override the file and environment;
+        // clap's defaults sit below them. Argument ids are the field names.
+        let serde_json::Value::Object(mut explicit) = serde_json::to_value(&cli)? else {
+            unreachable!("AppConfig serializes to a map");
+        };
+        explicit.retain(|key, _| matches.value_source(key) == Some(ValueSource::CommandLine));
+
+        let mut builder = Config::builder().add_source(Config::try_from(&cli)?);
+        if let Some(file) = file {
+            builder = builder.add_source(file);
+        }
+        let config: AppConfig = builder
+            .add_source(
+                config::Environment::with_prefix("IBTOP")
+                    .try_parsing(true)
+                    .list_separator(",")
+                    .with_list_parse_key("columns")
+                    .with_list_parse_key("error_counters")
+                    .with_list_parse_key("error_string_counters")
+                    .with_list_parse_key("extra_counters"),
+            )
+            .add_source(config::File::from_str(
+                &serde_json::Value::Object(explicit).to_string(),
+                config::FileFormat::Json,
+            ))
+            .build()
+            .and_then(Config::try_deserialize)
+            .wrap_err("invalid configuration")?;
+
+        if config.hca.is_empty() {
+            bail!("no HCA given (use --hca, IBTOP_HCA or `hca` in the config file)");
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reject values that would stall or flood the fabric. Checked after layering since the
+    /// config file and environment bypass the argument parser.
+    fn validate(&self) -> color_eyre::Result<()> {
+        let checks = [
+            ("threads", self.threads as u64, 1, 1024),
+            ("update_interval", self.update_interval as u64, 1, 3600),
+            ("timeout", self.timeout as u64, 1, 60_000),
+            ("retries", self.retries as u64, 0, 20),
+        ];
+        for (name, value, min, max) in checks {
+            if !(min..=max).contains(&value) {
+                bail!("{name} must be between {min} and {max}, got {value}");
+            }
+        }
+        for name in self.extra_counters.iter().flatten() {
+            if !METRIC_COUNTERS.contains(&name.as_str()) {
+                bail!(
+                    "unknown counter '{name}' in extra_counters, expected one of: {}",
+                    METRIC_COUNTERS.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Delay before the `retry`-th (1-based) retry of a counters query.
+    pub fn retry_backoff(&self, retry: u32) -> Duration {
+        let factor = 1u64 << retry.saturating_sub(1).min(16);
+        Duration::from_millis(self.retry_backoff_ms.saturating_mul(factor))
+    }
+
+    /// The HCAs listed in `hca`, which accepts a comma separated list (e.g. `mlx5_0,mlx5_1`).
+    pub fn hcas(&self) -> Vec<String> {
+        let hcas: Vec<String> = self
+            .hca
+            .split(',')
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .map(String::from)
+            .collect();
+
+        if hcas.is_empty() {
+            vec![self.hca.clone()]
+        } else {
+            hcas
+        }
+    }
 }
 
 // Main application state.
@@ -75,35 +650,102 @@ pub struct App {
     pub running: bool,
     pub config: AppConfig,
     pub nodes: Vec<Node>,
+    /// MAD statistics from the last successful discovery
+    pub discovery_stats: Option<DiscoveryStats>,
+    /// When the in-flight discovery request was sent
+    pub discovery_start_time: Option<DateTime<Utc>>,
+    /// Id of the last discovery request sent; the event handler drops responses to older ones
+    pub discovery_request_id: RequestId,
+    /// `nodes` holds partial results of the in-flight discovery
+    pub discovery_streaming: bool,
+    /// Latest MADs sent and nodes found by the in-flight discovery
+    pub discovery_progress: Option<(u64, usize)>,
+    /// Problems reported by the last discovery, listed in the warnings popup
+    pub discovery_warnings: Vec<String>,
 
     /// Selected Node
-    pub selected_node: Option<(u64, u16, String, u16, f64, f64, f64, u128, String)>,
+    pub selected_node: Option<MainNodeInfo>,
 
     /// Counters
-    pub display_counters: HashMap<(u16, i32), HashMap<String, u64>>,
-    pub current_counters: HashMap<(u16, i32), HashMap<String, u64>>,
-    pub previous_counters: HashMap<(u16, i32), HashMap<String, u64>>,
-    pub baseline_counters: HashMap<(u16, i32), HashMap<String, u64>>,
+    pub display_counters: CountersMap,
+    pub current_counters: CountersMap,
+    pub previous_counters: CountersMap,
+    pub baseline_counters: CountersMap,
 
     pub pending_counter_update: bool,
+    /// Id of the last counters request sent; the event handler drops responses to older ones
+    pub counters_request_id: RequestId,
+    /// An update was asked for while one was in flight; it runs once that one finishes
+    pub queued_counter_update: bool,
+    /// The in-flight update only covers the rows on screen (see `visible_only_updates`)
+    pub pending_partial_update: bool,
+    /// Auto-update refreshes only the rows on screen; `u` still refreshes the whole fabric
+    pub visible_only_updates: bool,
+    /// When each LID/port's counters last arrived
+    pub counter_times: HashMap<CounterKey, DateTime<Utc>>,
+    /// A `g` was pressed and the next key may complete `gg`
+    pub pending_g: bool,
     pub update_start_time: Option<DateTime<Utc>>,
     pub last_counter_update: Option<DateTime<Utc>>,
+    /// When the response before `last_counter_update` arrived (pairs with `previous_counters`)
+    pub previous_counter_update: Option<DateTime<Utc>>,
+    /// Measured wall-clock time between the last two counter responses
+    pub sample_period: Option<chrono::Duration>,
     pub counter_mode: CounterMode,
 
+    /// While paused, `display_counters` is frozen and new results wait in `paused_counters`
+    pub paused: bool,
+    pub paused_counters: Option<CountersMap>,
+
     pub status: String,
+    /// Last status added to the log popup's history
+    pub logged_status: String,
+    /// Frame counter driving animations; wraps around
     pub tick: usize,
     pub auto_update: bool,
+    /// Seconds between automatic counter updates
     pub auto_update_interval: usize,
-    pub auto_update_counter: usize,
+    /// When the last automatic update was due; the next one is `auto_update_interval` later
+    pub last_auto_update: Instant,
 
-    pub sort_column: i32,
+    pub sort_column: SortColumn,
     pub sort_ascending: bool,
+    /// Last direction used for each sort column, indexed by `SortColumn::index`
+    pub sort_directions: [bool; SortColumn::ALL.len()],
+
+    /// Tiebreaker sort column (`None` = none) and its direction
+    pub secondary_sort_column: SortColumn,
+    pub secondary_sort_ascending: bool,
+
+    /// Rows with more errors than `error_threshold` are highlighted while enabled
+    pub error_highlight: bool,
+    pub error_threshold: u128,
+
+    /// Counters summed into ERR_CNT and listed in ERR_STR
+    pub error_counters: Vec<String>,
+    pub error_string_counters: Vec<String>,
+
+    /// Bandwidth alerts raised since they were last cleared, newest last
+    pub bw_alerts: VecDeque<BwAlert>,
+    pub bw_alert_count: usize,
+    /// Node GUID and metric pairs currently above the alert threshold (their rows flash)
+    pub bw_alert_active: HashSet<(u64, &'static str)>,
+
+    /// Recent bandwidth samples per node GUID (oldest first), for the trend column and chart
+    pub bw_history: HashMap<u64, VecDeque<BwSample>>,
+
+    /// Show the bandwidth chart in the details popup
+    pub show_details_chart: bool,
+    /// Ports queried and listed by the details popup
+    pub details_ports: DetailsPorts,
+    /// What the details popup's port table is sorted by (the total row always stays last)
+    pub popup_sort_column: DetailsSortColumn,
+    pub popup_sort_ascending: bool,
 
     /// Search field for filtering results
     pub search_form: SearchForm,
-
-    /// NodeDetails form
-    pub node_details_form: NodeDetailsForm,
+    /// Only list nodes of this type (`T` cycles switches, HCAs and both)
+    pub node_type_filter: Option<NodeType>,
 
     /// Current scroll offset for the nodes table
     pub table_offset: usize,
@@ -114,87 +756,352 @@ pub struct App {
     /// Track the selected port
     pub popup_selected: usize,
 
-    /// Number of visible rows in the table (set during rendering)
+    /// LID and port shown by the raw counter inspector
+    pub inspect_port: CounterKey,
+    /// Raw counter inspector offset
+    pub inspect_offset: usize,
+
+    /// LID/port whose counters the reset prompt asks to clear
+    pub reset_target: Option<LidPort>,
+    /// The reset prompt was opened from the details popup, which it returns to
+    pub reset_from_details: bool,
+
+    /// Number of visible rows in the main table (set during rendering)
     pub visible_rows: Cell<usize>,
+    /// Number of visible rows in the details or counters popup table (set during rendering).
+    /// Kept apart from `visible_rows` so scrolling the main table behind a popup still uses
+    /// its own height.
+    pub popup_visible_rows: Cell<usize>,
+
+    /// Screen area of the main table, header row included (set during rendering)
+    pub table_area: Cell<Rect>,
+
+    /// Screen area of each shown main table column (set during rendering)
+    pub column_areas: RefCell<Vec<Rect>>,
 
     /// Currently selected table row
     pub selected: usize,
 
+    /// Main table columns that are shown
+    pub columns: Vec<MainColumn>,
+
+    /// Highlighted entry of the columns popup (index into `MainColumn::ALL`)
+    pub columns_selected: usize,
+
+    /// Counter shown in the METRIC column, one of `METRIC_COUNTERS`
+    pub metric_counter: String,
+    /// Highlighted entry of the metric popup
+    pub metric_selected: usize,
+
     /// Active popup
     pub active_popup: Popup,
 
     /// Manages all event handling (tick, crossterm, discovery, counters).
     pub events: EventHandler,
+
+    /// Writes responses to the `--record` file
+    pub recorder: Option<Recorder>,
+    /// Writer for `--json-stream`
+    pub json_stream: Option<CounterStream>,
+
+    /// UI colors
+    pub theme: Theme,
+
+    /// Friendly node names from `--name-map`, by GUID
+    pub name_map: HashMap<u64, String>,
+
+    /// Text served on `--prometheus-port`, and the values it was rendered from by node GUID
+    pub prometheus: Option<MetricsText>,
+    pub prometheus_nodes: BTreeMap<u64, NodeMetrics>,
+
+    /// Bumped whenever `nodes` or `display_counters` change, invalidating the cached rows
+    pub rows_revision: u64,
+
+    /// Main table rows from the last `visible_nodes` call and the inputs they were built from
+    visible_nodes_cache: RefCell<Option<(VisibleNodesKey, Rc<Vec<MainNodeInfo>>)>>,
+}
+
+/// Inputs the cached main table rows were built from.
+#[derive(Debug, Clone, PartialEq)]
+struct VisibleNodesKey {
+    rows_revision: u64,
+    filter: String,
+    filter_field: SearchField,
+    fuzzy_query: Option<String>,
+    node_type_filter: Option<NodeType>,
+    counter_mode: CounterMode,
+    sort: (SortColumn, bool),
+    secondary_sort: (SortColumn, bool),
+    metric_counter: String,
+}
+
+/// Port, state, link status, link, remote description, recv/send BW, BW loss, whether
+/// xmit_waits wrapped, error count, error string and whether the counter query failed for a
+/// row of the details popup table.
+/// A row of the details popup table: one port of the selected node, or its total.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetailsPortInfo {
+    /// Port number, `AGG_COUNTERS_PORT` for the node total
+    pub port: i32,
+    pub link_state: String,
+    pub link_status: LinkStatus,
+    /// Link width and speed, e.g. `4x HDR`
+    pub link: String,
+    pub remote_description: String,
+    pub recv_bw: f64,
+    pub send_bw: f64,
+    /// Bandwidth lost to xmit waits
+    pub bw_loss: f64,
+    /// `xmit_waits` wrapped around since the previous sample
+    pub xmit_waits_wrapped: bool,
+    pub error_count: u128,
+    pub error_strings: String,
+    pub query_failed: bool,
 }
 
-pub type MainNodeInfo = (u64, u16, String, u16, f64, f64, f64, u128, String);
+/// When a sample was taken and the node's aggregate recv/send bandwidth at that time.
+pub type BwSample = (DateTime<Utc>, f64, f64);
+
+/// A row of the main node table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MainNodeInfo {
+    pub guid: u64,
+    pub lid: u16,
+    /// Name from `--name-map`, or the node description
+    pub name: String,
+    /// Ports with a link; down ports are only listed in the details popup
+    pub ports: u16,
+    pub recv_bw: f64,
+    pub send_bw: f64,
+    /// Bandwidth lost to xmit waits
+    pub bw_loss: f64,
+    pub error_count: u128,
+    pub error_strings: String,
+    pub hca: String,
+    /// Line rate of the node's links in Gbps, if known
+    pub line_rate: Option<f64>,
+    /// Value of the METRIC column's counter
+    pub metric: f64,
+    pub node_type: NodeType,
+}
 
 impl App {
     ///  Constructor
-    pub fn new(args: Args) -> Self {
-        let app_config: AppConfig = Config::builder()
-            .add_source(
-                config::Environment::with_prefix("IBTOP")
-                    .try_parsing(true)
-                    .separator("_")
-                    .list_separator(" "),
-            )
-            .build()
-            .and_then(|c| c.try_deserialize())
-            .unwrap_or_else(|_| AppConfig {
-                hca: args.hca,
-                timeout: args.timeout,
-                retries: args.retries,
-                threads: args.threads,
-                pkey: args.pkey,
-                update_interval: args.update_interval,
-                include_hcas: args.include_hcas,
-                service_type: args.service_type,
-                scope_file: args.scope_file,
-            });
-
+    pub fn new(app_config: AppConfig) -> Self {
         let mut app = App {
             config: app_config.clone(),
             running: true,
             status: "".into(),
+            logged_status: String::new(),
             search_form: SearchForm::new("Search"),
-            node_details_form: NodeDetailsForm::new("Details"),
+            node_type_filter: None,
             nodes: Vec::new(),
+            discovery_stats: None,
+            discovery_start_time: None,
+            discovery_request_id: 0,
+            discovery_streaming: false,
+            discovery_progress: None,
+            discovery_warnings: Vec::new(),
             selected_node: None,
             display_counters: HashMap::new(),
             current_counters: HashMap::new(),
             previous_counters: HashMap::new(),
             baseline_counters: HashMap::new(),
             pending_counter_update: false,
+            counters_request_id: 0,
+            queued_counter_update: false,
+            pending_partial_update: false,
+            visible_only_updates: false,
+            counter_times: HashMap::new(),
+            pending_g: false,
             update_start_time: None,
             counter_mode: CounterMode::Whole,
             last_counter_update: None,
+            previous_counter_update: None,
+            sample_period: None,
+            paused: false,
+            paused_counters: None,
 
             tick: 0,
             auto_update: false,
             auto_update_interval: app_config.update_interval,
-            auto_update_counter: 0,
-            sort_column: 0,
+            last_auto_update: Instant::now(),
+            sort_column: SortColumn::None,
             sort_ascending: false,
+            sort_directions: SortColumn::ALL.map(SortColumn::default_ascending),
+            secondary_sort_column: SortColumn::None,
+            secondary_sort_ascending: false,
+            error_highlight: app_config.error_threshold.is_some(),
+            error_threshold: app_config.error_threshold.unwrap_or(0) as u128,
+            error_counters: resolve_error_counters(&app_config.error_counters),
+            error_string_counters: resolve_error_counters(&app_config.error_string_counters),
+            bw_alerts: VecDeque::new(),
+            bw_alert_count: 0,
+            bw_alert_active: HashSet::new(),
+            bw_history: HashMap::new(),
+            show_details_chart: false,
+            details_ports: DetailsPorts::default(),
+            popup_sort_column: DetailsSortColumn::default(),
+            popup_sort_ascending: true,
             table_offset: 0,
             popup_table_offset: 0,
             popup_selected: 0,
+            inspect_port: (String::new(), 0, 0),
+            inspect_offset: 0,
+            reset_target: None,
+            reset_from_details: false,
             visible_rows: Cell::new(0),
+            popup_visible_rows: Cell::new(0),
+            table_area: Cell::new(Rect::default()),
+            column_areas: RefCell::new(Vec::new()),
             selected: 0,
+            columns: app_config.columns.clone().unwrap_or_else(|| {
+                // The HCA column is only useful when monitoring more than one HCA.
+                let multi_hca = app_config.hcas().len() > 1;
+                // Without `--include-hcas` every node is a switch.
+                let mixed_types = app_config.include_hcas;
+                // The metric column is shown once a metric is picked, and GUIDs on request.
+                MainColumn::ALL
+                    .into_iter()
+                    .filter(|&c| c != MainColumn::Guid)
+                    .filter(|&c| c != MainColumn::Hca || multi_hca)
+                    .filter(|&c| c != MainColumn::Type || mixed_types)
+                    .filter(|&c| c != MainColumn::Metric)
+                    .collect()
+            }),
+            columns_selected: 0,
+            metric_counter: DEFAULT_METRIC_COUNTER.to_string(),
+            metric_selected: 0,
+            rows_revision: 0,
+            visible_nodes_cache: RefCell::new(None),
             active_popup: Popup::None,
+            theme: Theme::load(app_config.theme.as_deref()),
+            name_map: HashMap::new(),
+            prometheus: None,
+            prometheus_nodes: BTreeMap::new(),
             events: EventHandler::new(app_config),
+            recorder: None,
+            json_stream: None,
         };
-        if app.config.scope_file.is_some() {
-            let scope_file = app.config.scope_file.as_ref().unwrap();
-            app.status = format!("Using scope file");
-            let nodes = read_scope_file(&scope_file);
-            app.nodes.extend(nodes);
-        } else {
-            app.discover_fabric();
+        if let Some(path) = &app.config.record {
+            match Recorder::open(path) {
+                Ok(recorder) => app.recorder = Some(recorder),
+                Err(e) => tracing::error!("Cannot open record file '{path}': {e}"),
+            }
+        }
+        if let Some(path) = &app.config.json_stream {
+            match CounterStream::open(path) {
+                Ok(stream) => app.json_stream = Some(stream),
+                Err(e) => tracing::error!("Cannot open JSON stream '{path}': {e}"),
+            }
+        }
+        // A headless snapshot shouldn't be filtered by whatever was last typed in the TUI
+        if !app.config.no_persist
+            && !app.config.once
+            && let Some(state) = load_state()
+        {
+            app.apply_state(state);
+        }
+        app.load_scope_baseline();
+        if let Some(port) = app.config.prometheus_port {
+            match serve(port) {
+                Ok(text) => {
+                    app.prometheus = Some(text);
+                    // The endpoint is only as fresh as the last update
+                    app.auto_update = true;
+                }
+                Err(e) => tracing::error!("Cannot serve metrics on port {port}: {e}"),
+            }
         }
+        app.discover_fabric();
         app
     }
 
+    /// Seed the baseline from a JSON `--scope-file` that carries one and start in Baseline
+    /// mode, so deltas from the snapshot show from the first update.
+    fn load_scope_baseline(&mut self) {
+        let Some(path) = &self.config.scope_file else {
+            return;
+        };
+        if !is_json_scope_file(path) {
+            return;
+        }
+
+        match read_scope_snapshot(path) {
+            Ok(snapshot) if !snapshot.baseline.is_empty() => {
+                // Entries that don't name an HCA apply to the LID on every HCA
+                let hcas = self.config.hcas();
+                self.baseline_counters = snapshot
+                    .baseline
+                    .into_iter()
+                    .flat_map(|c| {
+                        let entry_hcas = if c.hca.is_empty() {
+                            hcas.clone()
+                        } else {
+                            vec![c.hca]
+                        };
+                        entry_hcas
+                            .into_iter()
+                            .map(move |hca| ((hca, c.lid, c.port), c.counters.clone()))
+                    })
+                    .collect();
+                self.counter_mode = CounterMode::Baseline;
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Cannot read baseline from scope file '{path}': {e}"),
+        }
+    }
+
+    /// Restore view settings saved by a previous session.
+    fn apply_state(&mut self, state: PersistedState) {
+        self.sort_column = SortColumn::from_index(state.sort_column);
+        self.sort_ascending = state.sort_ascending;
+        self.sort_directions[self.sort_column.index() as usize] = self.sort_ascending;
+        self.secondary_sort_column = SortColumn::from_index(state.secondary_sort_column);
+        self.secondary_sort_ascending = state.secondary_sort_ascending;
+        // The baseline itself isn't saved, so don't come back up in Baseline mode.
+        self.counter_mode = match state.counter_mode {
+            CounterMode::Baseline => CounterMode::Whole,
+            mode => mode,
+        };
+        self.auto_update = state.auto_update;
+        if state.auto_update_interval > 0 {
+            self.auto_update_interval = state.auto_update_interval;
+        }
+        self.search_form.value = state.search;
+        self.search_form.mode = state.search_mode;
+        self.search_form.update_filter();
+        self.node_type_filter = state.node_type_filter;
+        // Columns given on the command line win over saved ones.
+        if self.config.columns.is_none()
+            && let Some(columns) = state.columns
+            && !columns.is_empty()
+        {
+            self.columns = columns;
+        }
+        if METRIC_COUNTERS.contains(&state.metric_counter.as_str()) {
+            self.metric_counter = state.metric_counter;
+        }
+    }
+
+    /// Snapshot the view settings worth restoring next session.
+    fn persisted_state(&self) -> PersistedState {
+        PersistedState {
+            sort_column: self.sort_column.index(),
+            sort_ascending: self.sort_ascending,
+            secondary_sort_column: self.secondary_sort_column.index(),
+            secondary_sort_ascending: self.secondary_sort_ascending,
+            counter_mode: self.counter_mode,
+            auto_update: self.auto_update,
+            auto_update_interval: self.auto_update_interval,
+            search: self.search_form.value.clone(),
+            search_mode: self.search_form.mode,
+            node_type_filter: self.node_type_filter,
+            columns: Some(self.columns.clone()),
+            metric_counter: self.metric_counter.clone(),
+        }
+    }
+
     /// Run the application, drawing the UI and handling events until it is no longer `running`.
     pub fn run(mut self, mut terminal: DefaultTerminal) -> color_eyre::Result<()> {
         // Initial draw so the UI appears immediately.
@@ -206,14 +1113,83 @@ impl App {
             if !self.running {
                 break;
             }
+            self.follow_selected_node();
+            self.log_status();
             self.draw(&mut terminal)?;
         }
+        self.events.shutdown(SHUTDOWN_TIMEOUT);
         Ok(())
     }
 
+    /// Headless `--once` mode: wait for discovery and a single counters update, print the
+    /// node table to stdout and exit. Exits with 2 when a node's error count exceeds
+    /// `--error-threshold`.
+    pub fn run_once(mut self) -> color_eyre::Result<ExitCode> {
+        while self.discovery_start_time.is_some() {
+            self.handle_events()?;
+        }
+        if self.discovery_stats.is_none() {
+            bail!("{}", self.status);
+        }
+
+        if !self.nodes.is_empty() {
+            self.update_counters();
+            while self.pending_counter_update {
+                self.handle_events()?;
+            }
+            if self.last_counter_update.is_none() {
+                bail!("{}", self.status);
+            }
+        }
+
+        self.events.shutdown(SHUTDOWN_TIMEOUT);
+
+        let rows = self.visible_nodes();
+        let mut stdout = io::stdout().lock();
+        let written = match self.config.format {
+            OutputFormat::Text => write_nodes_text(&mut stdout, &rows, |gbps| {
+                format_bw(gbps, self.config.units, &self.counter_mode)
+            }),
+            OutputFormat::Json => write_nodes_json(&mut stdout, &rows),
+        };
+        // A reader that stops early (e.g. `head`) is not an error
+        if let Err(e) = written
+            && e.kind() != io::ErrorKind::BrokenPipe
+        {
+            return Err(e.into());
+        }
+
+        let exceeded = self.config.error_threshold.is_some()
+            && rows
+                .iter()
+                .any(|row| row.error_count > self.error_threshold);
+        Ok(if exceeded {
+            ExitCode::from(2)
+        } else {
+            ExitCode::SUCCESS
+        })
+    }
+
+    /// Add the status line to the log popup's history whenever it changes.
+    fn log_status(&mut self) {
+        if self.status.is_empty() || self.status == self.logged_status {
+            return;
+        }
+        push_log_line("INFO", &self.status);
+        self.logged_status.clone_from(&self.status);
+    }
+
     fn draw(&self, terminal: &mut DefaultTerminal) -> color_eyre::Result<()> {
         match self.active_popup {
-            Popup::None | Popup::Details => {
+            Popup::None
+            | Popup::Details
+            | Popup::Alerts
+            | Popup::Columns
+            | Popup::Warnings
+            | Popup::Metric
+            | Popup::Counters
+            | Popup::Log
+            | Popup::ConfirmReset => {
                 let _ = terminal.hide_cursor();
             }
             Popup::Search => {
@@ -248,29 +1224,52 @@ impl App {
 
     /// Handle inbound events from the [`EventHandler`] channels.
     fn handle_events(&mut self) -> color_eyre::Result<()> {
-        match self.events.next()? {
+        match self.events.next_event()? {
             Event::Tick => self.on_tick(),
-            Event::Crossterm(event) => {
-                if let crossterm::event::Event::Key(key_event) = event {
-                    self.handle_key_event(key_event)?;
-                }
-            }
+            Event::Crossterm(event) => match event {
+                crossterm::event::Event::Key(key_event) => self.handle_key_event(key_event)?,
+                crossterm::event::Event::Mouse(mouse_event) => self.handle_mouse_event(mouse_event),
+                _ => {}
+            },
             Event::App(app_event) => {
                 if let AppEvent::Quit = app_event {
                     self.quit();
                 }
             }
             Event::Discover(discovery_event) => match discovery_event {
-                DiscoveryEvent::Response(nodes) => {
-                    self.status = format!("Discovery complete: {} nodes found", nodes.len());
-                    self.nodes = nodes;
-                    if !self.nodes.is_empty() {
-                        self.selected = 0;
-                        self.set_selected_node_guid();
+                DiscoveryEvent::Response(_, nodes, stats) => {
+                    self.handle_discovery_response(nodes, stats);
+                }
+                DiscoveryEvent::Partial(nodes) => {
+                    // The first partial result replaces the previous sweep's nodes
+                    if !self.discovery_streaming {
+                        self.nodes.clear();
+                        self.discovery_streaming = true;
                     }
+                    self.nodes.extend(nodes);
+                    self.rows_revision += 1;
+                    self.status = format!("Discovering... {} nodes so far", self.nodes.len());
+                }
+                DiscoveryEvent::Progress {
+                    mads_sent,
+                    nodes_found,
+                    ..
+                } => {
+                    self.discovery_progress = Some((mads_sent, nodes_found));
                 }
-                DiscoveryEvent::Error => {
-                    self.status = "Discovery failed".into();
+                DiscoveryEvent::Warnings(_, warnings) => {
+                    self.discovery_warnings = warnings;
+                }
+                DiscoveryEvent::Error(_, msg) => {
+                    self.discovery_streaming = false;
+                    self.discovery_progress = None;
+                    self.discovery_start_time = None;
+                    self.status = format!("Discovery failed: {msg} (l = log)");
+                    self.nodes.clear();
+                    self.rows_revision += 1;
+                    self.selected = 0;
+                    self.table_offset = 0;
+                    self.selected_node = None;
                 }
                 DiscoveryEvent::Exit => {
                     // Discovery service is shutting down
@@ -280,13 +1279,31 @@ impl App {
                 }
             },
             Event::Counters(counter_event) => match counter_event {
-                CounterEvent::Response(counters) => {
+                CounterEvent::Response(_, counters) => {
+                    if let Some(recorder) = &mut self.recorder
+                        && let Err(e) = recorder.record_counters(&counters)
+                    {
+                        tracing::warn!("Failed to record counters response: {e}");
+                    }
+                    self.stream_counters(&counters);
                     self.handle_counters_update(counters);
-                    self.last_counter_update = Some(Utc::now());
+                    self.run_queued_counter_update();
                 }
-                CounterEvent::Error => {
+                CounterEvent::Error(_) => {
                     self.status = "Counter update failed".into();
                     self.pending_counter_update = false;
+                    self.run_queued_counter_update();
+                }
+                CounterEvent::Timeout(allowed) => {
+                    self.status = format!(
+                        "Counter update timed out after {}s, is the fabric responding?",
+                        allowed.as_secs()
+                    );
+                    self.pending_counter_update = false;
+                    self.run_queued_counter_update();
+                }
+                CounterEvent::ResetResponse(results) => {
+                    self.handle_reset_response(results);
                 }
                 CounterEvent::Exit => {
                     // Counter service is shutting down
@@ -315,10 +1332,6 @@ impl App {
                             ..
                         } => {
                             self.active_popup = Popup::None;
-                            if !self.nodes.is_empty() {
-                                self.selected = 0;
-                                self.set_selected_node_guid();
-                            }
                         }
 
                         // Other key presses go to the search field
@@ -337,39 +1350,60 @@ impl App {
                             self.active_popup = Popup::None;
                         }
 
-                        // Move selection down
+                        // Move selection down, wrapping to the top with `--wrap-around`
                         KeyEvent {
-                            code: KeyCode::Down,
+                            code: KeyCode::Down | KeyCode::Char('j'),
                             ..
                         } => {
-                            if !self.display_counters.is_empty() {
-                                let max_idx = self.display_counters.len().saturating_sub(1);
-                                self.popup_selected = (self.popup_selected + 1).min(max_idx);
-
-                                let vis = self.visible_rows.get().max(1);
-                                let max_offset = self.display_counters.len().saturating_sub(vis);
+                            let len = self.details_rows().len();
+                            if self.config.wrap_around && self.popup_selected + 1 >= len {
+                                self.select_popup_row(0);
+                            } else {
+                                self.select_popup_row(self.popup_selected + 1);
+                            }
+                        }
 
-                                if self.popup_selected >= self.popup_table_offset + vis {
-                                    self.popup_table_offset =
-                                        (self.popup_table_offset + 1).min(max_offset);
-                                }
+                        // Move selection up, wrapping to the bottom with `--wrap-around`
+                        KeyEvent {
+                            code: KeyCode::Up | KeyCode::Char('k'),
+                            ..
+                        } => {
+                            if self.config.wrap_around && self.popup_selected == 0 {
+                                self.select_popup_row(usize::MAX);
+                            } else {
+                                self.select_popup_row(self.popup_selected.saturating_sub(1));
                             }
                         }
 
-                        // Move selection up
+                        // Move selection by a page
                         KeyEvent {
-                            code: KeyCode::Up, ..
+                            code: KeyCode::PageDown,
+                            ..
                         } => {
-                            if !self.display_counters.is_empty() {
-                                if self.popup_selected > 0 {
-                                    self.popup_selected -= 1;
-                                }
+                            let vis = self.popup_visible_rows.get().max(1);
+                            self.select_popup_row(self.popup_selected + vis);
+                        }
 
-                                if self.popup_selected < self.popup_table_offset {
-                                    self.popup_table_offset =
-                                        self.popup_table_offset.saturating_sub(1);
-                                }
-                            }
+                        KeyEvent {
+                            code: KeyCode::PageUp,
+                            ..
+                        } => {
+                            let vis = self.popup_visible_rows.get().max(1);
+                            self.select_popup_row(self.popup_selected.saturating_sub(vis));
+                        }
+
+                        // Jump to the first or last row
+                        KeyEvent {
+                            code: KeyCode::Home,
+                            ..
+                        } => {
+                            self.select_popup_row(0);
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::End, ..
+                        } => {
+                            self.select_popup_row(usize::MAX);
                         }
 
                         // Update counters
@@ -384,53 +1418,342 @@ impl App {
                                 self.update_counters();
                             }
                         }
-                        _ => {}
-                    }
-                }
-            }
-            return Ok(());
-        }
 
-        match key_event {
-            // Quit keys: ESC, 'q', or Ctrl-C
-            KeyEvent {
-                code: KeyCode::Esc,
-                modifiers: KeyModifiers::NONE,
-                kind: _,
-                state: _,
-            }
-            | KeyEvent {
-                code: KeyCode::Char('q'),
-                modifiers: KeyModifiers::NONE,
-                kind: _,
-                state: _,
-            }
-            | KeyEvent {
-                code: KeyCode::Char('c'),
-                modifiers: KeyModifiers::CONTROL,
-                kind: _,
-                state: _,
-            } => {
-                self.events.send(AppEvent::Quit);
-            }
+                        // Jump to the node at the other end of the selected port's link
+                        KeyEvent {
+                            code: KeyCode::Char('f'),
+                            ..
+                        } => {
+                            self.follow_link();
+                        }
 
-            // Discovery request
-            KeyEvent {
-                code: KeyCode::Char('d'),
-                ..
-            } => {
-                if self.config.scope_file.is_some() {
-                    self.status = "Scope file mode, skipping discovery.".into();
-                    let scope_file = self.config.scope_file.as_ref().unwrap();
-                    let nodes = read_scope_file(&scope_file);
-                    self.nodes = nodes;
-                    if !self.nodes.is_empty() {
-                        self.selected = 0;
-                        self.set_selected_node_guid();
-                    }
-                } else {
-                    self.discover_fabric();
-                }
+                        // Export the port counters to CSV
+                        KeyEvent {
+                            code: KeyCode::Char('x'),
+                            ..
+                        } => {
+                            self.export_ports();
+                        }
+
+                        // Clear the selected port's counters, after confirmation
+                        KeyEvent {
+                            code: KeyCode::Char('X'),
+                            ..
+                        } => {
+                            self.prompt_counter_reset();
+                        }
+
+                        // Toggle the bandwidth chart
+                        KeyEvent {
+                            code: KeyCode::Char('c'),
+                            ..
+                        } => {
+                            self.show_details_chart = !self.show_details_chart;
+                        }
+
+                        // Inspect every raw counter of the selected port
+                        KeyEvent {
+                            code: KeyCode::Char('r'),
+                            ..
+                        } => {
+                            self.inspect_counters();
+                        }
+
+                        // Sort the port table by the next column
+                        KeyEvent {
+                            code: KeyCode::Char('s'),
+                            ..
+                        } => {
+                            let column = self.popup_sort_column.next();
+                            self.set_popup_sort(column, column.default_ascending());
+                        }
+
+                        // Flip the port table's sort direction
+                        KeyEvent {
+                            code: KeyCode::Char('S'),
+                            ..
+                        } => {
+                            self.set_popup_sort(self.popup_sort_column, !self.popup_sort_ascending);
+                        }
+
+                        // Cycle between per-port rows, per-port rows plus the node total,
+                        // and the total alone
+                        KeyEvent {
+                            code: KeyCode::Char('t'),
+                            ..
+                        } => {
+                            self.details_ports = self.details_ports.next();
+                            self.popup_selected = 0;
+                            self.popup_table_offset = 0;
+                            if !self.pending_counter_update {
+                                self.update_counters();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Popup::Alerts => {
+                    match key_event {
+                        KeyEvent {
+                            code: KeyCode::Esc, ..
+                        }
+                        | KeyEvent {
+                            code: KeyCode::Enter,
+                            ..
+                        }
+                        | KeyEvent {
+                            code: KeyCode::Char('a'),
+                            ..
+                        } => {
+                            self.active_popup = Popup::None;
+                        }
+
+                        // Clear alerts
+                        KeyEvent {
+                            code: KeyCode::Char('c'),
+                            ..
+                        } => {
+                            self.bw_alerts.clear();
+                            self.bw_alert_count = 0;
+                            self.bw_alert_active.clear();
+                            self.status = "Bandwidth alerts cleared".into();
+                        }
+                        _ => {}
+                    }
+                }
+                Popup::Warnings => {
+                    if let KeyEvent {
+                        code: KeyCode::Esc | KeyCode::Enter | KeyCode::Char('i'),
+                        ..
+                    } = key_event
+                    {
+                        self.active_popup = Popup::None;
+                    }
+                }
+                Popup::Log => {
+                    if let KeyEvent {
+                        code: KeyCode::Esc | KeyCode::Enter | KeyCode::Char('l'),
+                        ..
+                    } = key_event
+                    {
+                        self.active_popup = Popup::None;
+                    }
+                }
+                Popup::ConfirmReset => match key_event {
+                    KeyEvent {
+                        code: KeyCode::Char('y'),
+                        ..
+                    } => {
+                        self.confirm_counter_reset(true);
+                    }
+                    KeyEvent {
+                        code: KeyCode::Esc | KeyCode::Char('n'),
+                        ..
+                    } => {
+                        self.confirm_counter_reset(false);
+                    }
+                    _ => {}
+                },
+                Popup::Counters => {
+                    let len = self
+                        .display_counters
+                        .get(&self.inspect_port)
+                        .map_or(0, HashMap::len);
+                    let vis = self.popup_visible_rows.get().max(1);
+                    let max_offset = len.saturating_sub(vis);
+                    match key_event {
+                        // Back to the details popup
+                        KeyEvent {
+                            code: KeyCode::Esc | KeyCode::Enter | KeyCode::Char('r'),
+                            ..
+                        } => {
+                            self.active_popup = Popup::Details;
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Down | KeyCode::Char('j'),
+                            ..
+                        } => {
+                            self.inspect_offset = (self.inspect_offset + 1).min(max_offset);
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Up | KeyCode::Char('k'),
+                            ..
+                        } => {
+                            self.inspect_offset =
+                                self.inspect_offset.min(max_offset).saturating_sub(1);
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::PageDown,
+                            ..
+                        } => {
+                            self.inspect_offset = (self.inspect_offset + vis).min(max_offset);
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::PageUp,
+                            ..
+                        } => {
+                            self.inspect_offset =
+                                self.inspect_offset.min(max_offset).saturating_sub(vis);
+                        }
+
+                        // Update counters
+                        KeyEvent {
+                            code: KeyCode::Char('u'),
+                            ..
+                        } => {
+                            self.update_counters();
+                        }
+                        _ => {}
+                    }
+                }
+                Popup::Metric => {
+                    match key_event {
+                        KeyEvent {
+                            code: KeyCode::Esc, ..
+                        }
+                        | KeyEvent {
+                            code: KeyCode::Char('m'),
+                            ..
+                        } => {
+                            self.active_popup = Popup::None;
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Down | KeyCode::Char('j'),
+                            ..
+                        } => {
+                            self.metric_selected =
+                                (self.metric_selected + 1).min(METRIC_COUNTERS.len() - 1);
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Up | KeyCode::Char('k'),
+                            ..
+                        } => {
+                            self.metric_selected = self.metric_selected.saturating_sub(1);
+                        }
+
+                        // Show the highlighted counter
+                        KeyEvent {
+                            code: KeyCode::Enter,
+                            ..
+                        } => {
+                            self.metric_counter = METRIC_COUNTERS[self.metric_selected].to_string();
+                            if !self.columns.contains(&MainColumn::Metric) {
+                                self.columns.push(MainColumn::Metric);
+                            }
+                            self.active_popup = Popup::None;
+                        }
+                        _ => {}
+                    }
+                }
+                Popup::Columns => {
+                    match key_event {
+                        KeyEvent {
+                            code: KeyCode::Esc, ..
+                        }
+                        | KeyEvent {
+                            code: KeyCode::Enter,
+                            ..
+                        }
+                        | KeyEvent {
+                            code: KeyCode::Char('c'),
+                            ..
+                        } => {
+                            self.active_popup = Popup::None;
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Down,
+                            ..
+                        } => {
+                            self.columns_selected =
+                                (self.columns_selected + 1).min(MainColumn::ALL.len() - 1);
+                        }
+
+                        KeyEvent {
+                            code: KeyCode::Up, ..
+                        } => {
+                            self.columns_selected = self.columns_selected.saturating_sub(1);
+                        }
+
+                        // Toggle the highlighted column
+                        KeyEvent {
+                            code: KeyCode::Char(' '),
+                            ..
+                        } => {
+                            self.toggle_column(MainColumn::ALL[self.columns_selected]);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        // Any key other than a second `g` cancels a pending `gg`.
+        let pending_g = std::mem::take(&mut self.pending_g);
+
+        match key_event {
+            // ESC cancels a counters update in flight instead of quitting
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } if self.pending_counter_update => {
+                self.events.send(AppEvent::Counters(CounterEvent::Cancel));
+                self.pending_counter_update = false;
+                self.queued_counter_update = false;
+                self.status = "Counter update cancelled".into();
+            }
+
+            // Quit keys: ESC, 'q', or Ctrl-C
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('q'),
+                modifiers: KeyModifiers::NONE,
+                kind: _,
+                state: _,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: _,
+                state: _,
+            } => {
+                self.events.send(AppEvent::Quit);
+            }
+
+            // Discovery request
+            KeyEvent {
+                code: KeyCode::Char('d'),
+                ..
+            } => {
+                self.discover_fabric();
+            }
+
+            // Clear the search filter without opening the popup; the cursor stays on the
+            // selected node
+            KeyEvent {
+                code: KeyCode::Char('u'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => {
+                if self.search_form.value.is_empty() {
+                    self.status = "No search filter to clear".into();
+                } else {
+                    self.search_form.clear();
+                    self.status = "Search filter cleared".into();
+                }
             }
 
             // Update counters
@@ -451,6 +1774,7 @@ impl App {
                 ..
             } => {
                 self.auto_update = !self.auto_update;
+                self.last_auto_update = Instant::now();
             }
 
             // Whole Counters
@@ -469,6 +1793,14 @@ impl App {
                 self.counter_mode = CounterMode::Delta;
             }
 
+            // Per-second rates
+            KeyEvent {
+                code: KeyCode::Char('P'),
+                ..
+            } => {
+                self.counter_mode = CounterMode::Rate;
+            }
+
             // Baseline Counters
             KeyEvent {
                 code: KeyCode::Char('B'),
@@ -478,6 +1810,61 @@ impl App {
                 self.counter_mode = CounterMode::Baseline;
             }
 
+            // Reset: drop the baseline and go back to whole counters
+            KeyEvent {
+                code: KeyCode::Char('R'),
+                ..
+            } => {
+                self.baseline_counters.clear();
+                self.previous_counters.clear();
+                self.counter_mode = CounterMode::Whole;
+                if !self.paused {
+                    self.display_counters = self.current_counters.clone();
+                    self.rows_revision += 1;
+                }
+                self.status = "Baseline cleared, showing whole counters".into();
+            }
+
+            // Pause / resume the displayed counters
+            KeyEvent {
+                code: KeyCode::Char('p'),
+                ..
+            } => {
+                self.paused = !self.paused;
+                if !self.paused
+                    && let Some(counters) = self.paused_counters.take()
+                {
+                    self.display_counters = counters;
+                    self.rows_revision += 1;
+                }
+            }
+
+            // Toggle error threshold highlighting
+            KeyEvent {
+                code: KeyCode::Char('E'),
+                ..
+            } => {
+                self.error_highlight = !self.error_highlight;
+            }
+
+            // Cycle secondary sort column
+            KeyEvent {
+                code: KeyCode::Char('s'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => {
+                self.secondary_sort_column = self.secondary_sort_column.next();
+            }
+
+            // Flip secondary sort direction
+            KeyEvent {
+                code: KeyCode::Char('S'),
+                modifiers,
+                ..
+            } if modifiers.contains(KeyModifiers::ALT) => {
+                self.secondary_sort_ascending = !self.secondary_sort_ascending;
+            }
+
             // Cycle sort column
             KeyEvent {
                 code: KeyCode::Char('s'),
@@ -490,13 +1877,17 @@ impl App {
                 code: KeyCode::Char('S'),
                 ..
             } => {
-                self.sort_ascending = !self.sort_ascending;
+                self.flip_sort_direction();
             }
 
             // Move selection down
             KeyEvent {
                 code: KeyCode::Down,
                 ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('j'),
+                ..
             } => {
                 if !self.nodes.is_empty() {
                     let max_idx = self.filtered_len().saturating_sub(1);
@@ -509,6 +1900,10 @@ impl App {
             // Move selection up
             KeyEvent {
                 code: KeyCode::Up, ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('k'),
+                ..
             } => {
                 if !self.nodes.is_empty() {
                     if self.selected > 0 {
@@ -546,10 +1941,22 @@ impl App {
                 }
             }
 
-            // Home (go to first row)
+            // First `g` of `gg`
+            KeyEvent {
+                code: KeyCode::Char('g'),
+                ..
+            } if !pending_g => {
+                self.pending_g = true;
+            }
+
+            // Home or `gg` (go to first row)
             KeyEvent {
                 code: KeyCode::Home,
                 ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('g'),
+                ..
             } => {
                 if !self.nodes.is_empty() {
                     self.selected = 0;
@@ -558,9 +1965,13 @@ impl App {
                 }
             }
 
-            // End (go to last row)
+            // End or `G` (go to last row)
             KeyEvent {
                 code: KeyCode::End, ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('G'),
+                ..
             } => {
                 if !self.nodes.is_empty() {
                     let len = self.filtered_len();
@@ -579,16 +1990,128 @@ impl App {
 
                 if self.selected_node.is_some() {
                     self.display_counters.clear();
+                    self.rows_revision += 1;
+                    self.paused_counters = None;
                     self.current_counters.clear();
                     self.previous_counters.clear();
                     self.popup_table_offset = 0;
                     self.popup_selected = 0;
                     self.active_popup = Popup::Details;
+
+                    // Fill the port table right away, unless a request is already in flight.
+                    if !self.pending_counter_update {
+                        self.update_counters();
+                    }
                 } else {
                     self.active_popup = Popup::None;
                 }
             }
 
+            // Export the displayed node table
+            KeyEvent {
+                code: KeyCode::Char('e'),
+                ..
+            } => {
+                self.export_nodes();
+            }
+
+            // Write a scope file
+            KeyEvent {
+                code: KeyCode::Char('w'),
+                ..
+            } => {
+                self.write_scope();
+            }
+
+            // Show Columns popup
+            KeyEvent {
+                code: KeyCode::Char('c'),
+                ..
+            } => {
+                self.active_popup = Popup::Columns;
+            }
+
+            // Pick the METRIC column's counter
+            KeyEvent {
+                code: KeyCode::Char('m'),
+                ..
+            } => {
+                self.metric_selected = METRIC_COUNTERS
+                    .iter()
+                    .position(|&c| c == self.metric_counter)
+                    .unwrap_or(0);
+                self.active_popup = Popup::Metric;
+            }
+
+            // Toggle refreshing only the visible rows on auto-update
+            KeyEvent {
+                code: KeyCode::Char('v'),
+                ..
+            } => {
+                self.visible_only_updates = !self.visible_only_updates;
+                self.status = if self.visible_only_updates {
+                    "Auto-update refreshes visible rows only (u = whole fabric)".into()
+                } else {
+                    "Auto-update refreshes the whole fabric".into()
+                };
+            }
+
+            // Copy the selected node's summary
+            KeyEvent {
+                code: KeyCode::Char('y'),
+                ..
+            } => {
+                self.copy_selected_node();
+            }
+
+            // Clear the selected node's counters, after confirmation
+            KeyEvent {
+                code: KeyCode::Char('X'),
+                ..
+            } => {
+                self.prompt_counter_reset();
+            }
+
+            // Cycle listing all nodes, switches only and HCAs only
+            KeyEvent {
+                code: KeyCode::Char('T'),
+                ..
+            } => {
+                self.node_type_filter = match self.node_type_filter {
+                    None => Some(NodeType::Switch),
+                    Some(NodeType::Switch) => Some(NodeType::Hca),
+                    Some(NodeType::Hca) => None,
+                };
+                self.status = match self.node_type_filter {
+                    Some(node_type) => format!("Showing {} nodes only", node_type.name()),
+                    None => "Showing all node types".into(),
+                };
+            }
+
+            // Show discovery warnings popup
+            KeyEvent {
+                code: KeyCode::Char('i'),
+                ..
+            } => {
+                self.active_popup = Popup::Warnings;
+            }
+
+            // Show the log popup
+            KeyEvent {
+                code: KeyCode::Char('l'),
+                ..
+            } => {
+                self.active_popup = Popup::Log;
+            }
+
+            // Show Alerts popup
+            KeyEvent {
+                code: KeyCode::Char('a'),
+                ..
+            } => {
+                self.active_popup = Popup::Alerts;
+            }
+
             // Show Search popup
             KeyEvent {
                 code: KeyCode::Char('/'),
@@ -602,17 +2125,407 @@ impl App {
         Ok(())
     }
 
-    // Discover Fabric
-    fn discover_fabric(&mut self) {
-        self.status = "Discovering...".into();
-        self.events
-            .send(AppEvent::Discover(DiscoveryEvent::Request));
+    /// Handle mouse inputs.
+    fn handle_mouse_event(&mut self, mouse_event: MouseEvent) {
+        match self.active_popup {
+            Popup::None => match mouse_event.kind {
+                MouseEventKind::ScrollDown => {
+                    let vis = self.visible_rows.get().max(1);
+                    let max_offset = self.filtered_len().saturating_sub(vis);
+                    self.table_offset = (self.table_offset + MOUSE_SCROLL_ROWS).min(max_offset);
+                }
+                MouseEventKind::ScrollUp => {
+                    let vis = self.visible_rows.get().max(1);
+                    let max_offset = self.filtered_len().saturating_sub(vis);
+                    self.table_offset = self
+                        .table_offset
+                        .min(max_offset)
+                        .saturating_sub(MOUSE_SCROLL_ROWS);
+                }
+                MouseEventKind::Down(MouseButton::Left) => {
+                    self.handle_table_click(Position::new(mouse_event.column, mouse_event.row));
+                }
+                _ => {}
+            },
+            // The wheel scrolls the port table; clicks are ignored
+            Popup::Details => {
+                let vis = self.popup_visible_rows.get().max(1);
+                let max_offset = self.details_rows().len().saturating_sub(vis);
+                match mouse_event.kind {
+                    MouseEventKind::ScrollDown => {
+                        self.popup_table_offset =
+                            (self.popup_table_offset + MOUSE_SCROLL_ROWS).min(max_offset);
+                    }
+                    MouseEventKind::ScrollUp => {
+                        self.popup_table_offset = self
+                            .popup_table_offset
+                            .min(max_offset)
+                            .saturating_sub(MOUSE_SCROLL_ROWS);
+                    }
+                    _ => {}
+                }
+            }
+            Popup::Counters => {
+                let len = self
+                    .display_counters
+                    .get(&self.inspect_port)
+                    .map_or(0, HashMap::len);
+                let vis = self.popup_visible_rows.get().max(1);
+                let max_offset = len.saturating_sub(vis);
+                match mouse_event.kind {
+                    MouseEventKind::ScrollDown => {
+                        self.inspect_offset =
+                            (self.inspect_offset + MOUSE_SCROLL_ROWS).min(max_offset);
+                    }
+                    MouseEventKind::ScrollUp => {
+                        self.inspect_offset = self
+                            .inspect_offset
+                            .min(max_offset)
+                            .saturating_sub(MOUSE_SCROLL_ROWS);
+                    }
+                    _ => {}
+                }
+            }
+            // Other popups swallow mouse input so it doesn't reach the table underneath
+            Popup::Search
+            | Popup::Alerts
+            | Popup::Columns
+            | Popup::Warnings
+            | Popup::Metric
+            | Popup::Log
+            | Popup::ConfirmReset => {}
+        }
+    }
+
+    /// Select the clicked row, or sort by the clicked header cell.
+    fn handle_table_click(&mut self, pos: Position) {
+        let area = self.table_area.get();
+        if !area.contains(pos) {
+            return;
+        }
+
+        if pos.y == area.y {
+            // Clicking the current sort column flips its direction
+            let clicked = self
+                .column_areas
+                .borrow()
+                .iter()
+                .position(|col_area| col_area.contains(pos));
+            if let Some(sort_idx) = clicked
+                .and_then(|idx| self.columns.get(idx))
+                .and_then(|col| col.sort_column())
+            {
+                if sort_idx == self.sort_column {
+                    self.flip_sort_direction();
+                } else {
+                    self.set_sort_column(sort_idx);
+                }
+            }
+            return;
+        }
+
+        // Rows start below the header; match the offset clamping done when rendering
+        let len = self.filtered_len();
+        let vis = self.visible_rows.get().max(1);
+        let idx = self.table_offset.min(len.saturating_sub(vis)) + (pos.y - area.y - 1) as usize;
+        if idx < len {
+            self.selected = idx;
+            self.set_selected_node_guid();
+        }
+    }
+
+    // Discover Fabric
+    fn discover_fabric(&mut self) {
+        self.status = if self.config.scope_file.is_some() {
+            "Loading scope file...".into()
+        } else {
+            "Discovering...".into()
+        };
+        self.discovery_start_time = Some(Utc::now());
+        self.discovery_warnings.clear();
+        self.load_name_map();
+        self.discovery_request_id = self.discovery_request_id.wrapping_add(1);
+        self.events.send(AppEvent::Discover(DiscoveryEvent::Request(
+            self.discovery_request_id,
+        )));
+    }
+
+    /// (Re)read the `--name-map` file, keeping the current names if it can't be read.
+    fn load_name_map(&mut self) {
+        let Some(path) = &self.config.name_map else {
+            return;
+        };
+
+        match read_name_map(path) {
+            Ok(names) => {
+                self.name_map = names;
+                self.rows_revision += 1;
+            }
+            Err(e) => tracing::warn!("Cannot read name map {path}: {e}"),
+        }
+    }
+
+    /// Name shown for `node`: its `--name-map` entry, or else its node description.
+    pub fn node_name<'a>(&'a self, node: &'a Node) -> &'a str {
+        self.name_map
+            .get(&node.guid)
+            .map_or(node.node_description.as_str(), String::as_str)
+    }
+
+    // Export the filtered and sorted node table to JSON
+    fn export_nodes(&mut self) {
+        let rows = self.visible_nodes();
+        self.status = match export_nodes_json(self.config.export_path.as_deref(), &rows) {
+            Ok(path) => format!("Exported {} nodes to {}", rows.len(), path.display()),
+            Err(e) => format!("Export failed: {e}"),
+        };
+    }
+
+    /// Copy a summary of the selected node to the clipboard, or to a temp file when there is
+    /// no clipboard.
+    fn copy_selected_node(&mut self) {
+        let Some(row) = &self.selected_node else {
+            self.status = "No node selected".into();
+            return;
+        };
+
+        let summary = format_node_summary(row, |gbps| {
+            format_bw(gbps, self.config.units, &self.counter_mode)
+        });
+        let name = &row.name;
+        self.status = match clipboard::copy(&summary) {
+            Ok(()) => format!("Copied {name} to the clipboard"),
+            Err(e) => {
+                let path =
+                    std::env::temp_dir().join(format!("ibtop-node-{}.txt", format_guid(row.guid)));
+                match fs::write(&path, &summary) {
+                    Ok(()) => format!("No clipboard ({e}), wrote {name} to {}", path.display()),
+                    Err(write_err) => format!("Copy failed: {e}; writing file failed: {write_err}"),
+                }
+            }
+        };
+    }
+
+    // Write the discovered nodes to a scope file for `--scope-file`
+    fn write_scope(&mut self) {
+        if self.nodes.is_empty() {
+            self.status = "No nodes discovered yet, nothing to write.".into();
+            return;
+        }
+
+        let path = timestamped_path(self.config.export_path.as_deref(), "ibtop-scope", "csv");
+        self.status = match write_scope_file(&path, &self.nodes) {
+            Ok(()) => format!("Wrote {} nodes to {}", self.nodes.len(), path.display()),
+            Err(e) => format!("Writing scope file failed: {e}"),
+        };
+    }
+
+    /// Open the raw counter inspector for the port selected in the details popup.
+    fn inspect_counters(&mut self) {
+        let Some(node) = self
+            .selected_node
+            .as_ref()
+            .and_then(|selected| self.nodes.iter().find(|n| n.guid == selected.guid))
+        else {
+            return;
+        };
+
+        let Some(port) = self
+            .details_rows()
+            .get(self.popup_selected)
+            .map(|row| row.port)
+        else {
+            self.status = "No port selected".into();
+            return;
+        };
+
+        self.inspect_port = node.counter_key(port);
+        self.inspect_offset = 0;
+        self.active_popup = Popup::Counters;
+    }
+
+    /// Ask to confirm clearing the counters of the port selected in the details popup, or of
+    /// every port of the selected node from the main table.
+    fn prompt_counter_reset(&mut self) {
+        let Some(node) = self
+            .selected_node
+            .as_ref()
+            .and_then(|selected| self.nodes.iter().find(|n| n.guid == selected.guid))
+        else {
+            self.status = "No node selected".into();
+            return;
+        };
+
+        let from_details = self.active_popup == Popup::Details;
+        let number = if from_details {
+            let Some(number) = self
+                .details_rows()
+                .get(self.popup_selected)
+                .map(|row| row.port)
+            else {
+                self.status = "No port selected".into();
+                return;
+            };
+            number
+        } else {
+            AGG_COUNTERS_PORT
+        };
+
+        self.reset_target = Some(LidPort {
+            lid: node.lid,
+            number,
+            hca: node.hca.clone(),
+        });
+        self.reset_from_details = from_details;
+        self.active_popup = Popup::ConfirmReset;
+    }
+
+    /// Close the reset prompt, sending the reset if it was `confirmed`.
+    fn confirm_counter_reset(&mut self, confirmed: bool) {
+        self.active_popup = if self.reset_from_details {
+            Popup::Details
+        } else {
+            Popup::None
+        };
+        let Some(target) = self.reset_target.take() else {
+            return;
+        };
+
+        let label = counter_reset_label(target.lid, target.number);
+        if !confirmed {
+            self.status = format!("Left the counters of {label} as they are");
+            return;
+        }
+        self.status = format!("Clearing the counters of {label}...");
+        self.events
+            .send(AppEvent::Counters(CounterEvent::Reset(vec![target])));
+    }
+
+    /// Report which counter resets succeeded and refresh the counters to show the result.
+    fn handle_reset_response(&mut self, results: HashMap<CounterKey, bool>) {
+        let mut results: Vec<_> = results.into_iter().collect();
+        results.sort_unstable();
+        let labels = |ok: bool| -> Vec<String> {
+            results
+                .iter()
+                .filter(|&&(_, done)| done == ok)
+                .map(|((_, lid, port), _)| counter_reset_label(*lid, *port))
+                .collect()
+        };
+
+        let (cleared, failed) = (labels(true), labels(false));
+        if !cleared.is_empty() {
+            self.update_counters();
+        }
+        self.status = if failed.is_empty() {
+            format!("Cleared the counters of {}", cleared.join(", "))
+        } else {
+            format!(
+                "Clearing the counters of {} failed (see the log)",
+                failed.join(", ")
+            )
+        };
+    }
+
+    /// Select the node at the other end of the link of the port selected in the details
+    /// popup and close the popup.
+    fn follow_link(&mut self) {
+        let Some(node) = self
+            .selected_node
+            .as_ref()
+            .and_then(|selected| self.nodes.iter().find(|n| n.guid == selected.guid))
+        else {
+            return;
+        };
+
+        // The total row has no link to follow
+        let number = self
+            .details_rows()
+            .get(self.popup_selected)
+            .map(|row| row.port);
+        let Some(port) = number.and_then(|number| node.ports.iter().find(|p| p.number == number))
+        else {
+            self.status = "No port selected".into();
+            return;
+        };
+        if port.remote_node_description.is_empty() && port.remote_guid.is_none() {
+            self.status = format!("Port {} has no remote node", port.number);
+            return;
+        }
+
+        // Match by GUID when known; descriptions can repeat
+        let remote = &port.remote_node_description;
+        let remote_node = match port.remote_guid {
+            Some(guid) => self.nodes.iter().find(|n| n.guid == guid),
+            None => self.nodes.iter().find(|n| n.node_description == *remote),
+        };
+        let Some(remote_guid) = remote_node.map(|n| n.guid) else {
+            self.status = format!("Remote node '{remote}' is not in the current node set");
+            return;
+        };
+        let Some(idx) = self
+            .visible_nodes()
+            .iter()
+            .position(|row| row.guid == remote_guid)
+        else {
+            self.status = format!("Remote node '{remote}' is hidden by the search filter");
+            return;
+        };
+
+        self.status = format!("Followed port {} to {remote}", port.number);
+        self.active_popup = Popup::None;
+        self.popup_selected = 0;
+        self.popup_table_offset = 0;
+        self.selected = idx;
+        self.set_selected_node_guid();
+        self.ensure_selected_visible();
+    }
+
+    // Export the selected node's port counters to CSV
+    fn export_ports(&mut self) {
+        let node = match &self.selected_node {
+            Some(selected) => self.nodes.iter().find(|n| n.guid == selected.guid),
+            None => None,
+        };
+        let Some(node) = node else {
+            self.status = "No node selected, nothing to export.".into();
+            return;
+        };
+
+        self.status = match export_ports_csv(
+            self.config.export_path.as_deref(),
+            node,
+            &self.display_counters,
+            &self.counter_mode,
+            &self.error_counters,
+        ) {
+            Ok(path) => format!("Exported port counters to {}", path.display()),
+            Err(e) => format!("Export failed: {e}"),
+        };
+    }
+
+    /// Whether the details popup is on screen, including under the counters inspector or a
+    /// reset prompt opened from it.
+    fn details_shown(&self) -> bool {
+        match self.active_popup {
+            Popup::Details | Popup::Counters => true,
+            Popup::ConfirmReset => self.reset_from_details,
+            _ => false,
+        }
     }
 
     // Update Counters
     fn update_counters(&mut self) {
+        self.request_counters(false);
+    }
+
+    /// Request counters for the current view. With `visible_only`, the main table only
+    /// refreshes the rows on screen and the selected node, keeping the others' last values.
+    fn request_counters(&mut self, visible_only: bool) {
+        // Latest wins: repeated requests while one is in flight collapse into one follow-up
         if self.pending_counter_update {
-            self.status = "Counters update is already pending...".into();
+            self.queued_counter_update = true;
+            self.status = "Counters update in progress, another is queued to run next".into();
             return;
         }
         if self.nodes.is_empty() {
@@ -625,20 +2538,38 @@ impl App {
         self.update_start_time = Some(Utc::now());
 
         let lid_ports: Vec<LidPort> = match self.active_popup {
-            Popup::Details => match &self.selected_node {
+            // The inspector and reset prompt keep the details popup's ports up to date
+            _ if self.details_shown() => match &self.selected_node {
                 Some(node) => {
-                    let node_option = self.nodes.iter().find(|n| n.guid == node.0);
+                    let node_option = self.nodes.iter().find(|n| n.guid == node.guid);
 
                     match node_option {
                         Some(node) => {
-                            self.status = node.node_description.clone();
-
-                            let lid = node.lid;
-                            node.ports
-                                .iter()
-                                .map(|p| LidPort {
-                                    lid,
-                                    number: p.number,
+                            self.status = self.node_name(node).to_string();
+
+                            let ports = match self.details_ports {
+                                DetailsPorts::TotalOnly => Vec::new(),
+                                _ => node
+                                    .ports
+                                    .iter()
+                                    .filter(|p| {
+                                        !self.config.skip_down_ports
+                                            || p.link_status() != LinkStatus::Down
+                                    })
+                                    .map(|p| p.number)
+                                    .collect(),
+                            };
+                            let total = match self.details_ports {
+                                DetailsPorts::PerPort => None,
+                                _ => Some(AGG_COUNTERS_PORT),
+                            };
+                            ports
+                                .into_iter()
+                                .chain(total)
+                                .map(|number| LidPort {
+                                    lid: node.lid,
+                                    number,
+                                    hca: node.hca.clone(),
                                 })
                                 .collect()
                         }
@@ -648,6 +2579,7 @@ impl App {
                             .map(|n| LidPort {
                                 lid: n.lid,
                                 number: AGG_COUNTERS_PORT,
+                                hca: n.hca.clone(),
                             })
                             .collect(),
                     }
@@ -658,9 +2590,26 @@ impl App {
                     .map(|n| LidPort {
                         lid: n.lid,
                         number: AGG_COUNTERS_PORT,
+                        hca: n.hca.clone(),
                     })
                     .collect(),
             },
+            // Only the rows on screen and the selected node
+            _ if visible_only => {
+                let rows = self.visible_nodes();
+                let vis = self.visible_rows.get().max(1);
+                let on_screen = rows.iter().skip(self.table_offset).take(vis);
+                let mut seen = HashSet::new();
+                on_screen
+                    .chain(rows.get(self.selected))
+                    .filter(|row| seen.insert(row.guid))
+                    .map(|row| LidPort {
+                        lid: row.lid,
+                        number: AGG_COUNTERS_PORT,
+                        hca: row.hca.clone(),
+                    })
+                    .collect()
+            }
             // Everything else
             _ => self
                 .nodes
@@ -668,18 +2617,90 @@ impl App {
                 .map(|n| LidPort {
                     lid: n.lid,
                     number: AGG_COUNTERS_PORT,
+                    hca: n.hca.clone(),
                 })
                 .collect(),
         };
+        self.pending_partial_update = visible_only && !self.details_shown();
 
-        self.events
-            .send(AppEvent::Counters(CounterEvent::Request(lid_ports)));
+        self.counters_request_id = self.counters_request_id.wrapping_add(1);
+        self.events.send(AppEvent::Counters(CounterEvent::Request(
+            self.counters_request_id,
+            lid_ports,
+        )));
+    }
+
+    /// Replace the node list with a finished discovery sweep's nodes.
+    fn handle_discovery_response(&mut self, nodes: Vec<Node>, stats: DiscoveryStats) {
+        self.discovery_streaming = false;
+        self.discovery_progress = None;
+        if let Some(recorder) = &mut self.recorder
+            && let Err(e) = recorder.record_nodes(&nodes)
+        {
+            tracing::warn!("Failed to record discovery response: {e}");
+        }
+        self.status = match self.discovery_start_time.take() {
+            Some(start) => {
+                let secs = (Utc::now() - start).num_milliseconds() as f64 / 1000.0;
+                let rate = if secs > 0.0 {
+                    nodes.len() as f64 / secs
+                } else {
+                    0.0
+                };
+                format!(
+                    "Discovered {} nodes in {secs:.1}s ({rate:.0} nodes/s)",
+                    nodes.len()
+                )
+            }
+            None => format!("Discovery complete: {} nodes found", nodes.len()),
+        };
+        if !self.discovery_warnings.is_empty() {
+            self.status.push_str(&format!(
+                ", {} warnings (i = show)",
+                self.discovery_warnings.len()
+            ));
+        }
+        self.nodes = nodes;
+        self.rows_revision += 1;
+        self.discovery_stats = Some(stats);
+        self.bw_history.clear();
     }
 
     /// Populate the counters
-    fn handle_counters_update(&mut self, counters: HashMap<(u16, i32), HashMap<String, u64>>) {
-        self.previous_counters = std::mem::take(&mut self.current_counters);
-        self.current_counters = counters;
+    fn handle_counters_update(&mut self, counters: CountersMap) {
+        let now = Utc::now();
+        // A failed query leaves the LID/port's sample as old as its last success
+        for (key, ctrs) in &counters {
+            if !is_query_failed(ctrs) {
+                self.counter_times.insert(key.clone(), now);
+            }
+        }
+
+        if std::mem::take(&mut self.pending_partial_update) {
+            // Rows off screen keep their previous and current samples
+            for (key, new_map) in counters {
+                match self.current_counters.insert(key.clone(), new_map) {
+                    Some(old_map) => self.previous_counters.insert(key, old_map),
+                    None => self.previous_counters.remove(&key),
+                };
+            }
+        } else {
+            self.previous_counters = std::mem::take(&mut self.current_counters);
+            self.current_counters = counters;
+        }
+
+        // Track the real time between responses; queries on a large fabric take variable time.
+        self.previous_counter_update = self.last_counter_update.replace(now);
+        self.sample_period = self.previous_counter_update.map(|prev| now - prev);
+        let fallback_interval_ns = self
+            .sample_period
+            .and_then(|period| period.num_nanoseconds())
+            .and_then(|ns| u64::try_from(ns).ok());
+
+        // Keep showing the frozen counters while paused; the new ones are shown on resume.
+        let frozen = self
+            .paused
+            .then(|| std::mem::take(&mut self.display_counters));
 
         match self.counter_mode {
             CounterMode::Whole => {
@@ -687,16 +2708,17 @@ impl App {
                 self.display_counters = self.current_counters.clone();
                 self.status = format!("Updated counters ({})", self.display_counters.len());
             }
-            CounterMode::Delta => {
+            // Rates are deltas divided by their sample interval when displayed.
+            CounterMode::Delta | CounterMode::Rate => {
                 self.display_counters.clear();
                 // For each LID in the incoming counters, mutate the old counters in place
                 for (lid, new_map) in &self.current_counters {
-                    if let Some(old_map) = self.previous_counters.get_mut(&lid) {
-                        let delta = calc_counters_delta(old_map, &new_map);
-                        self.display_counters.insert(*lid, delta);
+                    if let Some(old_map) = self.previous_counters.get_mut(lid) {
+                        let delta = calc_counters_delta(old_map, new_map, fallback_interval_ns);
+                        self.display_counters.insert(lid.clone(), delta);
                     } else {
                         // If we had no previous entry for that LID, just insert the new one
-                        self.display_counters.insert(*lid, new_map.clone());
+                        self.display_counters.insert(lid.clone(), new_map.clone());
                     }
                 }
 
@@ -705,62 +2727,345 @@ impl App {
             CounterMode::Baseline => {
                 self.display_counters.clear();
                 for (lid, new_map) in &self.current_counters {
-                    if let Some(old_map) = self.baseline_counters.get_mut(&lid) {
-                        let delta = calc_counters_delta(&old_map, &new_map);
-                        self.display_counters.insert(*lid, delta);
+                    if let Some(old_map) = self.baseline_counters.get_mut(lid) {
+                        let delta = calc_counters_delta(old_map, new_map, None);
+                        self.display_counters.insert(lid.clone(), delta);
                     } else {
                         // If we had no previous entry for that LID, just insert the new one
-                        self.display_counters.insert(*lid, new_map.clone());
+                        self.display_counters.insert(lid.clone(), new_map.clone());
                     }
                 }
                 self.status = format!("Updated counters ({})", self.current_counters.len());
             }
         }
 
+        self.rows_revision += 1;
+
+        let failed = self
+            .current_counters
+            .values()
+            .filter(|ctrs| is_query_failed(ctrs))
+            .count();
+        if failed > 0 {
+            self.status.push_str(&format!(", {failed} queries failed"));
+        }
+        let retries: u64 = self
+            .current_counters
+            .values()
+            .filter_map(|ctrs| ctrs.get(QUERY_RETRIES_KEY))
+            .sum();
+        if retries > 0 {
+            self.status.push_str(&format!(", {retries} retries"));
+        }
+
+        self.update_prometheus(fallback_interval_ns);
+
+        if let Some(frozen) = frozen {
+            self.paused_counters = Some(std::mem::replace(&mut self.display_counters, frozen));
+            self.status.push_str(" (paused)");
+        } else {
+            self.check_bw_alerts(fallback_interval_ns);
+            self.record_bw_history();
+        }
         self.pending_counter_update = false;
     }
 
+    /// Write a counters response to the `--json-stream` file with each LID's node details.
+    fn stream_counters(&mut self, counters: &CountersMap) {
+        let Some(stream) = &mut self.json_stream else {
+            return;
+        };
+
+        let nodes: HashMap<(&str, u16), StreamNode> = self
+            .nodes
+            .iter()
+            .map(|n| {
+                let name = self.name_map.get(&n.guid).unwrap_or(&n.node_description);
+                ((n.hca.as_str(), n.lid), StreamNode { guid: n.guid, name })
+            })
+            .collect();
+        if let Err(e) = stream.write_counters(counters, &nodes) {
+            tracing::warn!("Failed to write counters to the JSON stream: {e}");
+        }
+    }
+
+    /// Refresh the `--prometheus-port` metrics of every node whose aggregate counters were in
+    /// the last response. Rates always come from the last two samples, whatever the counter
+    /// mode; details popup updates leave the other nodes' values as they were.
+    fn update_prometheus(&mut self, fallback_interval_ns: Option<u64>) {
+        let Some(text) = &self.prometheus else {
+            return;
+        };
+
+        for n in &self.nodes {
+            let key = n.counter_key(AGG_COUNTERS_PORT);
+            let Some(new_map) = self.current_counters.get(&key) else {
+                continue;
+            };
+            if is_query_failed(new_map) {
+                continue;
+            }
+
+            let rates = self
+                .previous_counters
+                .get(&key)
+                .map(|old_map| calc_counters_delta(old_map, new_map, fallback_interval_ns))
+                .filter(|delta| !is_query_failed(delta));
+            let bps = |f: fn(&HashMap<String, u64>, &str, &CounterMode) -> f64, counter| {
+                rates
+                    .as_ref()
+                    .map(|delta| f(delta, counter, &CounterMode::Rate) * 1e9)
+            };
+
+            self.prometheus_nodes.insert(
+                n.guid,
+                NodeMetrics {
+                    lid: n.lid,
+                    name: self.node_name(n).to_string(),
+                    hca: n.hca.clone(),
+                    recv_bps: bps(get_bw, "rcv_bytes"),
+                    send_bps: bps(get_bw, "xmt_bytes"),
+                    bw_loss_bps: bps(get_bw_loss, "xmit_waits"),
+                    errors: count_errors(new_map, &self.error_counters),
+                },
+            );
+        }
+
+        // Drop nodes that are no longer part of the fabric
+        let guids: HashSet<u64> = self.nodes.iter().map(|n| n.guid).collect();
+        self.prometheus_nodes.retain(|guid, _| guids.contains(guid));
+
+        if let Ok(mut text) = text.lock() {
+            *text = render_metrics(&self.prometheus_nodes);
+        }
+    }
+
+    /// Append each node's current bandwidth to its history, keeping `history_len` samples.
+    fn record_bw_history(&mut self) {
+        let history_len = self.config.history_len;
+        if history_len == 0 {
+            return;
+        }
+
+        let now = Utc::now();
+        for n in &self.nodes {
+            let bw = |counter: &str| -> Option<f64> {
+                // Main view updates carry the aggregate port; details popup updates carry each
+                // port of the selected node, which are summed instead.
+                if let Some(ctrs) = self.display_counters.get(&n.counter_key(AGG_COUNTERS_PORT)) {
+                    return Some(get_bw(ctrs, counter, &self.counter_mode));
+                }
+                let ports: Vec<_> = n
+                    .ports
+                    .iter()
+                    .filter_map(|p| self.display_counters.get(&n.counter_key(p.number)))
+                    .collect();
+                (!ports.is_empty()).then(|| {
+                    ports
+                        .iter()
+                        .map(|ctrs| get_bw(ctrs, counter, &self.counter_mode))
+                        .sum()
+                })
+            };
+            let (Some(recv_bw), Some(xmt_bw)) = (bw("rcv_bytes"), bw("xmt_bytes")) else {
+                continue;
+            };
+
+            let history = self.bw_history.entry(n.guid).or_default();
+            if history.len() >= history_len {
+                history.pop_front();
+            }
+            history.push_back((now, recv_bw, xmt_bw));
+        }
+    }
+
+    /// Record an alert for each node whose recv or send bandwidth has crossed `--bw-alert-gbps`
+    /// since the previous sample.
+    ///
+    /// Bandwidth is the per-second rate between the last two samples, whichever counter mode
+    /// is shown.
+    fn check_bw_alerts(&mut self, fallback_interval_ns: Option<u64>) {
+        let Some(threshold) = self.config.bw_alert_gbps else {
+            return;
+        };
+
+        let now = Utc::now();
+        let mut active = HashSet::new();
+        for n in &self.nodes {
+            let key = n.counter_key(AGG_COUNTERS_PORT);
+            let (Some(old_map), Some(new_map)) = (
+                self.previous_counters.get(&key),
+                self.current_counters.get(&key),
+            ) else {
+                continue;
+            };
+            let rates = calc_counters_delta(old_map, new_map, fallback_interval_ns);
+            if is_query_failed(&rates) {
+                continue;
+            }
+
+            for (metric, counter) in [("RECV_BW", "rcv_bytes"), ("SEND_BW", "xmt_bytes")] {
+                let gbps = get_bw(&rates, counter, &CounterMode::Rate);
+                if gbps < threshold {
+                    continue;
+                }
+
+                active.insert((n.guid, metric));
+                if self.bw_alert_active.contains(&(n.guid, metric)) {
+                    continue;
+                }
+
+                self.bw_alert_count += 1;
+                if self.bw_alerts.len() == MAX_BW_ALERTS {
+                    self.bw_alerts.pop_front();
+                }
+                self.bw_alerts.push_back(BwAlert {
+                    timestamp: now,
+                    lid: n.lid,
+                    node_description: self.node_name(n).to_string(),
+                    metric,
+                    gbps,
+                });
+            }
+        }
+        self.bw_alert_active = active;
+    }
+
     // Called every tick.
     fn on_tick(&mut self) {
-        self.tick = (self.tick + 1) % TICK_RESET_INTERVAL; // Reset tick after TICK_RESET_INTERVAL - 1
-        if self.tick == 0 {
-            self.auto_update_counter += 1;
-        }
+        self.tick = self.tick.wrapping_add(1);
 
+        // Wall-clock time, so dropped or slow frames don't stretch the interval
         if self.auto_update
-            && self.pending_counter_update == false
-            && self.auto_update_counter >= self.auto_update_interval
+            && !self.pending_counter_update
+            && self.last_auto_update.elapsed() >= self.auto_update_period()
         {
             if !self.nodes.is_empty() {
                 self.status = "Updating counters...".into();
-                self.update_counters();
+                self.request_counters(self.visible_only_updates);
+            }
+            self.last_auto_update = Instant::now();
+        }
+    }
+
+    /// Start the update queued while the previous one was in flight, keeping the finished
+    /// update's status visible. The auto-update timer restarts so the two don't double up.
+    fn run_queued_counter_update(&mut self) {
+        if !std::mem::take(&mut self.queued_counter_update) || self.pending_counter_update {
+            return;
+        }
+
+        let finished = std::mem::take(&mut self.status);
+        self.update_counters();
+        self.last_auto_update = Instant::now();
+        self.status = format!("{finished}; running the queued update");
+    }
+
+    /// Age of the aggregate counters shown for `lid` on `hca` at `now`, if any have arrived.
+    pub fn counters_age(
+        &self,
+        hca: &str,
+        lid: u16,
+        now: DateTime<Utc>,
+    ) -> Option<chrono::Duration> {
+        self.counter_times
+            .get(&(hca.to_string(), lid, AGG_COUNTERS_PORT))
+            .map(|time| now - *time)
+    }
+
+    /// Age past which counters are shown as stale.
+    pub fn stale_after(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.auto_update_period() * STALE_AFTER_INTERVALS)
+            .unwrap_or(chrono::Duration::MAX)
+    }
+
+    /// Age of the oldest aggregate counters still shown in the main table.
+    pub fn oldest_sample_age(&self, now: DateTime<Utc>) -> Option<chrono::Duration> {
+        self.current_counters
+            .keys()
+            .filter(|(_, _, port)| *port == AGG_COUNTERS_PORT)
+            .filter_map(|(hca, lid, _)| self.counters_age(hca, *lid, now))
+            .max()
+    }
+
+    /// Time between automatic counter updates.
+    pub fn auto_update_period(&self) -> Duration {
+        Duration::from_secs(self.auto_update_interval as u64)
+    }
+
+    /// Show or hide a main table column, keeping at least one column visible.
+    fn toggle_column(&mut self, column: MainColumn) {
+        if let Some(pos) = self.columns.iter().position(|&c| c == column) {
+            if self.columns.len() > 1 {
+                self.columns.remove(pos);
             }
-            // Reset
-            self.auto_update_counter = 0;
+        } else {
+            // Keep the display order of `MainColumn::ALL`
+            self.columns.push(column);
+            self.columns
+                .sort_by_key(|c| MainColumn::ALL.iter().position(|a| a == c));
         }
     }
 
-    /// Increments the sort column, cycling through available columns (0-8).
-    /// Column 0 means no sorting, columns 1-8 correspond to different data fields.
+    /// Increments the sort column, cycling through `SortColumn::ALL` back to no sorting.
     fn increment_sort_column(&mut self) {
-        self.sort_column = (self.sort_column + 1) % MAX_SORT_COLUMNS;
+        self.set_sort_column(self.sort_column.next());
+    }
+
+    /// Sort by `column` in the direction it was last used with.
+    fn set_sort_column(&mut self, column: SortColumn) {
+        self.sort_column = column;
+        self.sort_ascending = self.sort_directions[column.index() as usize];
+    }
+
+    /// Sort the details popup's port table, keeping the selected port selected.
+    fn set_popup_sort(&mut self, column: DetailsSortColumn, ascending: bool) {
+        let port = self
+            .details_rows()
+            .get(self.popup_selected)
+            .map(|row| row.port);
+        self.popup_sort_column = column;
+        self.popup_sort_ascending = ascending;
+
+        let rows = self.details_rows();
+        if let Some(idx) = port.and_then(|port| rows.iter().position(|row| row.port == port)) {
+            self.select_popup_row(idx);
+        }
+    }
+
+    /// Select row `idx` of the details popup's port table, clamped to the last row, and
+    /// scroll so it is visible.
+    fn select_popup_row(&mut self, idx: usize) {
+        let len = self.details_rows().len();
+        self.popup_selected = idx.min(len.saturating_sub(1));
+
+        let vis = self.popup_visible_rows.get().max(1);
+        if self.popup_selected < self.popup_table_offset {
+            self.popup_table_offset = self.popup_selected;
+        } else if self.popup_selected >= self.popup_table_offset + vis {
+            self.popup_table_offset = self.popup_selected + 1 - vis;
+        }
+    }
+
+    /// Flip the sort direction, remembering it for the current column.
+    fn flip_sort_direction(&mut self) {
+        self.sort_ascending = !self.sort_ascending;
+        self.sort_directions[self.sort_column.index() as usize] = self.sort_ascending;
     }
 
     // Cleanly shuts down the application.
     fn quit(&mut self) {
+        if !self.config.no_persist
+            && let Err(e) = save_state(&self.persisted_state())
+        {
+            tracing::warn!("Failed to save state: {e}");
+        }
         self.running = false;
     }
 
     /// Number of rows after applying the current filter
-    fn filtered_len(&self) -> usize {
-        let re = regex::RegexBuilder::new(&self.search_form.value)
-            .case_insensitive(true)
-            .build()
-            .unwrap_or_else(|_| regex::Regex::new("").unwrap());
-        self.nodes
-            .iter()
-            .filter(|n| re.is_match(&n.node_description))
-            .count()
+    pub(crate) fn filtered_len(&self) -> usize {
+        self.visible_nodes().len()
     }
 
     /// Keep `table_offset` in sync so the selected row stays visible.
@@ -779,20 +3084,71 @@ impl App {
         }
     }
 
-    fn set_selected_node_guid(&mut self) {
-        // Create regex for filtering, defaulting to empty string if invalid
-        let re = regex::RegexBuilder::new(&self.search_form.value)
-            .case_insensitive(true)
-            .build()
-            .unwrap_or_else(|_| regex::Regex::new("").unwrap());
+    /// Filtered and sorted rows for the main node table, as currently displayed.
+    ///
+    /// The rows are cached and only rebuilt when the nodes, counters, filter, counter mode or
+    /// sort order change.
+    pub(crate) fn visible_nodes(&self) -> Rc<Vec<MainNodeInfo>> {
+        let key = VisibleNodesKey {
+            rows_revision: self.rows_revision,
+            filter: self.search_form.regex().as_str().to_string(),
+            filter_field: self.search_form.field(),
+            fuzzy_query: self.search_form.fuzzy_query().map(str::to_string),
+            node_type_filter: self.node_type_filter,
+            counter_mode: self.counter_mode,
+            sort: (self.sort_column, self.sort_ascending),
+            secondary_sort: (self.secondary_sort_column, self.secondary_sort_ascending),
+            metric_counter: self.metric_counter.clone(),
+        };
+
+        let mut cache = self.visible_nodes_cache.borrow_mut();
+        if let Some((cached_key, rows)) = cache.as_ref()
+            && *cached_key == key
+        {
+            return Rc::clone(rows);
+        }
+
+        let rows = Rc::new(self.node_info());
+        *cache = Some((key, Rc::clone(&rows)));
+        rows
+    }
+
+    /// Build the main table rows from scratch; see [`App::visible_nodes`].
+    fn node_info(&self) -> Vec<MainNodeInfo> {
+        // Regex for filtering; an invalid search keeps the last valid filter applied
+        let re = self.search_form.regex();
+        let fuzzy_query = self.search_form.fuzzy_query();
+        // Fuzzy match quality by GUID; rows are ranked by it while a fuzzy search is active
+        let mut scores: HashMap<u64, i64> = HashMap::new();
 
         // Filter and gather node information
-        let mut node_info: Vec<(u64, u16, String, u16, f64, f64, f64, u128, String)> = self
+        let mut node_info: Vec<MainNodeInfo> = self
             .nodes
             .iter()
-            .filter(|n| re.is_match(&n.node_description))
+            .filter(|n| self.node_type_filter.is_none_or(|t| t == n.node_type))
+            .filter(|n| {
+                let guid = format_guid(n.guid);
+                let lid = n.lid.to_string();
+                // Descriptions match the friendly name or the original description
+                let desc = [self.node_name(n), n.node_description.as_str()];
+                let fields: Vec<&str> = match self.search_form.field() {
+                    SearchField::Any => vec![desc[0], desc[1], &guid, &lid],
+                    SearchField::Description => desc.to_vec(),
+                    SearchField::Guid => vec![&guid],
+                    SearchField::Lid => vec![&lid],
+                };
+
+                let Some(query) = fuzzy_query else {
+                    return fields.iter().any(|field| re.is_match(field));
+                };
+                let best = fields.iter().filter_map(|f| fuzzy_score(query, f)).max();
+                if let Some(score) = best {
+                    scores.insert(n.guid, score);
+                }
+                best.is_some()
+            })
             .map(|n| {
-                let counters = self.display_counters.get(&(n.lid, AGG_COUNTERS_PORT));
+                let counters = self.display_counters.get(&n.counter_key(AGG_COUNTERS_PORT));
 
                 let recv_bw =
                     counters.map_or(0.0, |ctrs| get_bw(ctrs, "rcv_bytes", &self.counter_mode));
@@ -801,44 +3157,93 @@ impl App {
                 let xmit_waits = counters.map_or(0.0, |ctrs| {
                     get_bw_loss(ctrs, "xmit_waits", &self.counter_mode)
                 });
-                let error_count = counters.map_or(0, |ctrs| count_errors(ctrs));
-                let error_strings = counters.map_or("".to_string(), |ctrs| get_error_strings(ctrs));
-
-                (
-                    n.guid,
-                    n.lid,
-                    n.node_description.clone(),
-                    n.ports.len() as u16,
+                let error_count =
+                    counters.map_or(0, |ctrs| count_errors(ctrs, &self.error_counters));
+                let error_strings = counters.map_or("".to_string(), |ctrs| {
+                    get_error_strings(ctrs, &self.error_string_counters)
+                });
+
+                MainNodeInfo {
+                    guid: n.guid,
+                    lid: n.lid,
+                    name: self.node_name(n).to_string(),
+                    // Count linked ports only; down ports are listed in the details popup.
+                    ports: n
+                        .ports
+                        .iter()
+                        .filter(|p| p.link_status() != LinkStatus::Down)
+                        .count() as u16,
                     recv_bw,
-                    xmt_bw,
-                    xmit_waits,
+                    send_bw: xmt_bw,
+                    bw_loss: xmit_waits,
                     error_count,
                     error_strings,
-                )
+                    hca: n.hca.clone(),
+                    line_rate: get_line_rate(&n.ports, self.config.line_rate_gbps),
+                    metric: counters.map_or(0.0, |ctrs| {
+                        get_metric(ctrs, &self.metric_counter, &self.counter_mode)
+                    }),
+                    node_type: n.node_type,
+                }
             })
             .collect();
 
-        // Sort based on `self.sort_column`
+        // Sort based on `self.sort_column`, breaking ties with `self.secondary_sort_column`.
+        // A fuzzy search ranks the best matches first and sorts within equal scores.
         node_info.sort_by(|a, b| {
-            let ordering = match self.sort_column {
-                1 => a.1.cmp(&b.1),                                    // LID
-                2 => a.2.cmp(&b.2),                                    // Description
-                3 => a.3.cmp(&b.3),                                    // Port count
-                4 => a.4.partial_cmp(&b.4).unwrap_or(Ordering::Equal), // Receive BW
-                5 => a.5.partial_cmp(&b.5).unwrap_or(Ordering::Equal), // Transmit BW
-                6 => a.6.partial_cmp(&b.6).unwrap_or(Ordering::Equal), // Xmit waits
-                7 => a.7.cmp(&b.7),                                    // Error count
-                8 => a.8.cmp(&b.8),                                    // Error strings
-                _ => Ordering::Equal,
-            };
+            let by_score = scores.get(&b.guid).cmp(&scores.get(&a.guid));
+            if by_score != Ordering::Equal {
+                return by_score;
+            }
 
-            if self.sort_ascending {
+            let ordering = self.sort_column.compare(a, b);
+            let ordering = if self.sort_ascending {
                 ordering
             } else {
                 ordering.reverse()
-            }
+            };
+
+            ordering.then_with(|| {
+                let tiebreak = self.secondary_sort_column.compare(a, b);
+                if self.secondary_sort_ascending {
+                    tiebreak
+                } else {
+                    tiebreak.reverse()
+                }
+            })
         });
 
+        node_info
+    }
+
+    /// Keep the cursor on the selected node when rows are re-sorted, re-filtered or
+    /// rediscovered. The index is only clamped when the node is no longer listed.
+    fn follow_selected_node(&mut self) {
+        let guid = self.selected_node.as_ref().map(|node| node.guid);
+        let position =
+            guid.and_then(|guid| self.visible_nodes().iter().position(|row| row.guid == guid));
+
+        // The details and counters popups show the selected node; rather than switch them to
+        // whichever row now sits under the cursor, close them when the node is gone.
+        if guid.is_some() && position.is_none() && self.details_shown() {
+            self.active_popup = Popup::None;
+            self.reset_target = None;
+            self.status = "Selected node is no longer listed, closed its details".into();
+        }
+
+        let previous = self.selected;
+        if let Some(idx) = position {
+            self.selected = idx;
+        }
+        self.set_selected_node_guid();
+        if self.selected != previous {
+            self.ensure_selected_visible();
+        }
+    }
+
+    fn set_selected_node_guid(&mut self) {
+        let node_info = self.visible_nodes();
+
         // Clamp selection to available rows and set the selected GUID
         if self.selected >= node_info.len() {
             self.selected = node_info.len().saturating_sub(1);
@@ -853,29 +3258,524 @@ impl App {
     }
 }
 
+/// What a counter reset of `port` on `lid` covers, for the prompt and status line.
+pub(crate) fn counter_reset_label(lid: u16, port: i32) -> String {
+    if port == AGG_COUNTERS_PORT {
+        format!("every port of LID {lid}")
+    } else {
+        format!("LID {lid} port {port}")
+    }
+}
+
+/// Error counters to use from a configured list, defaulting to all of [`ERROR_COUNTERS`].
+///
+/// `--error-counters` is validated by clap; names from the environment are checked here and
+/// unknown ones dropped.
+fn resolve_error_counters(names: &Option<Vec<String>>) -> Vec<String> {
+    let Some(names) = names else {
+        return ERROR_COUNTERS.iter().map(|&c| c.to_string()).collect();
+    };
+
+    names
+        .iter()
+        .filter(|name| {
+            let known = ERROR_COUNTERS.contains(&name.as_str());
+            if !known {
+                tracing::warn!("Ignoring unknown error counter '{name}'");
+            }
+            known
+        })
+        .cloned()
+        .collect()
+}
+
 /// Calculate the delta between two counter maps.
 ///
 /// This function computes the difference between new and old counter values.
-/// If the new value is less than the old value (indicating a counter reset),
-/// it returns the new value as-is.
+/// If the new value is less than the old value, a counter from [`WRAPPING_COUNTERS`] is
+/// assumed to have wrapped when the wrapped delta is under half its range; the wrapped delta
+/// is used and `<counter>_wrapped` is set so the UI can flag it. Any other drop is taken as a
+/// reset and the new value is returned as-is. Failed queries are also returned as-is.
+///
+/// Sample timestamps are kept as-is, and the time between the two samples' end timestamps
+/// is stored under [`SAMPLE_INTERVAL_KEY`] so rates use the true elapsed time. When the
+/// samples carry no timestamps, `fallback_interval_ns` (time between responses) is used.
 fn calc_counters_delta(
     old_map: &HashMap<String, u64>,
     new_map: &HashMap<String, u64>,
+    fallback_interval_ns: Option<u64>,
 ) -> HashMap<String, u64> {
+    // A failed query has nothing to diff; show the new entry as-is.
+    if is_query_failed(old_map) || is_query_failed(new_map) {
+        return new_map.clone();
+    }
+
     let mut output = HashMap::new();
 
+    let interval_ns = match (old_map.get("end_timestamp"), new_map.get("end_timestamp")) {
+        (Some(&old_end), Some(&new_end)) => Some(new_end.saturating_sub(old_end)),
+        _ => fallback_interval_ns,
+    };
+    if let Some(interval_ns) = interval_ns {
+        output.insert(SAMPLE_INTERVAL_KEY.to_string(), interval_ns);
+    }
+
     for (key, &new_val) in new_map {
+        // Per-sample values, not counters
+        if key == "start_timestamp" || key == "end_timestamp" || key == QUERY_RETRIES_KEY {
+            output.insert(key.clone(), new_val);
+            continue;
+        }
+
         let old_val = old_map.get(key).copied().unwrap_or(0);
 
         let delta = match new_val.cmp(&old_val) {
             Ordering::Equal | Ordering::Greater => new_val.saturating_sub(old_val),
-            _ => {
+            _ => match wrapped_delta(key, old_val, new_val) {
+                Some(delta) => {
+                    output.insert(format!("{key}{WRAPPED_KEY_SUFFIX}"), 1);
+                    delta
+                }
                 // Counter likely reset, use new value as-is
-                new_val
-            }
+                None => new_val,
+            },
         };
         output.insert(key.clone(), delta);
     }
 
     output
 }
+
+/// Delta of a fixed-width counter that dropped from `old_val` to `new_val`, if the drop looks
+/// like a wrap rather than a reset.
+fn wrapped_delta(key: &str, old_val: u64, new_val: u64) -> Option<u64> {
+    let &(_, bits) = WRAPPING_COUNTERS.iter().find(|(name, _)| *name == key)?;
+    let range = 1u64.checked_shl(bits)?;
+    if old_val >= range || new_val >= range {
+        return None;
+    }
+
+    let delta = range - old_val + new_val;
+    (delta < range / 2).then_some(delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::helpers::counter_wrapped;
+    use clap::{CommandFactory, FromArgMatches, Parser};
+    use std::sync::Mutex;
+
+    /// Load the configuration for an `ibtop` command line.
+    fn load(args: &[&str]) -> color_eyre::Result<AppConfig> {
+        let matches = Args::command()
+            .try_get_matches_from(std::iter::once("ibtop").chain(args.iter().copied()))?;
+        let args = Args::from_arg_matches(&matches)?;
+        AppConfig::load(args, &matches)
+    }
+
+    /// Serializes tests that set `IBTOP_*` environment variables, which the whole process shares.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Write `contents` to a config file unique to this test.
+    fn config_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ibtop-{}-{name}.toml", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn command_line_beats_environment_beats_config_file() {
+        let _env = ENV_LOCK.lock().unwrap();
+        let file = config_file("precedence", "hca = \"baz\"\ntimeout = 200\nretries = 5\n");
+
+        // SAFETY: ENV_LOCK is held, so no other test reads or writes the environment.
+        unsafe {
+            std::env::set_var("IBTOP_HCA", "bar");
+            std::env::set_var("IBTOP_TIMEOUT", "300");
+        }
+        let cli = load(&["--config", &file, "--hca", "foo"]);
+        let env = load(&["--config", &file]);
+        unsafe {
+            std::env::remove_var("IBTOP_HCA");
+            std::env::remove_var("IBTOP_TIMEOUT");
+        }
+        let file_only = load(&["--config", &file]);
+        let _ = fs::remove_file(&file);
+
+        let cli = cli.unwrap();
+        assert_eq!(
+            (cli.hca.as_str(), cli.timeout, cli.retries),
+            ("foo", 300, 5)
+        );
+        assert_eq!(env.unwrap().hca, "bar");
+        assert_eq!(file_only.unwrap().hca, "baz");
+    }
+
+    /// An app backed by the test discovery and counters services. Headless, so there are no
+    /// tick or terminal events and tests call `on_tick` themselves.
+    fn test_app() -> App {
+        App::new(AppConfig {
+            hca: "mlx5_0".into(),
+            service_type: "test".into(),
+            no_persist: true,
+            once: true,
+            threads: 1,
+            timeout: 1000,
+            update_interval: 1,
+            ..AppConfig::default()
+        })
+    }
+
+    #[test]
+    fn tick_after_discovery_queries_the_new_nodes() {
+        let mut app = test_app();
+        // Nodes left from an earlier sweep, replaced by the response already on its way
+        let stale = Node {
+            guid: 1,
+            node_description: "stale".into(),
+            ports: Vec::new(),
+            lid: 60000,
+            hca: "mlx5_0".into(),
+            node_type: NodeType::Switch,
+        };
+        app.nodes = vec![stale];
+        app.auto_update = true;
+
+        // A tick before the update is due, then the discovery response
+        app.on_tick();
+        assert!(!app.pending_counter_update);
+        while app.discovery_start_time.is_some() {
+            app.handle_events().unwrap();
+        }
+        assert!(app.nodes.iter().all(|n| n.lid != 60000));
+
+        // The next tick that's due asks for the discovered nodes only
+        app.last_auto_update = Instant::now() - Duration::from_secs(60);
+        app.on_tick();
+        assert!(app.pending_counter_update);
+        while app.pending_counter_update {
+            app.handle_events().unwrap();
+        }
+        let queried: HashSet<_> = app.current_counters.keys().cloned().collect();
+        let expected: HashSet<_> = app
+            .nodes
+            .iter()
+            .map(|n| n.counter_key(AGG_COUNTERS_PORT))
+            .collect();
+        assert_eq!(queried, expected);
+    }
+
+    #[test]
+    fn auto_update_interval_survives_dropped_frames() {
+        let mut app = test_app();
+        while app.discovery_start_time.is_some() {
+            app.handle_events().unwrap();
+        }
+        assert!(!app.nodes.is_empty());
+        app.auto_update = true;
+        app.auto_update_interval = 10;
+
+        // Frames were dropped for several periods: one tick catches up with one request
+        app.last_auto_update = Instant::now() - app.auto_update_period() * 3;
+        app.on_tick();
+        assert_eq!(app.counters_request_id, 1);
+        while app.pending_counter_update {
+            app.handle_events().unwrap();
+        }
+
+        // Ticks within the next period send nothing more
+        app.on_tick();
+        app.on_tick();
+        assert_eq!(app.counters_request_id, 1);
+        assert!(!app.pending_counter_update);
+    }
+
+    #[test]
+    fn filter_and_cursor_survive_the_details_popup() {
+        let mut app = test_app();
+        while app.discovery_start_time.is_some() {
+            app.handle_events().unwrap();
+        }
+        let node = |i: u16, name: &str| Node {
+            guid: i as u64,
+            node_description: format!("{name}-{i:02}"),
+            ports: Vec::new(),
+            lid: i,
+            hca: "mlx5_0".into(),
+            node_type: NodeType::Switch,
+        };
+        let nodes: Vec<Node> = (1..=30)
+            .map(|i| node(i, if i % 3 == 0 { "spine" } else { "leaf" }))
+            .collect();
+        app.handle_discovery_response(nodes.clone(), DiscoveryStats::default());
+        app.visible_rows.set(5);
+        let press = |app: &mut App, code| app.handle_key_event(KeyEvent::from(code)).unwrap();
+
+        press(&mut app, KeyCode::Char('/'));
+        for c in "leaf".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Enter);
+        app.follow_selected_node();
+        assert_eq!(app.filtered_len(), 20);
+        for _ in 0..12 {
+            press(&mut app, KeyCode::Down);
+        }
+        app.follow_selected_node();
+        let (selected, table_offset) = (app.selected, app.table_offset);
+        let guid = app.selected_node.as_ref().unwrap().guid;
+        assert_eq!(selected, 12);
+        assert_ne!(table_offset, 0);
+
+        // Open the selected node's details and go back
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.active_popup, Popup::Details);
+        app.follow_selected_node();
+        press(&mut app, KeyCode::Esc);
+        app.follow_selected_node();
+        assert_eq!(app.active_popup, Popup::None);
+        assert_eq!((app.selected, app.table_offset), (selected, table_offset));
+        assert_eq!(app.selected_node.as_ref().unwrap().guid, guid);
+        assert_eq!(app.search_form.value, "leaf");
+        assert_eq!(app.filtered_len(), 20);
+
+        // The details close when a later discovery no longer finds the node
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.active_popup, Popup::Details);
+        let remaining = nodes.into_iter().filter(|n| n.guid != guid).collect();
+        app.handle_discovery_response(remaining, DiscoveryStats::default());
+        app.follow_selected_node();
+        assert_eq!(app.active_popup, Popup::None);
+        assert_eq!(app.search_form.value, "leaf");
+        assert_eq!(app.filtered_len(), 19);
+    }
+
+    /// A counters sample with the given counters, answered at `end_secs`.
+    fn sample(end_secs: u64, counters: &[(&str, u64)]) -> HashMap<String, u64> {
+        counters
+            .iter()
+            .map(|&(key, value)| (key.to_string(), value))
+            .chain([("end_timestamp".to_string(), end_secs * 1_000_000_000)])
+            .collect()
+    }
+
+    #[test]
+    fn rate_uses_the_interval_between_samples() {
+        // 1 GB is 250M four-byte words; over 2 seconds that's 4 Gbps
+        let old = sample(1_700_000_000, &[("rcv_bytes", 1_000)]);
+        let new = sample(1_700_000_002, &[("rcv_bytes", 1_000 + 250_000_000)]);
+        let delta = calc_counters_delta(&old, &new, None);
+
+        assert_eq!(delta[SAMPLE_INTERVAL_KEY], 2_000_000_000);
+        assert_eq!(delta["rcv_bytes"], 250_000_000);
+        assert!((get_bw(&delta, "rcv_bytes", &CounterMode::Rate) - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn interval_falls_back_without_timestamps() {
+        let old = HashMap::from([("rcv_bytes".to_string(), 0)]);
+        let new = HashMap::from([("rcv_bytes".to_string(), 250_000_000)]);
+        let delta = calc_counters_delta(&old, &new, Some(4_000_000_000));
+        assert!((get_bw(&delta, "rcv_bytes", &CounterMode::Rate) - 2.0).abs() < 1e-9);
+
+        let delta = calc_counters_delta(&old, &new, None);
+        assert!(!delta.contains_key(SAMPLE_INTERVAL_KEY));
+        assert_eq!(get_bw(&delta, "rcv_bytes", &CounterMode::Rate), 0.0);
+    }
+
+    #[test]
+    fn reset_counter_shows_its_new_value() {
+        let old = sample(10, &[("rcv_bytes", 5_000_000)]);
+        let new = sample(12, &[("rcv_bytes", 300)]);
+        let delta = calc_counters_delta(&old, &new, None);
+        assert_eq!(delta["rcv_bytes"], 300);
+        assert!(!counter_wrapped(&delta, "rcv_bytes"));
+    }
+
+    #[test]
+    fn xmit_waits_wrap_is_counted_across_the_wrap() {
+        let old = sample(10, &[("xmit_waits", u32::MAX as u64 - 9)]);
+        let new = sample(12, &[("xmit_waits", 5)]);
+        let delta = calc_counters_delta(&old, &new, None);
+        assert_eq!(delta["xmit_waits"], 15);
+        assert!(counter_wrapped(&delta, "xmit_waits"));
+    }
+
+    #[test]
+    fn xmit_waits_cleared_to_zero_is_a_reset() {
+        let old = sample(10, &[("xmit_waits", 1_000_000)]);
+        let new = sample(12, &[("xmit_waits", 0)]);
+        let delta = calc_counters_delta(&old, &new, None);
+        assert_eq!(delta["xmit_waits"], 0);
+        assert!(!counter_wrapped(&delta, "xmit_waits"));
+    }
+
+    #[test]
+    fn wrapped_delta_only_for_known_width_counters() {
+        assert_eq!(wrapped_delta("xmit_waits", u32::MAX as u64, 0), Some(1));
+        // Values that don't fit the counter's width can't have wrapped
+        assert_eq!(wrapped_delta("xmit_waits", 1 << 32, 0), None);
+        // 64-bit data counters only drop on a reset
+        assert_eq!(wrapped_delta("rcv_bytes", u64::MAX, 0), None);
+    }
+
+    #[test]
+    fn bw_alerts_use_rates_in_every_counter_mode() {
+        for counter_mode in [
+            CounterMode::Whole,
+            CounterMode::Delta,
+            CounterMode::Baseline,
+        ] {
+            let mut app = test_app();
+            app.config.bw_alert_gbps = Some(3.0);
+            app.counter_mode = counter_mode;
+            app.nodes = vec![Node {
+                guid: 7,
+                node_description: "leaf-01".into(),
+                ports: Vec::new(),
+                lid: 7,
+                hca: "mlx5_0".into(),
+                node_type: NodeType::Switch,
+            }];
+            let key = app.nodes[0].counter_key(AGG_COUNTERS_PORT);
+
+            // 1 GB received over 2 seconds is 4 Gbps
+            app.handle_counters_update(HashMap::from([(
+                key.clone(),
+                sample(10, &[("rcv_bytes", 1_000)]),
+            )]));
+            assert!(app.bw_alerts.is_empty());
+            app.handle_counters_update(HashMap::from([(
+                key,
+                sample(12, &[("rcv_bytes", 1_000 + 250_000_000)]),
+            )]));
+            assert_eq!(app.bw_alerts.len(), 1, "{counter_mode:?}");
+            assert_eq!(app.bw_alerts[0].metric, "RECV_BW");
+            assert!((app.bw_alerts[0].gbps - 4.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn saved_view_settings_are_restored() {
+        let mut app = test_app();
+        app.sort_column = SortColumn::ErrCnt;
+        app.sort_ascending = true;
+        app.counter_mode = CounterMode::Rate;
+        app.search_form.value = "leaf".into();
+        app.search_form.update_filter();
+        app.metric_counter = "rcv_pkts".into();
+        let state = app.persisted_state();
+
+        let mut restored = test_app();
+        restored.apply_state(state.clone());
+        assert_eq!(restored.persisted_state(), state);
+        assert_eq!(restored.search_form.regex().as_str(), "leaf");
+
+        // Baseline mode needs a baseline, and unknown counters are ignored
+        restored.apply_state(PersistedState {
+            counter_mode: CounterMode::Baseline,
+            metric_counter: "no_such_counter".into(),
+            ..state
+        });
+        assert_eq!(restored.counter_mode, CounterMode::Whole);
+        assert_eq!(restored.metric_counter, "rcv_pkts");
+    }
+
+    #[test]
+    fn sort_column_cycle_visits_every_column_once() {
+        let mut seen = Vec::new();
+        let mut column = SortColumn::None;
+        loop {
+            seen.push(column);
+            column = column.next();
+            if column == SortColumn::None {
+                break;
+            }
+        }
+        assert_eq!(seen, SortColumn::ALL);
+        assert_eq!(SortColumn::from_index(-1), SortColumn::Guid);
+        assert_eq!(
+            SortColumn::from_index(SortColumn::ALL.len() as i32),
+            SortColumn::None
+        );
+    }
+
+    #[test]
+    fn sorting_by_type_or_hca_groups_rows_stably() {
+        let mut app = test_app();
+        let node = |lid, hca: &str, node_type| Node {
+            guid: lid as u64,
+            node_description: format!("node-{lid}"),
+            ports: Vec::new(),
+            lid,
+            hca: hca.into(),
+            node_type,
+        };
+        app.nodes = vec![
+            node(5, "mlx5_1", NodeType::Hca),
+            node(3, "mlx5_0", NodeType::Switch),
+            node(9, "mlx5_1", NodeType::Switch),
+            node(1, "mlx5_0", NodeType::Hca),
+            node(7, "mlx5_0", NodeType::Switch),
+        ];
+        app.rows_revision += 1;
+
+        let order = |app: &App| -> Vec<u16> { app.visible_nodes().iter().map(|r| r.lid).collect() };
+        app.sort_column = SortColumn::Type;
+        app.sort_ascending = SortColumn::Type.default_ascending();
+        assert_eq!(order(&app), [3, 9, 7, 5, 1]);
+
+        app.sort_column = SortColumn::Hca;
+        app.sort_ascending = SortColumn::Hca.default_ascending();
+        assert_eq!(order(&app), [3, 1, 7, 5, 9]);
+    }
+
+    #[test]
+    fn config_file_fills_in_options_not_given() {
+        let _env = ENV_LOCK.lock().unwrap();
+        let path = std::env::temp_dir().join(format!("ibtop-{}-layers.yaml", std::process::id()));
+        fs::write(&path, "hca: mlx5_1\nupdate_interval: 5\n").unwrap();
+        let config = load(&["--config", path.to_str().unwrap(), "--timeout", "250"]);
+        let _ = fs::remove_file(&path);
+
+        let config = config.unwrap();
+        assert_eq!(config.hca, "mlx5_1");
+        assert_eq!(config.update_interval, 5);
+        assert_eq!(config.timeout, 250);
+        // Options in neither keep their defaults
+        assert_eq!(
+            config.retries,
+            Args::try_parse_from(["ibtop"]).unwrap().retries
+        );
+    }
+
+    #[test]
+    fn config_file_values_are_validated() {
+        let _env = ENV_LOCK.lock().unwrap();
+        let file = config_file("invalid", "hca = \"mlx5_0\"\nthreads = 0\n");
+        let err = load(&["--config", &file]).unwrap_err();
+        let _ = fs::remove_file(&file);
+        assert!(
+            err.to_string()
+                .contains("threads must be between 1 and 1024")
+        );
+
+        assert!(load(&["--config", "/nonexistent/ibtop.toml", "--hca", "mlx5_0"]).is_err());
+    }
+
+    #[test]
+    fn pkey_must_fit_in_16_bits() {
+        // The lock keeps other tests' IBTOP_* variables out, and the empty file stands in
+        // for any config file of the user running the tests
+        let _env = ENV_LOCK.lock().unwrap();
+        let file = config_file("pkey", "");
+        let load_pkey = |pkey| load(&["--config", &file, "--hca", "mlx5_0", "--pkey", pkey]);
+        let valid = load_pkey("65535");
+        let too_big = load_pkey("65536");
+        let _ = fs::remove_file(&file);
+
+        assert_eq!(valid.unwrap().pkey, 0xffff);
+        assert!(too_big.is_err());
+    }
+}
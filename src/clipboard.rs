@@ -0,0 +1,37 @@
+//! System clipboard access for `y`, available when built with the `clipboard` feature.
+
+use std::io;
+
+#[cfg(feature = "clipboard")]
+lazy_static::lazy_static! {
+    /// Kept open after copying: on X11 and Wayland the copied text is only available while
+    /// the clipboard that set it is alive.
+    static ref CLIPBOARD: std::sync::Mutex<Option<arboard::Clipboard>> =
+        std::sync::Mutex::new(None);
+}
+
+/// Put `text` on the system clipboard.
+#[cfg(feature = "clipboard")]
+pub fn copy(text: &str) -> io::Result<()> {
+    let mut guard = CLIPBOARD
+        .lock()
+        .map_err(|_| io::Error::other("clipboard lock poisoned"))?;
+    let clipboard = match guard.take() {
+        Some(clipboard) => clipboard,
+        None => arboard::Clipboard::new().map_err(io::Error::other)?,
+    };
+
+    guard
+        .insert(clipboard)
+        .set_text(text)
+        .map_err(io::Error::other)
+}
+
+/// Without the `clipboard` feature there is no clipboard to copy to.
+#[cfg(not(feature = "clipboard"))]
+pub fn copy(_text: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "built without clipboard support",
+    ))
+}
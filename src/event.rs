@@ -1,22 +1,43 @@
 use color_eyre::eyre::WrapErr;
-use ratatui::crossterm::event::{self, Event as CrosstermEvent};
+use ratatui::crossterm::event::{self, Event as CrosstermEvent, MouseEventKind};
 use std::{
-    sync::mpsc,
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
     thread,
     time::{Duration, Instant},
 };
 
+use tracing::{debug, error, warn};
+
 use crate::{
-    app::AppConfig,
+    app::{AppConfig, CountersQuery},
     services::{
         ibmad::{IbmadCountersService, IbmadDiscoveryService},
-        lib::{CounterEvent, DiscoveryEvent, TestCountersService, TestDiscoverService},
+        lib::{
+            CounterEvent, CountersMap, DiscoveryEvent, DiscoveryStats, LidPort, Node, RequestId,
+            TestCountersService, TestDiscoverService,
+        },
+        replay::{ReplayCountersService, ReplayDiscoveryService},
+        scope::ScopeDiscoveryService,
     },
 };
 
 /// The frequency (in Hz) at which tick events are emitted.
 const TICK_FPS: f64 = 30.0;
 
+/// Slack added to every request deadline for scheduling and processing the results.
+const DEADLINE_GRACE: Duration = Duration::from_secs(5);
+
+/// How long quitting waits for the service threads to finish their in-flight work.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Sequential MAD round trips a discovery sweep may take before it is considered hung.
+const DISCOVERY_DEADLINE_MADS: u32 = 2000;
+
 /// Main event enum that represents all possible events in the application.
 #[derive(Clone, Debug)]
 pub enum Event {
@@ -46,155 +67,541 @@ pub enum AppEvent {
 // Terminal event handler responsible for spawning and managing
 // background threads and channels for various event types.
 pub struct EventHandler {
-    _config: AppConfig,
+    config: AppConfig,
 
     sender: mpsc::Sender<Event>,
     receiver: mpsc::Receiver<Event>,
 
-    disc_tx: mpsc::Sender<DiscoveryEvent>,
-    disc_rx: mpsc::Receiver<DiscoveryEvent>,
+    /// One discovery request channel per HCA
+    disc_txs: Vec<mpsc::Sender<DiscoveryEvent>>,
+
+    /// One counters request channel per HCA, keyed by HCA name
+    ctr_txs: Vec<(String, mpsc::Sender<CounterEvent>)>,
+
+    /// The discovery request responses are being collected for, if any. Responses to any
+    /// other request arrived after it timed out and are dropped.
+    disc_request: Option<RequestId>,
+
+    /// Responses still outstanding for the last discovery request, and what arrived so far
+    disc_pending: usize,
+    disc_nodes: Vec<Node>,
+    disc_stats: DiscoveryStats,
+    disc_errors: Vec<String>,
+
+    /// The counters request responses are being collected for, if any. Responses to any
+    /// other request arrived after it timed out or was cancelled and are dropped.
+    ctr_request: Option<RequestId>,
+
+    /// Responses still outstanding for the last counters request, what arrived so far, and
+    /// whether any HCA answered without an error
+    ctr_pending: usize,
+    ctr_counters: CountersMap,
+    ctr_answered: bool,
 
-    ctr_tx: mpsc::Sender<CounterEvent>,
-    ctr_rx: mpsc::Receiver<CounterEvent>,
+    /// Shared with the ibmad counters services; bumped to cancel their request in progress
+    ctr_cancel_epoch: Arc<AtomicU64>,
 
-    wait_duration: Duration,
+    /// Discovery and counters service threads, joined by `shutdown`
+    services: Vec<thread::JoinHandle<()>>,
+
+    /// When the outstanding discovery/counters request is given up on, and the time it was
+    /// allowed. Replays pace themselves and get no deadline.
+    disc_deadline: Option<(Instant, Duration)>,
+    ctr_deadline: Option<(Instant, Duration)>,
 }
 
 impl EventHandler {
     // Constructs a new [`EventHandler`] and spawns new threads for:
     //  1) Generating tick & crossterm events
-    //  2) Discovery service
-    //  3) Counters service
+    //  2) Discovery service (one per HCA)
+    //  3) Counters service (one per HCA)
+    //
+    // When a scope file is configured, discovery always uses a single scope service while the
+    // counters service is still selected by `service_type`. Replay uses a single discovery and
+    // counters service since the recording already holds every HCA's results.
     //
     // These threads communicate with the main event loop via channels.
     pub fn new(config: AppConfig) -> Self {
-        // 1) Spawn the general event thread (tick + crossterm).
+        // 1) Spawn the general event thread (tick + crossterm), unless running headless
+        //    with `--once`, where there is no terminal to read.
         let (sender, receiver) = mpsc::channel();
-        let sender_clone = sender.clone();
-        thread::spawn(move || {
-            let actor = EventThread::new(sender_clone);
-            if let Err(e) = actor.run() {
-                eprintln!("Error in EventThread: {e}");
-            }
-        });
+        if !config.once {
+            let sender_clone = sender.clone();
+            thread::spawn(move || {
+                let actor = EventThread::new(sender_clone);
+                if let Err(e) = actor.run() {
+                    error!("Error in EventThread: {e}");
+                }
+            });
+        }
 
-        // 2) Spawn the discovery service thread.
-        let (disc_tx, ev_disc_rx) = mpsc::channel::<DiscoveryEvent>();
+        let hcas = config.hcas();
+        let replay = config.service_type == "replay";
+        let mut services = Vec::new();
+
+        // 2) Spawn the discovery service threads.
         let (disc_ev_tx, disc_rx) = mpsc::channel::<DiscoveryEvent>();
-        {
-            let config_clone = config.clone();
-            let service_type_clone = config.service_type.clone();
-            thread::spawn(move || {
+        let mut disc_txs = Vec::new();
+        let disc_hcas = if config.scope_file.is_some() || replay {
+            &hcas[..1]
+        } else {
+            &hcas[..]
+        };
+        for hca in disc_hcas {
+            let (disc_tx, ev_disc_rx) = mpsc::channel::<DiscoveryEvent>();
+            disc_txs.push(disc_tx);
+
+            let disc_ev_tx = disc_ev_tx.clone();
+            let config_clone = AppConfig {
+                hca: hca.clone(),
+                ..config.clone()
+            };
+            let service_type_clone = if config.scope_file.is_some() {
+                "scope".to_string()
+            } else {
+                config.service_type.clone()
+            };
+            services.push(thread::spawn(move || {
                 match service_type_clone.as_str() {
                     "test" => {
                         let disc_actor =
                             TestDiscoverService::new(ev_disc_rx, disc_ev_tx, config_clone);
                         let _ = disc_actor.run();
                     }
+                    "scope" => {
+                        let disc_actor =
+                            ScopeDiscoveryService::new(ev_disc_rx, disc_ev_tx, config_clone);
+                        if let Err(e) = disc_actor.run() {
+                            error!("Error in ScopeDiscoveryService: {e}");
+                        }
+                    }
+                    "replay" => {
+                        let disc_actor =
+                            ReplayDiscoveryService::new(ev_disc_rx, disc_ev_tx, config_clone);
+                        if let Err(e) = disc_actor.run() {
+                            error!("Error in ReplayDiscoveryService: {e}");
+                        }
+                    }
                     // Default
                     _ => {
                         let disc_actor =
                             IbmadDiscoveryService::new(ev_disc_rx, disc_ev_tx, config_clone);
                         if let Err(e) = disc_actor.run() {
-                            eprintln!("Error in IbmadDiscoveryService: {e}");
+                            error!("Error in IbmadDiscoveryService: {e}");
                         }
                     }
                 }
-            });
+            }));
         }
 
-        // 3) Spawn the counters service thread.
-        let (ctr_tx, ev_ctx_rx) = mpsc::channel::<CounterEvent>();
+        // 3) Spawn the counters service threads.
         let (ctr_ev_tx, ctr_rx) = mpsc::channel::<CounterEvent>();
-        {
-            let config_clone = config.clone();
+        let mut ctr_txs = Vec::new();
+        let ctr_cancel_epoch = Arc::new(AtomicU64::new(0));
+        let ctr_hcas = if replay { &hcas[..1] } else { &hcas[..] };
+        for hca in ctr_hcas {
+            let (ctr_tx, ev_ctx_rx) = mpsc::channel::<CounterEvent>();
+            ctr_txs.push((hca.clone(), ctr_tx));
+
+            let ctr_ev_tx = ctr_ev_tx.clone();
+            let config_clone = AppConfig {
+                hca: hca.clone(),
+                ..config.clone()
+            };
             let service_type_clone = config.service_type.clone();
-            thread::spawn(move || {
+            let cancel_epoch = Arc::clone(&ctr_cancel_epoch);
+            services.push(thread::spawn(move || {
                 match service_type_clone.as_str() {
                     "test" => {
                         let ctr_actor =
                             TestCountersService::new(ev_ctx_rx, ctr_ev_tx, config_clone);
                         let _ = ctr_actor.run();
                     }
-                    // Default
-                    _ => {
+                    "replay" => {
                         let ctr_actor =
-                            IbmadCountersService::new(ev_ctx_rx, ctr_ev_tx, config_clone);
+                            ReplayCountersService::new(ev_ctx_rx, ctr_ev_tx, config_clone);
                         if let Err(e) = ctr_actor.run() {
-                            eprintln!("Error in IbmadCountersService: {e}");
+                            error!("Error in ReplayCountersService: {e}");
+                        }
+                    }
+                    // Default
+                    _ => {
+                        let result = IbmadCountersService::new(
+                            ev_ctx_rx,
+                            ctr_ev_tx,
+                            config_clone,
+                            cancel_epoch,
+                        )
+                        .and_then(IbmadCountersService::run);
+                        if let Err(e) = result {
+                            error!("Error in IbmadCountersService: {e}");
                         }
                     }
                 }
-            });
+            }));
         }
 
+        // 4) Forward the services' responses into the main channel, so `next_event` waits on
+        //    a single receiver. Each forwarder ends once its services have all exited.
+        forward_events(disc_rx, sender.clone(), Event::Discover);
+        forward_events(ctr_rx, sender.clone(), Event::Counters);
+
         Self {
-            _config: config,
+            config,
             sender,
             receiver,
-            disc_tx,
-            disc_rx,
-            ctr_tx,
-            ctr_rx,
-            wait_duration: Duration::from_millis(1),
+            disc_txs,
+            ctr_txs,
+            disc_request: None,
+            disc_pending: 0,
+            disc_nodes: Vec::new(),
+            disc_stats: DiscoveryStats::default(),
+            disc_errors: Vec::new(),
+            ctr_request: None,
+            ctr_pending: 0,
+            ctr_counters: HashMap::new(),
+            ctr_answered: false,
+            ctr_cancel_epoch,
+            services,
+            disc_deadline: None,
+            ctr_deadline: None,
         }
     }
 
-    // Blocks until an event is received from any of the three channels:
-    //  - General event receiver (tick, crossterm, app)
-    //  - Discovery event receiver
-    //  - Counter event receiver
-    pub fn next(&self) -> color_eyre::Result<Event> {
+    // Blocks until the next event (tick, crossterm, app, or a forwarded service response)
+    // arrives, or until the outstanding request's deadline passes.
+    //
+    // Responses from the per-HCA services are merged, so a single discovery or counters
+    // event is returned once every HCA has answered.
+    pub fn next_event(&mut self) -> color_eyre::Result<Event> {
         loop {
-            // Prefer service events if they're already queued, without blocking.
-            if let Ok(e) = self.disc_rx.try_recv() {
-                return Ok(Event::Discover(e));
-            }
-            if let Ok(e) = self.ctr_rx.try_recv() {
-                return Ok(Event::Counters(e));
+            if let Some(e) = self.expire_requests() {
+                return Ok(e);
             }
 
-            // Block briefly on the main event channel to avoid busy-spinning.
-            match self.receiver.recv_timeout(self.wait_duration) {
-                Ok(e) => return Ok(e),
-                Err(mpsc::RecvTimeoutError::Timeout) => {
-                    // Nothing ready; loop again.
+            // Sleep until an event arrives, waking for the nearest pending deadline
+            let deadline = [self.disc_deadline, self.ctr_deadline]
+                .into_iter()
+                .flatten()
+                .map(|(deadline, _)| deadline)
+                .min();
+            let event = match deadline {
+                Some(deadline) => {
+                    match self
+                        .receiver
+                        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    {
+                        Ok(e) => e,
+                        Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                        Err(e) => return Err(e).wrap_err("event channel closed"),
+                    }
                 }
-                Err(mpsc::RecvTimeoutError::Disconnected) => {
-                    // If the main channel is disconnected, avoid a tight spin.
-                    thread::sleep(self.wait_duration);
+                None => self.receiver.recv().wrap_err("event channel closed")?,
+            };
+
+            match event {
+                Event::Discover(e) => {
+                    if let Some(e) = self.merge_discovery(e) {
+                        return Ok(Event::Discover(e));
+                    }
+                }
+                Event::Counters(e) => {
+                    if let Some(e) = self.merge_counters(e) {
+                        return Ok(Event::Counters(e));
+                    }
+                }
+                e => return Ok(e),
+            }
+        }
+    }
+
+    /// Fold one HCA's discovery response into the pending request.
+    ///
+    /// Returns the merged event once all HCAs have responded. Failed HCAs contribute no
+    /// nodes; an error is only reported if every HCA failed. With `--stream-discovery`, each
+    /// HCA's nodes are also passed on as a [`DiscoveryEvent::Partial`] until the last one.
+    fn merge_discovery(&mut self, ev: DiscoveryEvent) -> Option<DiscoveryEvent> {
+        let id = match &ev {
+            DiscoveryEvent::Response(id, ..)
+            | DiscoveryEvent::Progress { id, .. }
+            | DiscoveryEvent::Warnings(id, _)
+            | DiscoveryEvent::Error(id, _) => *id,
+            _ => return Some(ev),
+        };
+        if self.disc_request != Some(id) {
+            debug!("Dropping discovery response to stale request {id}");
+            return None;
+        }
+
+        let mut partial = None;
+        match ev {
+            DiscoveryEvent::Response(_, nodes, stats) => {
+                if self.config.stream_discovery && self.disc_pending > 1 {
+                    partial = Some(DiscoveryEvent::Partial(nodes.clone()));
                 }
+                self.disc_nodes.extend(nodes);
+                self.disc_stats += stats;
             }
+            DiscoveryEvent::Error(_, msg) => self.disc_errors.push(msg),
+            other => return Some(other),
+        }
+
+        self.disc_pending -= 1;
+        if self.disc_pending > 0 {
+            return partial;
+        }
+
+        self.disc_request = None;
+        self.disc_deadline = None;
+        if self.disc_nodes.is_empty() && !self.disc_errors.is_empty() {
+            Some(DiscoveryEvent::Error(id, self.disc_errors.join("; ")))
+        } else {
+            self.disc_errors.clear();
+            Some(DiscoveryEvent::Response(
+                id,
+                std::mem::take(&mut self.disc_nodes),
+                std::mem::take(&mut self.disc_stats),
+            ))
+        }
+    }
+
+    /// Fold one HCA's counters response into the pending request.
+    ///
+    /// Returns the merged event once all HCAs that were sent a request have responded, or an
+    /// error if none of them succeeded.
+    fn merge_counters(&mut self, ev: CounterEvent) -> Option<CounterEvent> {
+        let id = match &ev {
+            CounterEvent::Response(id, _) | CounterEvent::Error(id) => *id,
+            _ => return Some(ev),
+        };
+        if self.ctr_request != Some(id) {
+            debug!("Dropping counters response to stale request {id}");
+            return None;
+        }
+
+        if let CounterEvent::Response(_, counters) = ev {
+            self.ctr_counters.extend(counters);
+            self.ctr_answered = true;
+        }
+
+        self.ctr_pending -= 1;
+        if self.ctr_pending > 0 {
+            return None;
+        }
+
+        self.ctr_request = None;
+        self.ctr_deadline = None;
+        // Only an error when every HCA failed; otherwise show what the others returned
+        if !std::mem::take(&mut self.ctr_answered) {
+            return Some(CounterEvent::Error(id));
+        }
+        Some(CounterEvent::Response(
+            id,
+            std::mem::take(&mut self.ctr_counters),
+        ))
+    }
+
+    /// Give up on a request whose services haven't all answered by its deadline, so the UI
+    /// isn't left waiting on hung hardware. Responses that still arrive later are dropped by
+    /// `merge_discovery` and `merge_counters`.
+    fn expire_requests(&mut self) -> Option<Event> {
+        let now = Instant::now();
+
+        if let Some(id) = self.disc_request
+            && let Some((deadline, allowed)) = self.disc_deadline
+            && now >= deadline
+        {
+            self.disc_request = None;
+            self.disc_pending = 0;
+            self.disc_deadline = None;
+            self.disc_nodes.clear();
+            self.disc_errors.clear();
+            return Some(Event::Discover(DiscoveryEvent::Error(
+                id,
+                format!("no response within {}s", allowed.as_secs()),
+            )));
+        }
+
+        if self.ctr_pending > 0
+            && let Some((deadline, allowed)) = self.ctr_deadline
+            && now >= deadline
+        {
+            // Stop the services working on it, as for a cancel
+            self.ctr_cancel_epoch.fetch_add(1, Ordering::Relaxed);
+            self.ctr_request = None;
+            self.ctr_pending = 0;
+            self.ctr_deadline = None;
+            self.ctr_counters.clear();
+            return Some(Event::Counters(CounterEvent::Timeout(allowed)));
+        }
+
+        None
+    }
+
+    /// Deadline for a request that needs `mad_rounds` sequential MAD round trips, each of
+    /// which may use every retry and its backoff.
+    fn deadline(&self, mad_rounds: u32) -> Option<(Instant, Duration)> {
+        if self.config.service_type == "replay" {
+            return None;
         }
+
+        let backoff: Duration = (1..=self.config.retries)
+            .map(|retry| self.config.retry_backoff(retry))
+            .sum();
+        let per_mad =
+            Duration::from_millis(self.config.timeout as u64) * (self.config.retries + 1) + backoff;
+        let allowed = per_mad * mad_rounds + DEADLINE_GRACE;
+        Some((Instant::now() + allowed, allowed))
     }
 
     pub fn send(&mut self, app_event: AppEvent) {
         match app_event {
-            AppEvent::Discover(DiscoveryEvent::Request) => {
-                if let Err(e) = self.disc_tx.send(DiscoveryEvent::Request) {
-                    eprintln!("Failed to send discovery request: {e}");
+            AppEvent::Discover(DiscoveryEvent::Request(id)) => {
+                self.disc_request = Some(id);
+                self.disc_pending = 0;
+                self.disc_nodes.clear();
+                self.disc_stats = DiscoveryStats::default();
+                self.disc_errors.clear();
+                for disc_tx in &self.disc_txs {
+                    match disc_tx.send(DiscoveryEvent::Request(id)) {
+                        Ok(()) => self.disc_pending += 1,
+                        Err(e) => error!("Failed to send discovery request: {e}"),
+                    }
                 }
+                self.disc_deadline = self.deadline(DISCOVERY_DEADLINE_MADS);
             }
-            AppEvent::Counters(CounterEvent::Request(nodes)) => {
-                if let Err(e) = self.ctr_tx.send(CounterEvent::Request(nodes)) {
-                    eprintln!("Failed to send counters request: {e}");
+            AppEvent::Counters(CounterEvent::Request(id, nodes)) => {
+                // Route each LID to the counters service of the HCA it was discovered through,
+                // falling back to the first HCA for unknown ones.
+                let mut requests: Vec<Vec<LidPort>> = vec![Vec::new(); self.ctr_txs.len()];
+                for lp in nodes {
+                    let idx = self
+                        .ctr_txs
+                        .iter()
+                        .position(|(hca, _)| *hca == lp.hca)
+                        .unwrap_or(0);
+                    requests[idx].push(lp);
+                }
+
+                // Each HCA's queries are spread over `threads` workers. With
+                // `--counters-query auto`, a port may be sent both counters queries.
+                let queries_per_port = match self.config.counters_query {
+                    CountersQuery::Auto => 2,
+                    CountersQuery::Extended | CountersQuery::Basic => 1,
+                };
+                let mad_rounds = requests
+                    .iter()
+                    .map(|lid_ports| lid_ports.len().div_ceil(self.config.threads.max(1)))
+                    .max()
+                    .unwrap_or(0)
+                    * queries_per_port;
+                self.ctr_deadline = self.deadline(mad_rounds as u32);
+
+                self.ctr_request = Some(id);
+                self.ctr_pending = 0;
+                self.ctr_counters.clear();
+                self.ctr_answered = false;
+                for (idx, ((_, ctr_tx), lid_ports)) in self.ctr_txs.iter().zip(requests).enumerate()
+                {
+                    // Always send to the first HCA so an empty request still gets a response.
+                    if lid_ports.is_empty() && idx != 0 {
+                        continue;
+                    }
+                    match ctr_tx.send(CounterEvent::Request(id, lid_ports)) {
+                        Ok(()) => self.ctr_pending += 1,
+                        Err(e) => error!("Failed to send counters request: {e}"),
+                    }
                 }
             }
+            // Resets bypass the pending request; their response is passed on as it comes
+            AppEvent::Counters(CounterEvent::Reset(lid_ports)) => {
+                for lp in lid_ports {
+                    let (_, ctr_tx) = self
+                        .ctr_txs
+                        .iter()
+                        .find(|(hca, _)| *hca == lp.hca)
+                        .unwrap_or(&self.ctr_txs[0]);
+                    if let Err(e) = ctr_tx.send(CounterEvent::Reset(vec![lp])) {
+                        error!("Failed to send counters reset request: {e}");
+                    }
+                }
+            }
+            AppEvent::Counters(CounterEvent::Cancel) => {
+                // Services notice the new epoch between queries and drop their results; any
+                // that still answer are dropped by `merge_counters`
+                self.ctr_cancel_epoch.fetch_add(1, Ordering::Relaxed);
+                self.ctr_request = None;
+                self.ctr_pending = 0;
+                self.ctr_deadline = None;
+                self.ctr_counters.clear();
+            }
             AppEvent::Quit => {
                 // Send exit signals to all services
-                let _ = self.disc_tx.send(DiscoveryEvent::Exit);
-                let _ = self.ctr_tx.send(CounterEvent::Exit);
+                self.send_exit();
                 if let Err(e) = self.sender.send(Event::App(app_event)) {
-                    eprintln!("Failed to send quit event: {e}");
+                    error!("Failed to send quit event: {e}");
                 }
             }
             _ => {
                 if let Err(e) = self.sender.send(Event::App(app_event)) {
-                    eprintln!("Failed to send app event: {e}");
+                    error!("Failed to send app event: {e}");
                 }
             }
         }
     }
+
+    /// Ask every service thread to exit and wait up to `timeout` for them, so in-flight
+    /// queries are cut short and MAD ports are closed before the process exits. Threads
+    /// still blocked in a query after that are left behind.
+    pub fn shutdown(&mut self, timeout: Duration) {
+        self.ctr_cancel_epoch.fetch_add(1, Ordering::Relaxed);
+        self.send_exit();
+
+        let deadline = Instant::now() + timeout;
+        while self.services.iter().any(|h| !h.is_finished()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut stuck = 0;
+        for handle in self.services.drain(..) {
+            if handle.is_finished() {
+                let _ = handle.join();
+            } else {
+                stuck += 1;
+            }
+        }
+        if stuck > 0 {
+            warn!(
+                "{stuck} service threads still busy after {}s, exiting anyway",
+                timeout.as_secs()
+            );
+        }
+    }
+
+    /// Ask every service thread to exit.
+    fn send_exit(&self) {
+        for disc_tx in &self.disc_txs {
+            let _ = disc_tx.send(DiscoveryEvent::Exit);
+        }
+        for (_, ctr_tx) in &self.ctr_txs {
+            let _ = ctr_tx.send(CounterEvent::Exit);
+        }
+    }
+}
+
+/// Pass every event from `rx` on to the main channel, wrapped with `wrap`.
+fn forward_events<T: Send + 'static>(
+    rx: mpsc::Receiver<T>,
+    sender: mpsc::Sender<Event>,
+    wrap: fn(T) -> Event,
+) {
+    thread::spawn(move || {
+        for ev in rx {
+            if sender.send(wrap(ev)).is_err() {
+                break;
+            }
+        }
+    });
 }
 
 // A thread that handles reading crossterm events and emitting tick events on a regular schedule.
@@ -224,7 +631,11 @@ impl EventThread {
                 let remaining = tick_interval.saturating_sub(elapsed);
                 if event::poll(remaining).wrap_err("failed to poll for crossterm events")? {
                     let ev = event::read().wrap_err("failed to read crossterm event")?;
-                    self.send(Event::Crossterm(ev));
+                    // Mouse capture reports every pointer motion; drop those rather than
+                    // redrawing for each one.
+                    if !matches!(ev, CrosstermEvent::Mouse(m) if m.kind == MouseEventKind::Moved) {
+                        self.send(Event::Crossterm(ev));
+                    }
                 }
             }
         }
@@ -239,7 +650,243 @@ impl EventThread {
 impl Drop for EventHandler {
     fn drop(&mut self) {
         // Send exit signals to all services when EventHandler is dropped
-        let _ = self.disc_tx.send(DiscoveryEvent::Exit);
-        let _ = self.ctr_tx.send(CounterEvent::Exit);
+        self.send_exit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An event handler with no service threads; returns the receiving end of each HCA's
+    /// counters channel in their place.
+    fn handler(hcas: &[&str]) -> (EventHandler, Vec<mpsc::Receiver<CounterEvent>>) {
+        let (sender, receiver) = mpsc::channel();
+        let (ctr_txs, ctr_rxs) = hcas
+            .iter()
+            .map(|hca| {
+                let (tx, rx) = mpsc::channel();
+                ((hca.to_string(), tx), rx)
+            })
+            .unzip();
+        let handler = EventHandler {
+            config: AppConfig::default(),
+            sender,
+            receiver,
+            disc_txs: Vec::new(),
+            ctr_txs,
+            disc_request: None,
+            disc_pending: 0,
+            disc_nodes: Vec::new(),
+            disc_stats: DiscoveryStats::default(),
+            disc_errors: Vec::new(),
+            ctr_request: None,
+            ctr_pending: 0,
+            ctr_counters: HashMap::new(),
+            ctr_answered: false,
+            ctr_cancel_epoch: Arc::new(AtomicU64::new(0)),
+            services: Vec::new(),
+            disc_deadline: None,
+            ctr_deadline: None,
+        };
+        (handler, ctr_rxs)
+    }
+
+    fn lid_port(hca: &str, lid: u16) -> LidPort {
+        LidPort {
+            lid,
+            number: 1,
+            hca: hca.to_string(),
+        }
+    }
+
+    fn response(id: RequestId, hca: &str, lid: u16, rcv_bytes: u64) -> CounterEvent {
+        let counters = HashMap::from([("rcv_bytes".to_string(), rcv_bytes)]);
+        CounterEvent::Response(id, HashMap::from([((hca.to_string(), lid, 1), counters)]))
+    }
+
+    fn request(handler: &mut EventHandler, id: RequestId, lid_ports: Vec<LidPort>) {
+        handler.send(AppEvent::Counters(CounterEvent::Request(id, lid_ports)));
+    }
+
+    #[test]
+    fn same_lid_on_two_hcas_is_kept_apart() {
+        let (mut handler, _rxs) = handler(&["mlx5_0", "mlx5_1"]);
+        request(
+            &mut handler,
+            1,
+            vec![lid_port("mlx5_0", 5), lid_port("mlx5_1", 5)],
+        );
+
+        assert!(
+            handler
+                .merge_counters(response(1, "mlx5_0", 5, 10))
+                .is_none()
+        );
+        let Some(CounterEvent::Response(1, counters)) =
+            handler.merge_counters(response(1, "mlx5_1", 5, 20))
+        else {
+            panic!("expected a merged response");
+        };
+        assert_eq!(counters[&("mlx5_0".to_string(), 5, 1)]["rcv_bytes"], 10);
+        assert_eq!(counters[&("mlx5_1".to_string(), 5, 1)]["rcv_bytes"], 20);
+    }
+
+    #[test]
+    fn error_only_when_every_hca_failed() {
+        let (mut handler, _rxs) = handler(&["mlx5_0", "mlx5_1"]);
+        let both = vec![lid_port("mlx5_0", 5), lid_port("mlx5_1", 6)];
+
+        request(&mut handler, 1, both.clone());
+        assert!(handler.merge_counters(CounterEvent::Error(1)).is_none());
+        assert!(matches!(
+            handler.merge_counters(response(1, "mlx5_1", 6, 0)),
+            Some(CounterEvent::Response(1, counters)) if counters.len() == 1
+        ));
+
+        request(&mut handler, 2, both);
+        assert!(handler.merge_counters(CounterEvent::Error(2)).is_none());
+        assert!(matches!(
+            handler.merge_counters(CounterEvent::Error(2)),
+            Some(CounterEvent::Error(2))
+        ));
+    }
+
+    #[test]
+    fn late_response_after_timeout_is_dropped() {
+        let (mut handler, _rxs) = handler(&["mlx5_0"]);
+        request(&mut handler, 1, vec![lid_port("mlx5_0", 5)]);
+        handler.ctr_deadline = Some((Instant::now(), Duration::from_secs(1)));
+        assert!(matches!(
+            handler.expire_requests(),
+            Some(Event::Counters(CounterEvent::Timeout(_)))
+        ));
+        assert_eq!(handler.ctr_cancel_epoch.load(Ordering::Relaxed), 1);
+
+        // Arriving on its own, or while the next request is outstanding
+        assert!(
+            handler
+                .merge_counters(response(1, "mlx5_0", 5, 10))
+                .is_none()
+        );
+        request(&mut handler, 2, vec![lid_port("mlx5_0", 5)]);
+        assert!(
+            handler
+                .merge_counters(response(1, "mlx5_0", 5, 10))
+                .is_none()
+        );
+        assert_eq!(handler.ctr_pending, 1);
+        assert!(matches!(
+            handler.merge_counters(response(2, "mlx5_0", 5, 20)),
+            Some(CounterEvent::Response(2, _))
+        ));
+    }
+
+    #[test]
+    fn response_to_cancelled_request_is_dropped() {
+        let (mut handler, rxs) = handler(&["mlx5_0"]);
+        request(&mut handler, 1, vec![lid_port("mlx5_0", 5)]);
+        handler.send(AppEvent::Counters(CounterEvent::Cancel));
+        assert_eq!(handler.ctr_cancel_epoch.load(Ordering::Relaxed), 1);
+
+        // Services that don't watch the cancel epoch still answer
+        assert!(matches!(rxs[0].try_recv(), Ok(CounterEvent::Request(1, _))));
+        assert!(
+            handler
+                .merge_counters(response(1, "mlx5_0", 5, 10))
+                .is_none()
+        );
+        assert!(handler.merge_counters(CounterEvent::Error(1)).is_none());
+    }
+
+    #[test]
+    fn auto_counters_query_allows_two_queries_per_port() {
+        let allowed = |counters_query| {
+            let (mut handler, _rxs) = handler(&["mlx5_0"]);
+            handler.config.counters_query = counters_query;
+            handler.config.timeout = 100;
+            request(&mut handler, 1, vec![lid_port("mlx5_0", 5)]);
+            handler.ctr_deadline.expect("deadline").1
+        };
+        assert_eq!(
+            allowed(CountersQuery::Auto) - DEADLINE_GRACE,
+            (allowed(CountersQuery::Basic) - DEADLINE_GRACE) * 2
+        );
+    }
+
+    fn discovery_request(handler: &mut EventHandler, id: RequestId) {
+        handler.send(AppEvent::Discover(DiscoveryEvent::Request(id)));
+    }
+
+    fn discovery_response(id: RequestId, lid: u16) -> DiscoveryEvent {
+        let node = Node {
+            guid: lid as u64,
+            node_description: format!("switch-{lid}"),
+            ports: Vec::new(),
+            lid,
+            hca: "mlx5_0".to_string(),
+            node_type: Default::default(),
+        };
+        DiscoveryEvent::Response(id, vec![node], DiscoveryStats::default())
+    }
+
+    #[test]
+    fn late_discovery_response_after_timeout_is_dropped() {
+        let (mut handler, _rxs) = handler(&["mlx5_0"]);
+        let (disc_tx, _disc_rx) = mpsc::channel();
+        handler.disc_txs.push(disc_tx);
+
+        discovery_request(&mut handler, 1);
+        handler.disc_deadline = Some((Instant::now(), Duration::from_secs(1)));
+        assert!(matches!(
+            handler.expire_requests(),
+            Some(Event::Discover(DiscoveryEvent::Error(1, _)))
+        ));
+
+        // Arriving on its own, or while the next request is outstanding
+        assert!(handler.merge_discovery(discovery_response(1, 5)).is_none());
+        discovery_request(&mut handler, 2);
+        assert!(handler.merge_discovery(discovery_response(1, 5)).is_none());
+        assert!(
+            handler
+                .merge_discovery(DiscoveryEvent::Progress {
+                    id: 1,
+                    mads_sent: 10,
+                    nodes_found: 1,
+                })
+                .is_none()
+        );
+        assert_eq!(handler.disc_pending, 1);
+        assert!(matches!(
+            handler.merge_discovery(discovery_response(2, 6)),
+            Some(DiscoveryEvent::Response(2, nodes, _)) if nodes[0].lid == 6
+        ));
+        assert!(handler.disc_deadline.is_none());
+    }
+
+    #[test]
+    fn forwarded_responses_and_ticks_arrive_in_order() {
+        let (mut handler, _rxs) = handler(&["mlx5_0"]);
+        let (disc_tx, disc_rx) = mpsc::channel();
+        forward_events(disc_rx, handler.sender.clone(), Event::Discover);
+        let (req_tx, _req_rx) = mpsc::channel();
+        handler.disc_txs.push(req_tx);
+        discovery_request(&mut handler, 1);
+
+        handler.sender.send(Event::Tick).unwrap();
+        disc_tx
+            .send(DiscoveryEvent::Response(
+                1,
+                Vec::new(),
+                DiscoveryStats::default(),
+            ))
+            .unwrap();
+        assert!(matches!(handler.next_event().unwrap(), Event::Tick));
+        assert!(matches!(
+            handler.next_event().unwrap(),
+            Event::Discover(DiscoveryEvent::Response(..))
+        ));
+        handler.sender.send(Event::Tick).unwrap();
+        assert!(matches!(handler.next_event().unwrap(), Event::Tick));
     }
 }
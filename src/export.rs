@@ -0,0 +1,344 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::{
+    app::{CounterMode, MainNodeInfo},
+    services::{
+        ibmad::ERROR_COUNTERS,
+        lib::{CountersMap, Node},
+    },
+    ui::helpers::{count_errors, format_guid, get_bw, get_bw_loss, group_thousands},
+};
+
+/// A single row of the main node table as written to a JSON export.
+#[derive(Debug, Serialize)]
+struct NodeRecord<'a> {
+    guid: String,
+    lid: u16,
+    node_description: &'a str,
+    ports: u16,
+    recv_bw: f64,
+    xmt_bw: f64,
+    bw_loss: f64,
+    error_count: u128,
+    errors: &'a str,
+    hca: &'a str,
+    node_type: &'static str,
+}
+
+/// Build `<dir>/<prefix>-<timestamp>.<ext>`, defaulting `dir` to the current directory.
+pub fn timestamped_path(dir: Option<&str>, prefix: &str, ext: &str) -> PathBuf {
+    let dir = dir.map(Path::new).unwrap_or_else(|| Path::new("."));
+    let ts = Utc::now().format("%Y%m%dT%H%M%SZ");
+    dir.join(format!("{prefix}-{ts}.{ext}"))
+}
+
+/// Write the given main table rows to a timestamped JSON file and return its path.
+pub fn export_nodes_json(dir: Option<&str>, rows: &[MainNodeInfo]) -> io::Result<PathBuf> {
+    let path = timestamped_path(dir, "ibtop-nodes", "json");
+    let mut writer = BufWriter::new(File::create(&path)?);
+    write_nodes_json(&mut writer, rows)?;
+    writer.flush()?;
+
+    Ok(path)
+}
+
+/// Write the given main table rows as a pretty-printed JSON array.
+pub fn write_nodes_json(writer: &mut impl Write, rows: &[MainNodeInfo]) -> io::Result<()> {
+    let records: Vec<NodeRecord> = rows
+        .iter()
+        .map(|row| NodeRecord {
+            guid: format_guid(row.guid),
+            lid: row.lid,
+            node_description: &row.name,
+            ports: row.ports,
+            recv_bw: row.recv_bw,
+            xmt_bw: row.send_bw,
+            bw_loss: row.bw_loss,
+            error_count: row.error_count,
+            errors: &row.error_strings,
+            hca: &row.hca,
+            node_type: row.node_type.name(),
+        })
+        .collect();
+
+    serde_json::to_writer_pretty(&mut *writer, &records)?;
+    writer.write_all(b"\n")
+}
+
+/// Write the given main table rows as an aligned plain-text table, formatting bandwidths
+/// with `format_bw`.
+pub fn write_nodes_text(
+    writer: &mut impl Write,
+    rows: &[MainNodeInfo],
+    format_bw: impl Fn(f64) -> String,
+) -> io::Result<()> {
+    const HEADER: [&str; 8] = [
+        "LID", "NODE", "PT", "RECV_BW", "SEND_BW", "BW_LOSS", "ERR_CNT", "ERR_STR",
+    ];
+
+    let lines: Vec<[String; 8]> = rows
+        .iter()
+        .map(|row| {
+            [
+                row.lid.to_string(),
+                row.name.clone(),
+                row.ports.to_string(),
+                format_bw(row.recv_bw),
+                format_bw(row.send_bw),
+                format_bw(row.bw_loss),
+                group_thousands(row.error_count),
+                row.error_strings.clone(),
+            ]
+        })
+        .collect();
+
+    let mut widths = HEADER.map(str::len);
+    for line in &lines {
+        for (width, field) in widths.iter_mut().zip(line) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+
+    let header = HEADER.map(String::from);
+    for line in std::iter::once(&header).chain(&lines) {
+        let fields: Vec<String> = line
+            .iter()
+            .zip(widths)
+            .map(|(field, width)| format!("{field:<width$}"))
+            .collect();
+        writeln!(writer, "{}", fields.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+/// Format a main table row as a labelled text block for pasting into tickets, formatting
+/// bandwidths with `format_bw`.
+pub fn format_node_summary(row: &MainNodeInfo, format_bw: impl Fn(f64) -> String) -> String {
+    let fields = [
+        ("GUID", format_guid(row.guid)),
+        ("LID", row.lid.to_string()),
+        ("Node", row.name.clone()),
+        ("Type", row.node_type.name().to_string()),
+        ("HCA", row.hca.clone()),
+        ("Ports", row.ports.to_string()),
+        ("Recv BW", format_bw(row.recv_bw)),
+        ("Send BW", format_bw(row.send_bw)),
+        ("BW Loss", format_bw(row.bw_loss)),
+        ("Errors", group_thousands(row.error_count)),
+        ("Error Counters", row.error_strings.clone()),
+    ];
+
+    fields
+        .iter()
+        .map(|(label, value)| format!("{:<16}{value}\n", format!("{label}:")))
+        .collect()
+}
+
+/// Quote a CSV field when it contains a delimiter, quote or newline.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write the per-port counters displayed in the details popup for `node` to a CSV file.
+///
+/// Only ports with an entry in `counters` are written, sorted by port number, using the
+/// same formatting as the popup table plus one column per error counter.
+pub fn export_ports_csv(
+    dir: Option<&str>,
+    node: &Node,
+    counters: &CountersMap,
+    counter_mode: &CounterMode,
+    error_counters: &[String],
+) -> io::Result<PathBuf> {
+    let path = timestamped_path(
+        dir,
+        &format!("ibtop-ports-{}", format_guid(node.guid)),
+        "csv",
+    );
+    let mut writer = BufWriter::new(File::create(&path)?);
+
+    let mut header = vec![
+        "port",
+        "remote_description",
+        "recv_bw",
+        "send_bw",
+        "bw_loss",
+        "error_count",
+    ];
+    header.extend(ERROR_COUNTERS);
+    writeln!(writer, "{}", header.join(","))?;
+
+    let mut ports: Vec<_> = node.ports.iter().collect();
+    ports.sort_by_key(|p| p.number);
+
+    for port in ports {
+        let Some(ctrs) = counters.get(&node.counter_key(port.number)) else {
+            continue;
+        };
+
+        let mut fields = vec![
+            port.number.to_string(),
+            csv_field(&port.remote_node_description),
+            format!("{:.2}", get_bw(ctrs, "rcv_bytes", counter_mode)),
+            format!("{:.2}", get_bw(ctrs, "xmt_bytes", counter_mode)),
+            format!("{:.2}", get_bw_loss(ctrs, "xmit_waits", counter_mode)),
+            count_errors(ctrs, error_counters).to_string(),
+        ];
+        fields.extend(
+            ERROR_COUNTERS
+                .iter()
+                .map(|&name| ctrs.get(name).copied().unwrap_or(0).to_string()),
+        );
+        writeln!(writer, "{}", fields.join(","))?;
+    }
+
+    writer.flush()?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::lib::{NodeType, Port};
+    use std::collections::HashMap;
+
+    fn row() -> MainNodeInfo {
+        MainNodeInfo {
+            guid: 0x0002c90300a1b2c3,
+            lid: 12,
+            name: "leaf-01".into(),
+            ports: 36,
+            recv_bw: 1.5,
+            send_bw: 2.5,
+            bw_loss: 0.25,
+            error_count: 3,
+            error_strings: "symbol_errors".into(),
+            hca: "mlx5_0".into(),
+            line_rate: Some(400.0),
+            metric: 0.0,
+            node_type: NodeType::Switch,
+        }
+    }
+
+    #[test]
+    fn json_export_holds_each_row() {
+        let dir = std::env::temp_dir().join(format!("ibtop-{}-nodes-json", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = export_nodes_json(dir.to_str(), &[row()]).unwrap();
+        let out = std::fs::read(&path);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let json: serde_json::Value = serde_json::from_slice(&out.unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "guid": "0x0002c90300a1b2c3",
+                "lid": 12,
+                "node_description": "leaf-01",
+                "ports": 36,
+                "recv_bw": 1.5,
+                "xmt_bw": 2.5,
+                "bw_loss": 0.25,
+                "error_count": 3,
+                "errors": "symbol_errors",
+                "hca": "mlx5_0",
+                "node_type": "Switch",
+            }])
+        );
+    }
+
+    #[test]
+    fn text_table_aligns_columns() {
+        let mut second = row();
+        second.lid = 7;
+        second.name = "spine-with-a-long-name".into();
+        second.error_count = 12_345;
+        second.error_strings = String::new();
+
+        let mut out = Vec::new();
+        write_nodes_text(&mut out, &[row(), second], |gbps| format!("{gbps:.1}")).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "LID  NODE                    PT  RECV_BW  SEND_BW  BW_LOSS  ERR_CNT  ERR_STR\n\
+             12   leaf-01                 36  1.5      2.5      0.2      3        symbol_errors\n\
+             7    spine-with-a-long-name  36  1.5      2.5      0.2      12,345\n"
+        );
+    }
+
+    #[test]
+    fn ports_csv_lists_ports_with_counters_in_order() {
+        let port = |number, remote: &str| Port {
+            number,
+            remote_node_description: remote.into(),
+            remote_guid: None,
+            remote_lid: None,
+            link_state: "Active".into(),
+            phys_state: "LinkUp".into(),
+            link_width: None,
+            link_speed: None,
+        };
+        let node = Node {
+            guid: 0x10,
+            node_description: "leaf-01".into(),
+            ports: vec![
+                port(3, "spine, \"A\""),
+                port(1, "host-1"),
+                port(2, "unqueried"),
+            ],
+            lid: 12,
+            hca: "mlx5_0".into(),
+            node_type: NodeType::Switch,
+        };
+        let ctrs = |rcv_bytes, symbol_errors| {
+            HashMap::from([
+                ("rcv_bytes".to_string(), rcv_bytes),
+                ("symbol_errors".to_string(), symbol_errors),
+            ])
+        };
+        let counters = HashMap::from([
+            (node.counter_key(1), ctrs(250_000_000, 0)),
+            (node.counter_key(3), ctrs(0, 7)),
+        ]);
+
+        let dir = std::env::temp_dir().join(format!("ibtop-{}-ports-csv", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = export_ports_csv(
+            dir.to_str(),
+            &node,
+            &counters,
+            &CounterMode::Whole,
+            &["symbol_errors".to_string()],
+        )
+        .unwrap();
+        let csv = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let csv = csv.unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines[0].starts_with("port,remote_description,recv_bw,send_bw,bw_loss,error_count,")
+        );
+        assert!(lines[1].starts_with("1,host-1,8.00,0.00,0.00,0,"));
+        assert!(lines[2].starts_with("3,\"spine, \"\"A\"\"\",0.00,0.00,0.00,7,"));
+
+        // The per-counter columns come last; the quoted description holds a comma, so count
+        // from the end
+        let header: Vec<&str> = lines[0].split(',').collect();
+        let from_end = header.len() - header.iter().position(|&h| h == "symbol_errors").unwrap();
+        let symbol_errors = |line: &str| line.rsplit(',').nth(from_end - 1).unwrap().to_string();
+        assert_eq!(symbol_errors(lines[1]), "0");
+        assert_eq!(symbol_errors(lines[2]), "7");
+    }
+}
@@ -1,9 +1,16 @@
-use std::path::PathBuf;
+use std::{collections::VecDeque, fmt, path::PathBuf, sync::Mutex};
 
+use chrono::Local;
 use color_eyre::eyre::Result;
 use lazy_static::lazy_static;
+use tracing::{
+  Event, Level, Subscriber,
+  field::{Field, Visit},
+};
 use tracing_error::ErrorLayer;
-use tracing_subscriber::{self, layer::SubscriberExt, util::SubscriberInitExt, Layer};
+use tracing_subscriber::{
+  self, Layer, filter::LevelFilter, layer::Context, layer::SubscriberExt, util::SubscriberInitExt,
+};
 
 lazy_static! {
   pub static ref PROJECT_NAME: String = env!("CARGO_CRATE_NAME").to_uppercase().to_string();
@@ -11,8 +18,13 @@ lazy_static! {
     std::env::var(format!("{}_DATA", PROJECT_NAME.clone())).ok().map(PathBuf::from);
   pub static ref LOG_ENV: String = format!("{}_LOGLEVEL", PROJECT_NAME.clone());
   pub static ref LOG_FILE: String = format!("{}.log", env!("CARGO_PKG_NAME"));
+  /// Recent status lines, warnings and errors, oldest first, shown in the log popup (`l`)
+  pub static ref LOG_LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
 }
 
+/// Number of messages kept in `LOG_LINES`.
+const LOG_LINES_MAX: usize = 500;
+
 pub fn get_data_dir() -> PathBuf {
   let directory = if let Some(s) = DATA_FOLDER.clone() {
     s
@@ -22,25 +34,85 @@ pub fn get_data_dir() -> PathBuf {
   directory
 }
 
-pub fn initialize_logging() -> Result<()> {
-  let directory = get_data_dir();
-  std::fs::create_dir_all(directory.clone())?;
-  let log_path = directory.join(LOG_FILE.clone());
-  let log_file = std::fs::File::create(log_path)?;
-  let log_filter = std::env::var("RUST_LOG")
-    .or_else(|_| std::env::var(LOG_ENV.clone()))
-    .unwrap_or_else(|_| format!("{}=info", env!("CARGO_CRATE_NAME")));
-  let file_subscriber = tracing_subscriber::fmt::layer()
-    .with_file(true)
-    .with_line_number(true)
-    .with_writer(log_file)
-    .with_target(false)
-    .with_ansi(false)
-    .with_filter(tracing_subscriber::filter::EnvFilter::builder().parse_lossy(log_filter));
-  tracing_subscriber::registry().with(file_subscriber).with(ErrorLayer::default()).init();
+/// Log file written with `--tracing`: `ibtop.log` in the data directory.
+pub fn default_log_path() -> PathBuf {
+  get_data_dir().join(LOG_FILE.clone())
+}
+
+/// Collect warnings and errors into `LOG_LINES`, and also write everything allowed by
+/// `RUST_LOG` to `log_path` when given.
+pub fn initialize_logging(log_path: Option<PathBuf>) -> Result<()> {
+  let file_subscriber = match log_path {
+    Some(log_path) => {
+      if let Some(directory) = log_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(directory)?;
+      }
+      let log_file = std::fs::File::create(log_path)?;
+      let log_filter = std::env::var("RUST_LOG")
+        .or_else(|_| std::env::var(LOG_ENV.clone()))
+        .unwrap_or_else(|_| format!("{}=info", env!("CARGO_CRATE_NAME")));
+      Some(
+        tracing_subscriber::fmt::layer()
+          .with_file(true)
+          .with_line_number(true)
+          .with_writer(log_file)
+          .with_target(false)
+          .with_ansi(false)
+          .with_filter(tracing_subscriber::filter::EnvFilter::builder().parse_lossy(log_filter)),
+      )
+    }
+    None => None,
+  };
+  tracing_subscriber::registry()
+    .with(file_subscriber)
+    .with(LogLinesLayer.with_filter(LevelFilter::WARN))
+    .with(ErrorLayer::default())
+    .init();
   Ok(())
 }
 
+/// Appends each event it sees to `LOG_LINES`.
+struct LogLinesLayer;
+
+impl<S: Subscriber> Layer<S> for LogLinesLayer {
+  fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+    let mut visitor = MessageVisitor(String::new());
+    event.record(&mut visitor);
+    let level: &Level = event.metadata().level();
+    push_log_line(level, &visitor.0);
+  }
+}
+
+/// Append a timestamped message to `LOG_LINES`, dropping the oldest beyond `LOG_LINES_MAX`.
+pub fn push_log_line(level: impl fmt::Display, message: &str) {
+  let line = format!("{} {level:>5} {message}", Local::now().format("%H:%M:%S"));
+
+  if let Ok(mut lines) = LOG_LINES.lock() {
+    if lines.len() >= LOG_LINES_MAX {
+      lines.pop_front();
+    }
+    lines.push_back(line);
+  }
+}
+
+/// Formats an event's message followed by its other fields as `name=value`.
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+  fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+    use std::fmt::Write;
+
+    if !self.0.is_empty() {
+      self.0.push(' ');
+    }
+    let _ = if field.name() == "message" {
+      write!(self.0, "{value:?}")
+    } else {
+      write!(self.0, "{}={value:?}", field.name())
+    };
+  }
+}
+
 /// Similar to the `std::dbg!` macro, but generates `tracing` events rather
 /// than printing to stdout.
 ///
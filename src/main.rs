@@ -1,72 +1,238 @@
-use clap::Parser;
-use std::panic::{AssertUnwindSafe, catch_unwind};
+use clap::{CommandFactory, FromArgMatches, Parser, builder::PossibleValuesParser};
+use color_eyre::{Section, eyre::eyre};
+use ratatui::crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute,
+};
+use std::{
+    io::{self, IsTerminal},
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::PathBuf,
+    process::ExitCode,
+};
 
-use crate::app::App;
+use crate::{
+    app::{App, AppConfig, CountersQuery, METRIC_COUNTERS, MainColumn, OutputFormat, RateUnits},
+    services::ibmad::ERROR_COUNTERS,
+};
 
 pub mod app;
+pub mod clipboard;
 pub mod event;
+pub mod export;
 pub mod logging;
+pub mod names;
+pub mod prometheus;
+pub mod record;
 pub mod scope;
 pub mod services;
+pub mod state;
 pub mod ui;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
+    /// HCA to query through, or a comma separated list to monitor several (e.g. mlx5_0,mlx5_1).
+    /// Required here, in the config file or as IBTOP_HCA.
     #[arg(long)]
-    pub hca: String,
+    pub hca: Option<String>,
 
+    /// Config file (TOML, YAML or JSON, by extension) with defaults for these options.
+    /// Defaults to $XDG_CONFIG_HOME/ibtop/config.toml when present.
+    #[arg(long)]
+    pub config: Option<String>,
+
+    /// Partition key used for counter queries (0-0xffff); LIDs that are not members of the
+    /// partition fail their queries and are flagged as such. 0 uses the default partition
+    /// (all LIDs).
     #[arg(long, default_value_t = 0)]
-    pub pkey: u32,
+    pub pkey: u16,
 
+    /// Counter query workers per HCA (1-1024)
     #[arg(long, default_value_t = 16)]
     pub threads: usize,
 
+    /// Service backend: ibmad, test, or replay (with --replay-file)
     #[arg(long, default_value = "ibmad")]
     pub service_type: String,
 
+    /// Seconds between automatic counter updates (1-3600)
     #[arg(long, default_value_t = 2)]
     pub update_interval: usize,
 
+    /// MAD timeout in milliseconds (1-60000)
     #[arg(long, default_value_t = 250)]
     pub timeout: u32,
 
+    /// Retries of a timed out MAD (0-20)
     #[arg(long, default_value_t = 2)]
     pub retries: u32,
 
+    /// Delay before the first retry of a counters query, doubled for each further retry.
+    /// 0 retries immediately.
+    #[arg(long, default_value_t = 0)]
+    pub retry_backoff_ms: u64,
+
     #[arg(long, default_value_t = false)]
     pub include_hcas: bool,
 
+    /// Don't query counters of ports whose link is down; the details popup still lists them
+    #[arg(long, default_value_t = false)]
+    pub skip_down_ports: bool,
+
+    /// Counters query: `extended` (64-bit PortCountersExtended), `basic` (32-bit PortCounters),
+    /// or `auto` to fall back to `basic` for nodes that reject `extended`
+    #[arg(long, value_enum, default_value_t = CountersQuery::Auto)]
+    pub counters_query: CountersQuery,
+
+    /// Down at the last port of the details popup moves to the first, and Up at the first
+    /// moves to the last
+    #[arg(long, default_value_t = false)]
+    pub wrap_around: bool,
+
     #[arg(long)]
     pub scope_file: Option<String>,
 
+    /// File mapping node GUIDs to friendly names (`<guid> "<name>"` per line), shown instead
+    /// of the node descriptions
+    #[arg(long)]
+    pub name_map: Option<String>,
+
+    /// Per-port line rate in Gbps used for the utilization column instead of the
+    /// negotiated link width × speed
+    #[arg(long)]
+    pub line_rate_gbps: Option<f64>,
+
+    /// Highlight rows whose error count (in the current counter mode) exceeds this value
+    #[arg(long)]
+    pub error_threshold: Option<u64>,
+
+    /// Raise an alert when a node's recv or send bandwidth (in Gbps) crosses this value
+    #[arg(long)]
+    pub bw_alert_gbps: Option<f64>,
+
+    /// Number of counter samples kept per node for the bandwidth trend column and chart
+    #[arg(long, default_value_t = 30)]
+    pub history_len: usize,
+
+    /// Append every discovery and counters response to this newline-delimited JSON file
+    #[arg(long)]
+    pub record: Option<String>,
+
+    /// Also write every counters update to this file as JSON lines, one per LID/port with
+    /// its node, e.g. a FIFO read by `jq`. Not stdout, which the interface draws on.
+    #[arg(long)]
+    pub json_stream: Option<String>,
+
+    /// Recording to play back with `--service-type replay`
+    #[arg(long)]
+    pub replay_file: Option<String>,
+
+    /// Replay counter samples as fast as they are requested instead of at the recorded cadence
+    #[arg(long, default_value_t = false)]
+    pub replay_fast: bool,
+
+    /// When monitoring several HCAs, show each HCA's nodes as soon as its discovery finishes
+    /// instead of waiting for all of them
+    #[arg(long, default_value_t = false)]
+    pub stream_discovery: bool,
+
+    /// Show bandwidth as bit rates (Gbps) or byte rates (GB/s)
+    #[arg(long, value_enum, default_value_t = RateUnits::Bits)]
+    pub units: RateUnits,
+
+    /// Error counters summed into ERR_CNT, e.g. `symbol_errors,link_downed` (defaults to all)
+    #[arg(long, value_delimiter = ',', value_parser = PossibleValuesParser::new(ERROR_COUNTERS))]
+    pub error_counters: Option<Vec<String>>,
+
+    /// Error counters listed in ERR_STR when non-zero (defaults to all)
+    #[arg(long, value_delimiter = ',', value_parser = PossibleValuesParser::new(ERROR_COUNTERS))]
+    pub error_string_counters: Option<Vec<String>>,
+
+    /// Extra counters shown as columns in the details popup, e.g. `xmit_discards,vl15dropped`
+    #[arg(long, value_delimiter = ',', value_parser = PossibleValuesParser::new(METRIC_COUNTERS))]
+    pub extra_counters: Option<Vec<String>>,
+
+    /// Main table columns to show, e.g. `lid,node,recv_bw,send_bw` (defaults to all)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub columns: Option<Vec<MainColumn>>,
+
+    /// Color theme: `dark` (default), `light`, or the path of a TOML theme file
+    #[arg(long)]
+    pub theme: Option<String>,
+
+    /// Directory for exported files (defaults to the current directory)
+    #[arg(long)]
+    pub export_path: Option<String>,
+
+    /// Don't restore or save view settings (sort, filter, counter mode, auto-update, columns)
+    #[arg(long, default_value_t = false)]
+    pub no_persist: bool,
+
+    /// Serve the latest counters as Prometheus metrics on http://0.0.0.0:<PORT>/metrics while
+    /// the interface runs; starts with auto-update on
+    #[arg(long)]
+    pub prometheus_port: Option<u16>,
+
+    /// Discover the fabric, query counters once, print the node table to stdout and exit.
+    /// Exits with 2 when a node's error count exceeds --error-threshold.
+    #[arg(long, default_value_t = false)]
+    pub once: bool,
+
+    /// Output format for --once
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
     #[arg(long, default_value_t = false)]
     pub verbose: bool,
 
+    /// Write a log to ibtop.log in $IBTOP_DATA (or the current directory)
     #[arg(long, default_value_t = false)]
     pub tracing: bool,
+
+    /// Write the log to this file instead; implies --tracing
+    #[arg(long)]
+    pub log_file: Option<String>,
 }
 
-fn main() -> color_eyre::Result<()> {
-    let args = Args::parse();
-    if args.tracing {
-        logging::initialize_logging()?;
+fn main() -> color_eyre::Result<ExitCode> {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches)?;
+    let log_path = args
+        .log_file
+        .clone()
+        .map(PathBuf::from)
+        .or_else(|| args.tracing.then(logging::default_log_path));
+    logging::initialize_logging(log_path)?;
+    color_eyre::install()?;
+    let config = AppConfig::load(args, &matches)?;
+    if config.once {
+        return App::new(config).run_once();
+    }
+    // Checked before stderr is gagged so the reason is visible when run from a script or pipe.
+    if !io::stdout().is_terminal() || !io::stdin().is_terminal() {
+        return Err(eyre!("ibtop needs an interactive terminal")
+            .with_note(|| "stdin or stdout is not a terminal, e.g. output is piped or redirected")
+            .suggestion(
+                "run ibtop directly in a terminal, or use --once for a plain-text snapshot",
+            ));
     }
     let _stderr_gag: Option<gag::Gag> = gag::Gag::stderr().ok();
-    color_eyre::install()?;
     let terminal = ratatui::init();
+    execute!(io::stdout(), EnableMouseCapture)?;
     // Ensure we restore the terminal even if the app panics (e.g. due to service thread issues).
-    let result = catch_unwind(AssertUnwindSafe(|| App::new(args).run(terminal)));
+    let result = catch_unwind(AssertUnwindSafe(|| App::new(config).run(terminal)));
+    let _ = execute!(io::stdout(), DisableMouseCapture);
     ratatui::restore();
 
     match result {
-        Ok(r) => r,
+        Ok(r) => r.map(|()| ExitCode::SUCCESS),
         Err(panic_payload) => {
             eprintln!(
                 "ibtop panicked (set RUST_BACKTRACE=1 for more detail). payload: {:?}",
                 panic_payload
             );
-            Err(color_eyre::eyre::eyre!("ibtop panicked"))
+            Err(eyre!("ibtop panicked"))
         }
     }
 }
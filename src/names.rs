@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+/// Read a node name map (`--name-map`) mapping node GUIDs to friendly names.
+///
+/// Uses the OFED node-name-map layout: one `<guid> <name>` pair per line, where the GUID is
+/// hex (with or without `0x`) and the name may be double-quoted. Blank lines and `#` comments
+/// are ignored; malformed lines are skipped. I/O errors are returned.
+pub fn read_name_map(path: &str) -> io::Result<HashMap<u64, String>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut names = HashMap::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((guid_str, name)) = line.split_once(char::is_whitespace) else {
+            tracing::warn!("Skipping name map line without a name: {line}");
+            continue;
+        };
+
+        let hex = guid_str
+            .strip_prefix("0x")
+            .or_else(|| guid_str.strip_prefix("0X"))
+            .unwrap_or(guid_str);
+        let guid = match u64::from_str_radix(hex, 16) {
+            Ok(guid) => guid,
+            Err(e) => {
+                tracing::warn!("Skipping name map line with invalid GUID '{guid_str}': {e}");
+                continue;
+            }
+        };
+
+        let name = name.trim();
+        let name = name
+            .strip_prefix('"')
+            .and_then(|n| n.strip_suffix('"'))
+            .unwrap_or(name);
+        if !name.is_empty() {
+            names.insert(guid, name.to_string());
+        }
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_map_lines_are_parsed_or_skipped() {
+        let path = std::env::temp_dir().join(format!("ibtop-{}-names.map", std::process::id()));
+        std::fs::write(
+            &path,
+            "# spine switches\n\
+             0x0002c90300a1b2c3 \"spine-01\"\n\
+             0002C90300A1B2C4   spine 02  \n\
+             \n\
+             0xnothex \"bad\"\n\
+             0x10\n\
+             0x11 \"\"\n",
+        )
+        .unwrap();
+        let names = read_name_map(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        let names = names.unwrap();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[&0x0002c90300a1b2c3], "spine-01");
+        assert_eq!(names[&0x0002c90300a1b2c4], "spine 02");
+    }
+
+    #[test]
+    fn missing_name_map_is_an_error() {
+        assert!(read_name_map("/nonexistent/ibtop.map").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::ui::helpers::format_guid;
+
+/// Exposition text served by the `--prometheus-port` endpoint, replaced after each update.
+pub type MetricsText = Arc<Mutex<String>>;
+
+/// Latest values exported for one node.
+#[derive(Debug, Clone, Default)]
+pub struct NodeMetrics {
+    pub lid: u16,
+    pub name: String,
+    pub hca: String,
+    /// Rates in bits per second; unknown until two samples have been taken
+    pub recv_bps: Option<f64>,
+    pub send_bps: Option<f64>,
+    pub bw_loss_bps: Option<f64>,
+    /// Sum of the error counters since they were last cleared
+    pub errors: u128,
+}
+
+/// Bind `0.0.0.0:<port>` and serve the returned text on `GET /metrics` from a background
+/// thread.
+pub fn serve(port: u16) -> io::Result<MetricsText> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    let text = MetricsText::default();
+
+    let shared = Arc::clone(&text);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| handle_connection(stream, &shared));
+            if let Err(e) = result {
+                warn!("Prometheus endpoint request failed: {e}");
+            }
+        }
+    });
+
+    Ok(text)
+}
+
+/// Answer a single HTTP/1.x request and close the connection.
+fn handle_connection(stream: TcpStream, text: &Mutex<String>) -> io::Result<()> {
+    // A client that never finishes its request mustn't block the next scrape
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers; the request has no body we care about
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = text.lock().map(|t| t.clone()).unwrap_or_default();
+            ("200 OK", body)
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "not found, try /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// Escape a label value as the exposition format requires.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render the exposition text for `nodes`, keyed by node GUID.
+pub fn render_metrics(nodes: &BTreeMap<u64, NodeMetrics>) -> String {
+    type Metric = (
+        &'static str,
+        &'static str,
+        &'static str,
+        fn(&NodeMetrics) -> Option<f64>,
+    );
+    const METRICS: [Metric; 4] = [
+        (
+            "ibtop_recv_bps",
+            "gauge",
+            "Receive bandwidth in bits per second",
+            |n| n.recv_bps,
+        ),
+        (
+            "ibtop_send_bps",
+            "gauge",
+            "Send bandwidth in bits per second",
+            |n| n.send_bps,
+        ),
+        (
+            "ibtop_bw_loss_bps",
+            "gauge",
+            "Bandwidth lost to transmit waits in bits per second",
+            |n| n.bw_loss_bps,
+        ),
+        (
+            "ibtop_errors_total",
+            "counter",
+            "Sum of the error counters",
+            |n| Some(n.errors as f64),
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in METRICS {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (guid, node) in nodes {
+            let Some(value) = value(node) else {
+                continue;
+            };
+            let _ = writeln!(
+                out,
+                "{name}{{guid=\"{}\",lid=\"{}\",node=\"{}\",hca=\"{}\"}} {value}",
+                format_guid(*guid),
+                node.lid,
+                escape_label(&node.name),
+                escape_label(&node.hca),
+            );
+        }
+    }
+    out
+}
@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    services::lib::{CounterKey, CountersMap, Node},
+    ui::helpers::format_guid,
+};
+
+/// Counters for a single LID/port in a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCounters {
+    /// HCA the LID was queried through; empty in recordings made before it was stored
+    #[serde(default)]
+    pub hca: String,
+    pub lid: u16,
+    pub port: i32,
+    pub counters: HashMap<String, u64>,
+}
+
+/// One line of a `--record` log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecordEntry {
+    /// A discovery response
+    Nodes {
+        timestamp: DateTime<Utc>,
+        nodes: Vec<Node>,
+    },
+    /// A counters response
+    Counters {
+        timestamp: DateTime<Utc>,
+        counters: Vec<RecordedCounters>,
+    },
+}
+
+/// Appends discovery and counters responses to a newline-delimited JSON log.
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Open `path` for appending, creating it if needed.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn record_nodes(&mut self, nodes: &[Node]) -> io::Result<()> {
+        self.write(&RecordEntry::Nodes {
+            timestamp: Utc::now(),
+            nodes: nodes.to_vec(),
+        })
+    }
+
+    pub fn record_counters(&mut self, counters: &CountersMap) -> io::Result<()> {
+        // Tuple keys aren't valid JSON object keys, so counters are stored as a list.
+        let mut counters: Vec<RecordedCounters> = counters
+            .iter()
+            .map(|((hca, lid, port), ctrs)| RecordedCounters {
+                hca: hca.clone(),
+                lid: *lid,
+                port: *port,
+                counters: ctrs.clone(),
+            })
+            .collect();
+        counters.sort_by(|a, b| (&a.hca, a.lid, a.port).cmp(&(&b.hca, b.lid, b.port)));
+
+        self.write(&RecordEntry::Counters {
+            timestamp: Utc::now(),
+            counters,
+        })
+    }
+
+    fn write(&mut self, entry: &RecordEntry) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, entry)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+/// One line of a `--json-stream` file: a LID/port's counters from one update, with the
+/// node it belongs to.
+#[derive(Debug, Serialize)]
+struct StreamRecord<'a> {
+    timestamp: DateTime<Utc>,
+    hca: &'a str,
+    lid: u16,
+    port: i32,
+    guid: Option<String>,
+    node: Option<&'a str>,
+    counters: &'a HashMap<String, u64>,
+}
+
+/// Node details attached to the `--json-stream` records of its LID.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamNode<'a> {
+    pub guid: u64,
+    pub name: &'a str,
+}
+
+/// Writes every counters update to a newline-delimited JSON file (`--json-stream`), one
+/// record per LID/port, for piping into `jq` or a log collector.
+pub struct CounterStream {
+    writer: BufWriter<File>,
+}
+
+impl CounterStream {
+    /// Open `path` for appending, creating it if needed. Opening a FIFO waits for a reader.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn write_counters(
+        &mut self,
+        counters: &CountersMap,
+        nodes: &HashMap<(&str, u16), StreamNode>,
+    ) -> io::Result<()> {
+        let timestamp = Utc::now();
+        let mut keys: Vec<&CounterKey> = counters.keys().collect();
+        keys.sort();
+
+        for key in keys {
+            let (hca, lid, port) = key;
+            let node = nodes.get(&(hca.as_str(), *lid));
+            let record = StreamRecord {
+                timestamp,
+                hca,
+                lid: *lid,
+                port: *port,
+                guid: node.map(|n| format_guid(n.guid)),
+                node: node.map(|n| n.name),
+                counters: &counters[key],
+            };
+            serde_json::to_writer(&mut self.writer, &record)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Read every entry of a recording made with `--record`.
+///
+/// Malformed lines are skipped; I/O errors are returned.
+pub fn read_recording(path: &str) -> io::Result<Vec<RecordEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => tracing::warn!("Skipping malformed recording line: {e}"),
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::lib::NodeType;
+
+    #[test]
+    fn recording_reads_back() {
+        let path = std::env::temp_dir().join(format!("ibtop-{}-record.jsonl", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let node = Node {
+            guid: 0x10,
+            node_description: "leaf-01".into(),
+            ports: Vec::new(),
+            lid: 12,
+            hca: "mlx5_0".into(),
+            node_type: NodeType::Switch,
+        };
+        let counters: CountersMap = HashMap::from([
+            (
+                node.counter_key(2),
+                HashMap::from([("rcv_bytes".into(), 20)]),
+            ),
+            (
+                node.counter_key(1),
+                HashMap::from([("rcv_bytes".into(), 10)]),
+            ),
+        ]);
+
+        let mut recorder = Recorder::open(path_str).unwrap();
+        recorder.record_nodes(std::slice::from_ref(&node)).unwrap();
+        recorder.record_counters(&counters).unwrap();
+        drop(recorder);
+        // A line cut short by a crash is skipped, and later appends still read
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"type\":\"counters\",\"timest\n")
+            .unwrap();
+        Recorder::open(path_str)
+            .unwrap()
+            .record_counters(&counters)
+            .unwrap();
+
+        let entries = read_recording(path_str);
+        let _ = std::fs::remove_file(&path);
+        let entries = entries.unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let RecordEntry::Nodes { nodes, .. } = &entries[0] else {
+            panic!("expected nodes first");
+        };
+        assert_eq!((nodes[0].guid, nodes[0].lid), (0x10, 12));
+        assert_eq!(nodes[0].hca, "mlx5_0");
+
+        let RecordEntry::Counters {
+            counters: recorded, ..
+        } = &entries[1]
+        else {
+            panic!("expected counters second");
+        };
+        let read: Vec<_> = recorded
+            .iter()
+            .map(|c| (c.hca.as_str(), c.lid, c.port, c.counters["rcv_bytes"]))
+            .collect();
+        assert_eq!(read, [("mlx5_0", 12, 1, 10), ("mlx5_0", 12, 2, 20)]);
+    }
+
+    #[test]
+    fn counters_recorded_without_hca_read_with_an_empty_one() {
+        let line = r#"{"type":"counters","timestamp":"2025-01-01T00:00:00Z","counters":[{"lid":5,"port":1,"counters":{}}]}"#;
+        let RecordEntry::Counters { counters, .. } = serde_json::from_str(line).unwrap() else {
+            panic!("expected counters");
+        };
+        assert_eq!((counters[0].hca.as_str(), counters[0].lid), ("", 5));
+    }
+}
@@ -1,111 +1,362 @@
-use crate::services::lib::{Node, Port};
+use crate::record::RecordedCounters;
+use crate::services::lib::{Node, NodeType, Port};
+use crate::ui::helpers::format_guid;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
 
-pub fn read_scope_file(path: &str) -> Vec<Node> {
-    let file = File::open(path).unwrap();
+/// A JSON scope file: the nodes to monitor plus an optional counter baseline.
+///
+/// Nodes use the same layout as the discovery entries of a `--record` log; their `hca` is
+/// filled in when loaded and may be omitted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScopeSnapshot {
+    pub nodes: Vec<Node>,
+    /// Counters per LID/port that Baseline mode shows deltas from
+    #[serde(default)]
+    pub baseline: Vec<RecordedCounters>,
+}
+
+/// Whether `path` names a JSON scope file rather than a CSV one.
+pub fn is_json_scope_file(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// A scope file line that was skipped or conflicts with an earlier one, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeIssue {
+    /// 1-based line number
+    pub line: usize,
+    pub reason: String,
+}
+
+impl std::fmt::Display for ScopeIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// Read the nodes of a scope file, which is JSON (see [`ScopeSnapshot`]) when the path ends
+/// in `.json` and CSV otherwise, along with the lines that were skipped.
+pub fn read_scope_file(path: &str) -> io::Result<(Vec<Node>, Vec<ScopeIssue>)> {
+    if is_json_scope_file(path) {
+        Ok((read_scope_snapshot(path)?.nodes, Vec::new()))
+    } else {
+        read_scope_csv(path)
+    }
+}
+
+/// Read a JSON scope file.
+pub fn read_scope_snapshot(path: &str) -> io::Result<ScopeSnapshot> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Parse a GUID given in hex (`0x...`) or decimal.
+fn parse_guid(s: &str) -> Result<u64, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => {
+            u64::from_str_radix(hex, 16).map_err(|e| format!("invalid hex GUID '{s}': {e}"))
+        }
+        None => s
+            .parse::<u64>()
+            .map_err(|e| format!("invalid decimal GUID '{s}': {e}")),
+    }
+}
+
+/// Parse one data line into its GUID, node description, LID and port.
+fn parse_scope_line(line: &str) -> Result<(u64, String, u16, Port), String> {
+    let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+    if !(4..=7).contains(&parts.len()) {
+        return Err(format!("expected 4 to 7 fields, found {}", parts.len()));
+    }
+
+    let guid = parse_guid(parts[0])?;
+    let node_description = parts[1].to_string();
+
+    let lid = match parts[2].parse::<u16>() {
+        Ok(0) => return Err("LID 0 is not a valid LID".to_string()),
+        Ok(lid) => lid,
+        Err(e) => return Err(format!("invalid LID '{}': {e}", parts[2])),
+    };
+
+    let port_number = match parts[3].parse::<i32>() {
+        Ok(n) if n < 0 => return Err(format!("port number {n} is negative")),
+        Ok(n) => n,
+        Err(e) => return Err(format!("invalid port number '{}': {e}", parts[3])),
+    };
+
+    // Optional remote GUID and LID; empty fields mean unknown
+    let remote_guid = match parts.get(5).filter(|s| !s.is_empty()) {
+        Some(s) => Some(parse_guid(s).map_err(|e| format!("remote {e}"))?),
+        None => None,
+    };
+    let remote_lid = match parts.get(6).filter(|s| !s.is_empty()) {
+        Some(s) => Some(
+            s.parse::<u16>()
+                .map_err(|e| format!("invalid remote LID '{s}': {e}"))?,
+        ),
+        None => None,
+    };
+
+    let port = Port {
+        number: port_number,
+        remote_node_description: parts.get(4).copied().unwrap_or_default().to_string(),
+        remote_guid,
+        remote_lid,
+        link_state: "Unknown".to_string(),
+        phys_state: "Unknown".to_string(),
+        link_width: None,
+        link_speed: None,
+    };
+
+    Ok((guid, node_description, lid, port))
+}
+
+/// Read a scope CSV file
+/// (`guid,node_desc,lid,port[,link_partner_description[,link_partner_guid[,link_partner_lid]]]`)
+/// into nodes.
+///
+/// Malformed lines and repeated ports are skipped and reported, as are lines whose LID or
+/// description disagree with an earlier line for the same GUID (the earlier one is kept).
+/// I/O errors (missing file, failed reads) are returned.
+fn read_scope_csv(path: &str) -> io::Result<(Vec<Node>, Vec<ScopeIssue>)> {
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
     let mut nodes_map: HashMap<u64, Node> = HashMap::new();
+    let mut issues = Vec::new();
 
     for (index, line) in reader.lines().enumerate() {
-        let line = line.unwrap();
+        let line = line?;
 
-        // Skip the header line
-        if index == 0 {
+        // Skip the header line and empty lines
+        if index == 0 || line.trim().is_empty() {
             continue;
         }
 
-        // Skip empty lines
-        if line.trim().is_empty() {
-            continue;
-        }
+        let (guid, node_description, lid, port) = match parse_scope_line(&line) {
+            Ok(parsed) => parsed,
+            Err(reason) => {
+                issues.push(ScopeIssue {
+                    line: index + 1,
+                    reason,
+                });
+                continue;
+            }
+        };
 
-        let parts: Vec<&str> = line.split(',').collect();
-        let parts_len = parts.len();
-        if parts_len != 4 && parts_len != 5 {
-            eprintln!("Warning: Skipping malformed line: {}", line);
+        let Some(node) = nodes_map.get_mut(&guid) else {
+            nodes_map.insert(
+                guid,
+                Node {
+                    guid,
+                    node_description,
+                    lid,
+                    ports: vec![port],
+                    hca: String::new(),
+                    // Scope files don't record the node type
+                    node_type: NodeType::default(),
+                },
+            );
             continue;
-        }
+        };
 
-        // Parse GUID - handle both hex (0x...) and decimal formats
-        let guid_str = parts[0].trim();
-        let guid = if guid_str.starts_with("0x") || guid_str.starts_with("0X") {
-            // Parse as hexadecimal
-            match u64::from_str_radix(&guid_str[2..], 16) {
-                Ok(val) => val,
-                Err(e) => {
-                    eprintln!(
-                        "Warning: Failed to parse GUID '{}' as hex: {}. Skipping line.",
-                        guid_str, e
-                    );
-                    continue;
-                }
-            }
+        // Add the port to the existing node; its first line's LID and description win
+        let mut reason = None;
+        if node.ports.iter().any(|p| p.number == port.number) {
+            reason = Some(format!(
+                "duplicate port {} for GUID {}, skipped",
+                port.number,
+                format_guid(guid)
+            ));
         } else {
-            // Parse as decimal
-            match guid_str.parse::<u64>() {
-                Ok(val) => val,
-                Err(e) => {
-                    eprintln!(
-                        "Warning: Failed to parse GUID '{}' as decimal: {}. Skipping line.",
-                        guid_str, e
-                    );
-                    continue;
-                }
+            if node.lid != lid {
+                reason = Some(format!(
+                    "LID {lid} conflicts with LID {} given earlier for GUID {}",
+                    node.lid,
+                    format_guid(guid)
+                ));
+            } else if node.node_description != node_description {
+                reason = Some(format!(
+                    "description '{node_description}' conflicts with '{}' given earlier for \
+                     GUID {}",
+                    node.node_description,
+                    format_guid(guid)
+                ));
             }
-        };
+            node.ports.push(port);
+        }
+        if let Some(reason) = reason {
+            issues.push(ScopeIssue {
+                line: index + 1,
+                reason,
+            });
+        }
+    }
 
-        let node_description = parts[1].trim().to_string();
+    Ok((nodes_map.into_values().collect(), issues))
+}
 
-        let lid = match parts[2].trim().parse::<u16>() {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!(
-                    "Warning: Failed to parse LID '{}': {}. Skipping line.",
-                    parts[2].trim(),
-                    e
-                );
-                continue;
-            }
-        };
+/// Write nodes to a scope CSV file that [`read_scope_file`] reads back, one line per port.
+///
+/// The format has no quoting, so commas in descriptions are written as semicolons. Nodes
+/// without ports can't be represented and are left out.
+pub fn write_scope_file(path: &Path, nodes: &[Node]) -> io::Result<()> {
+    let field = |s: &str| s.replace(',', ";");
 
-        let port_number = match parts[3].trim().parse::<i32>() {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!(
-                    "Warning: Failed to parse port number '{}': {}. Skipping line.",
-                    parts[3].trim(),
-                    e
-                );
-                continue;
-            }
-        };
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "guid,node_desc,lid,port,link_partner_description,link_partner_guid,link_partner_lid"
+    )?;
+    for node in nodes {
+        for port in &node.ports {
+            let remote_guid = port.remote_guid.map(format_guid).unwrap_or_default();
+            let remote_lid = port
+                .remote_lid
+                .map(|lid| lid.to_string())
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "{},{},{},{},{},{remote_guid},{remote_lid}",
+                format_guid(node.guid),
+                field(&node.node_description),
+                node.lid,
+                port.number,
+                field(&port.remote_node_description)
+            )?;
+        }
+    }
+    writer.flush()
+}
 
-        let remote_node_description = if parts_len == 5 {
-            parts[4].trim().to_string()
-        } else {
-            String::new()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Write `contents` to a scope file unique to this test.
+    fn scope_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ibtop-{}-{name}.csv", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn read(name: &str, contents: &str) -> (Vec<Node>, Vec<ScopeIssue>) {
+        let path = scope_file(name, contents);
+        let read = read_scope_file(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        read.unwrap()
+    }
+
+    const HEADER: &str = "guid,node_desc,lid,port\n";
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let err = read_scope_file("/nonexistent/ibtop-scope.csv").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn empty_and_header_only_files_have_no_nodes() {
+        for (name, contents) in [("empty", ""), ("header", HEADER)] {
+            let (nodes, issues) = read(name, contents);
+            assert!(nodes.is_empty() && issues.is_empty(), "{name}");
+        }
+    }
+
+    #[test]
+    fn written_scope_file_reads_back() {
+        let port = |number, remote_guid, remote_lid| Port {
+            number,
+            remote_node_description: "spine, rack 2".into(),
+            remote_guid,
+            remote_lid,
+            link_state: "Active".into(),
+            phys_state: "LinkUp".into(),
+            link_width: Some(4),
+            link_speed: None,
         };
+        let nodes = vec![
+            Node {
+                guid: 0x0002c90300a1b2c3,
+                node_description: "leaf-01".into(),
+                ports: vec![port(1, Some(0x10), Some(7)), port(2, None, None)],
+                lid: 12,
+                hca: "mlx5_0".into(),
+                node_type: NodeType::Switch,
+            },
+            Node {
+                guid: 42,
+                node_description: "leaf-02".into(),
+                ports: vec![port(1, None, Some(3))],
+                lid: 13,
+                hca: "mlx5_0".into(),
+                node_type: NodeType::Switch,
+            },
+        ];
+
+        let path = scope_file("roundtrip", "");
+        write_scope_file(&path, &nodes).unwrap();
+        let read = read_scope_file(path.to_str().unwrap());
+        let _ = std::fs::remove_file(&path);
+        let (mut read, issues) = read.unwrap();
+        assert!(issues.is_empty());
 
-        // Create the port
-        let port = Port {
-            number: port_number,
-            remote_node_description: remote_node_description,
-            link_state: "Unknown".to_string(),
+        // What a scope file holds: everything but the link state, HCA and node type
+        let summary = |nodes: &mut Vec<Node>| {
+            nodes.sort_by_key(|n| n.guid);
+            nodes
+                .iter_mut()
+                .map(|n| {
+                    n.ports.sort_by_key(|p| p.number);
+                    let ports: Vec<_> = n
+                        .ports
+                        .iter()
+                        .map(|p| (p.number, p.remote_guid, p.remote_lid))
+                        .collect();
+                    (n.guid, n.node_description.clone(), n.lid, ports)
+                })
+                .collect::<Vec<_>>()
         };
+        assert_eq!(summary(&mut read), summary(&mut nodes.clone()));
+        // Commas can't be quoted, so they come back as semicolons
+        assert!(
+            read.iter()
+                .flat_map(|n| &n.ports)
+                .all(|p| p.remote_node_description == "spine; rack 2")
+        );
+    }
 
-        // Add port to existing node or create new node
-        nodes_map
-            .entry(guid)
-            .and_modify(|node| node.ports.push(port.clone()))
-            .or_insert(Node {
-                guid,
-                node_description,
-                lid,
-                ports: vec![port],
-            });
+    #[test]
+    fn repeated_port_lines_are_merged() {
+        let (nodes, issues) = read(
+            "repeated",
+            &format!("{HEADER}0x10,leaf-01,12,1\n0x10,leaf-01,12,2\n0x10,leaf-01,12,1\n"),
+        );
+        assert_eq!(nodes.len(), 1);
+        let ports: Vec<i32> = nodes[0].ports.iter().map(|p| p.number).collect();
+        assert_eq!(ports, [1, 2]);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 4);
+        assert!(issues[0].reason.contains("duplicate port 1"));
     }
 
-    nodes_map.into_values().collect()
+    #[test]
+    fn conflicting_lid_is_flagged_and_first_kept() {
+        let (nodes, issues) = read(
+            "conflict",
+            &format!("{HEADER}0x10,leaf-01,12,1\n0x10,leaf-01,99,2\n"),
+        );
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].lid, 12);
+        assert_eq!(nodes[0].ports.len(), 2);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 3);
+        assert!(issues[0].reason.contains("LID 99 conflicts with LID 12"));
+    }
 }
@@ -1,17 +1,32 @@
-use super::lib::{CounterEvent, CountersService, DiscoverService, DiscoveryEvent, Node};
+use super::lib::{
+    CounterEvent, CounterKey, CountersMap, CountersService, DiscoverService, DiscoveryEvent,
+    DiscoveryStats, Node, RequestId,
+};
 use crate::{
-    app::AppConfig,
-    services::lib::{LidPort, Port},
+    app::{AppConfig, CountersQuery},
+    services::lib::{LidPort, LinkSpeed, NodeType, Port, QUERY_FAILED_KEY, QUERY_RETRIES_KEY},
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use ibmad::mad;
-use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
 use std::{
+    cell::RefCell,
     collections::HashMap,
-    sync::mpsc::{Receiver, Sender},
+    io,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering as AtomicOrdering},
+        mpsc::{Receiver, Sender},
+    },
+    thread,
 };
 use tracing::{error, warn};
 
+/// Description, GUID and LID of the node at the other end of a port's link.
+type RemoteNode = (String, u64, u16);
+
+/// Keys of the error counters in a counters map. Every counters service must emit all of
+/// them; ERR_CNT and ERR_STR look them up by these names.
 pub const ERROR_COUNTERS: [&str; 9] = [
     "symbol_errors",
     "link_recovers",
@@ -24,6 +39,67 @@ pub const ERROR_COUNTERS: [&str; 9] = [
     "qp1_drops",
 ];
 
+/// Reads one counter from a PortCounters(Extended) response.
+type PerfField = fn(&mad::PerfMad) -> u64;
+
+/// Where each of `ERROR_COUNTERS` is read from in a PortCounters(Extended) response.
+pub(crate) const PERF_ERROR_COUNTERS: [(&str, PerfField); ERROR_COUNTERS.len()] = [
+    ("symbol_errors", mad::PerfMad::symbol_error_counter),
+    ("link_recovers", mad::PerfMad::link_error_recovery_counter),
+    ("link_downed", mad::PerfMad::link_downed_counter),
+    ("rcv_errors", mad::PerfMad::port_rcv_errors),
+    (
+        "phys_rcv_errors",
+        mad::PerfMad::port_rcv_remote_physical_errors,
+    ),
+    (
+        "switch_rel_errors",
+        mad::PerfMad::port_rcv_switch_relay_errors,
+    ),
+    (
+        "excess_overrun_errors",
+        mad::PerfMad::excessive_buffer_overrun_errors,
+    ),
+    ("vl15dropped", mad::PerfMad::vl15_dropped),
+    ("qp1_drops", mad::PerfMad::qp1_dropped),
+];
+
+/// Counters that wrap around at a fixed bit width, and that width. Error counters saturate
+/// at their maximum instead, so a drop in one of those is always a reset.
+pub const WRAPPING_COUNTERS: [(&str, u32); 1] = [("xmit_waits", 32)];
+
+/// Decode the PortInfo LinkWidthActive bitmask into a lane count.
+fn decode_link_width(width: u8) -> Option<u8> {
+    match width {
+        1 => Some(1),
+        2 => Some(4),
+        4 => Some(8),
+        8 => Some(12),
+        16 => Some(2),
+        _ => None,
+    }
+}
+
+/// Decode PortInfo LinkSpeedActive/LinkSpeedExtActive into a per-lane speed.
+///
+/// The extended field takes precedence when set (FDR and faster).
+fn decode_link_speed(speed: u8, speed_ext: u8) -> Option<LinkSpeed> {
+    match speed_ext {
+        1 => return Some(LinkSpeed::FDR),
+        2 => return Some(LinkSpeed::EDR),
+        4 => return Some(LinkSpeed::HDR),
+        8 => return Some(LinkSpeed::NDR),
+        16 => return Some(LinkSpeed::XDR),
+        _ => {}
+    }
+    match speed {
+        1 => Some(LinkSpeed::SDR),
+        2 => Some(LinkSpeed::DDR),
+        4 => Some(LinkSpeed::QDR),
+        _ => None,
+    }
+}
+
 pub struct IbmadDiscoveryService {
     ev_disc_rx: Receiver<DiscoveryEvent>,
     disc_ev_tx: Sender<DiscoveryEvent>,
@@ -51,10 +127,13 @@ impl IbmadDiscoveryService {
                         // Terminate thread
                         return Ok(());
                     }
-                    DiscoveryEvent::Request => {
-                        let nodes = self.get_nodes();
+                    DiscoveryEvent::Request(id) => {
+                        let (nodes, stats) = self.discover(Some(id));
                         // Send the response even if empty
-                        if let Err(e) = self.disc_ev_tx.send(DiscoveryEvent::Response(nodes)) {
+                        if let Err(e) = self
+                            .disc_ev_tx
+                            .send(DiscoveryEvent::Response(id, nodes, stats))
+                        {
                             error!("Failed to send discovery response: {e}");
                         }
                     }
@@ -73,8 +152,10 @@ impl IbmadDiscoveryService {
     }
 }
 
-impl DiscoverService for IbmadDiscoveryService {
-    fn get_nodes(&self) -> Vec<Node> {
+impl IbmadDiscoveryService {
+    /// Sweep the fabric, returning the discovered nodes and the sweep's MAD statistics.
+    /// Progress is reported against request `id`, if the sweep was asked for by one.
+    fn discover(&self, id: Option<RequestId>) -> (Vec<Node>, DiscoveryStats) {
         let mut nodes = Vec::new();
 
         // Get the HCA
@@ -82,7 +163,7 @@ impl DiscoverService for IbmadDiscoveryService {
             Ok(ca) => ca,
             Err(e) => {
                 error!("Failed to get HCA '{}': {e}", self.config.hca);
-                return nodes;
+                return (nodes, DiscoveryStats::default());
             }
         };
 
@@ -91,7 +172,7 @@ impl DiscoverService for IbmadDiscoveryService {
             Ok(p) => p,
             Err(e) => {
                 error!("Failed to open SMP port: {e}");
-                return nodes;
+                return (nodes, DiscoveryStats::default());
             }
         };
 
@@ -104,7 +185,7 @@ impl DiscoverService for IbmadDiscoveryService {
             Ok(id) => id,
             Err(e) => {
                 error!("Failed to register DR SMP agent: {e}");
-                return nodes;
+                return (nodes, DiscoveryStats::default());
             }
         };
 
@@ -126,13 +207,29 @@ impl DiscoverService for IbmadDiscoveryService {
             tid: 1,
         };
 
-        if let Err(e) = fabric.seq_discover() {
+        // The sweep holds `fabric` until it is done and has no progress callback, so its counts
+        // can only be reported once it returns. Until then the header shows how long the
+        // sweep has been running.
+        let result = fabric.seq_discover();
+        let stats = DiscoveryStats {
+            mads_sent: fabric.mads_sent as u64,
+            mad_errors: fabric.mad_errors as u64,
+            mad_timeouts: fabric.mad_timeouts as u64,
+        };
+        if let Some(id) = id {
+            let _ = self.disc_ev_tx.send(DiscoveryEvent::Progress {
+                id,
+                mads_sent: stats.mads_sent,
+                nodes_found: fabric.nodes.len(),
+            });
+        }
+        if let Err(e) = result {
             error!("Error discovering fabric: {e}");
-            return nodes;
+            return (nodes, stats);
         }
 
         // Build port connections map
-        let mut port_connections: HashMap<(u64, u8), String> = HashMap::new();
+        let mut port_connections: HashMap<(u64, u8), RemoteNode> = HashMap::new();
 
         for node_arc in &fabric.nodes {
             let node_ref = match node_arc.read() {
@@ -146,19 +243,20 @@ impl DiscoverService for IbmadDiscoveryService {
                     Err(_) => continue,
                 };
 
-                if let Some(weak_remote) = &port_ref.remote_port {
-                    if let Some(remote_port_arc) = weak_remote.upgrade() {
-                        if let Ok(remote_port_ref) = remote_port_arc.read() {
-                            if let Some(remote_node_arc) = remote_port_ref.parent.upgrade() {
-                                if let Ok(remote_node_ref) = remote_node_arc.read() {
-                                    port_connections.insert(
-                                        (node_ref.node_guid, port_ref.number),
-                                        remote_node_ref.description.clone().unwrap_or_default(),
-                                    );
-                                }
-                            }
-                        }
-                    }
+                if let Some(weak_remote) = &port_ref.remote_port
+                    && let Some(remote_port_arc) = weak_remote.upgrade()
+                    && let Ok(remote_port_ref) = remote_port_arc.read()
+                    && let Some(remote_node_arc) = remote_port_ref.parent.upgrade()
+                    && let Ok(remote_node_ref) = remote_node_arc.read()
+                {
+                    port_connections.insert(
+                        (node_ref.node_guid, port_ref.number),
+                        (
+                            remote_node_ref.description.clone().unwrap_or_default(),
+                            remote_node_ref.node_guid,
+                            remote_node_ref.lid,
+                        ),
+                    );
                 }
             }
         }
@@ -170,68 +268,116 @@ impl DiscoverService for IbmadDiscoveryService {
                 Err(_) => continue,
             };
 
-            match node_ref.node_type {
-                ibmad::enums::IbNodeType::CA => {
-                    if self.config.include_hcas {
-                        nodes.push(Node {
-                            guid: node_ref.node_guid,
-                            node_description: node_ref.description.clone().unwrap_or_default(),
-                            ports: Vec::new(),
-                            lid: node_ref.lid,
-                        });
-                    }
-                }
-                ibmad::enums::IbNodeType::Switch => {
-                    let ports: Vec<Port> = node_ref
-                        .ports
-                        .iter()
-                        .filter_map(|port_arc| {
-                            let port_ref = port_arc.read().ok()?;
-
-                            // Skip port 0 (management port) and inactive ports
-                            // Only include ports that are Active or Init state
-                            if port_ref.number == 0 {
-                                return None;
-                            }
-                            if port_ref.link_state != ibmad::enums::IbPortLinkLayerState::Active
-                                && port_ref.link_state != ibmad::enums::IbPortLinkLayerState::Init
-                            {
-                                return None;
-                            }
-
-                            let remote_desc = port_connections
-                                .get(&(node_ref.node_guid, port_ref.number))
-                                .cloned()
-                                .unwrap_or_default();
-
-                            Some(Port {
-                                number: port_ref.number as i32,
-                                remote_node_description: remote_desc,
-                                link_state: format!("{:?}", port_ref.link_state),
-                            })
-                        })
-                        .collect();
-
-                    nodes.push(Node {
-                        guid: node_ref.node_guid,
-                        node_description: node_ref.description.clone().unwrap_or_default(),
-                        ports,
-                        lid: node_ref.lid,
+            let node_type = match node_ref.node_type {
+                ibmad::enums::IbNodeType::CA => NodeType::Hca,
+                _ => NodeType::Switch,
+            };
+            let ports = match node_ref.node_type {
+                // Only CA ports linked into this fabric are listed; a dual-port HCA's other
+                // port is usually cabled elsewhere or unused.
+                ibmad::enums::IbNodeType::CA if self.config.include_hcas => {
+                    let mut ports = convert_ports(&node_ref, &port_connections);
+                    ports.retain(|p| {
+                        port_connections.contains_key(&(node_ref.node_guid, p.number as u8))
                     });
+                    ports
                 }
-                _ => {}
-            }
+                ibmad::enums::IbNodeType::Switch => convert_ports(&node_ref, &port_connections),
+                _ => continue,
+            };
+
+            nodes.push(Node {
+                guid: node_ref.node_guid,
+                node_description: node_ref.description.clone().unwrap_or_default(),
+                ports,
+                lid: node_ref.lid,
+                hca: self.config.hca.clone(),
+                node_type,
+            });
         }
 
-        nodes
+        (nodes, stats)
+    }
+}
+
+/// Convert a discovered node's ports, skipping port 0 (the switch management port). Down
+/// ports are kept so the details popup can show dead links.
+fn convert_ports(
+    node_ref: &ibmad::discovery::IbNode,
+    port_connections: &HashMap<(u64, u8), RemoteNode>,
+) -> Vec<Port> {
+    node_ref
+        .ports
+        .iter()
+        .filter_map(|port_arc| {
+            let port_ref = port_arc.read().ok()?;
+            if port_ref.number == 0 {
+                return None;
+            }
+
+            let remote = port_connections.get(&(node_ref.node_guid, port_ref.number));
+
+            Some(Port {
+                number: port_ref.number as i32,
+                remote_node_description: remote.map(|r| r.0.clone()).unwrap_or_default(),
+                remote_guid: remote.map(|r| r.1),
+                remote_lid: remote.map(|r| r.2),
+                link_state: format!("{:?}", port_ref.link_state),
+                phys_state: format!("{:?}", port_ref.phys_state),
+                link_width: decode_link_width(port_ref.link_width_active),
+                link_speed: decode_link_speed(
+                    port_ref.link_speed_active,
+                    port_ref.link_speed_ext_active,
+                ),
+            })
+        })
+        .collect()
+}
+
+impl DiscoverService for IbmadDiscoveryService {
+    fn get_nodes(&self) -> Vec<Node> {
+        self.discover(None).0
+    }
+}
+
+thread_local! {
+    /// MAD port and perf agent of a counters pool thread, opened on its first query and kept
+    /// open across requests
+    static PERF_PORT: RefCell<Option<(mad::Port, u32)>> = const { RefCell::new(None) };
+}
+
+/// Open a MAD port on `hca` and register a performance agent on it.
+fn open_perf_port(hca: &ibmad::ca::Ca) -> Option<(mad::Port, u32)> {
+    let mut port = match mad::open_port(hca) {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Failed to open MAD port in thread: {e}");
+            return None;
+        }
+    };
+    match mad::register_agent(&mut port, mad::IB_MGMT_CLASS_PERFORMANCE) {
+        Ok(id) => Some((port, id)),
+        Err(e) => {
+            error!("Failed to register perf agent in thread: {e}");
+            None
+        }
     }
 }
 
 // Counters service
+//
+// Queries run on a pool of `--threads` workers owned by the service, so each HCA gets its own
+// pool. Every worker keeps its MAD port open until the service exits.
 pub struct IbmadCountersService {
     ev_ctr_rx: Receiver<CounterEvent>,
     ctr_ev_tx: Sender<CounterEvent>,
     config: AppConfig,
+    /// Bumped by the event handler to cancel the request in progress
+    cancel_epoch: Arc<AtomicU64>,
+    pool: ThreadPool,
+    /// With `--counters-query auto`, the query each LID was found to answer (`Extended` or
+    /// `Basic`). LIDs not in here yet are tried with both.
+    lid_queries: Mutex<HashMap<u16, CountersQuery>>,
 }
 
 impl IbmadCountersService {
@@ -239,12 +385,22 @@ impl IbmadCountersService {
         ev_ctr_rx: Receiver<CounterEvent>,
         ctr_ev_tx: Sender<CounterEvent>,
         config: AppConfig,
-    ) -> Self {
-        Self {
+        cancel_epoch: Arc<AtomicU64>,
+    ) -> color_eyre::Result<Self> {
+        let hca_name = config.hca.clone();
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.threads.max(1))
+            .thread_name(move |i| format!("ibtop-ctr-{hca_name}-{i}"))
+            .build()?;
+
+        Ok(Self {
             ev_ctr_rx,
             ctr_ev_tx,
             config,
-        }
+            cancel_epoch,
+            pool,
+            lid_queries: Mutex::new(HashMap::new()),
+        })
     }
 
     pub fn run(self) -> color_eyre::Result<()> {
@@ -254,44 +410,258 @@ impl IbmadCountersService {
                 Ok(ev) => match ev {
                     CounterEvent::Exit => {
                         tracing::info!("IbmadCountersService exiting");
+                        self.close_ports();
                         return Ok(());
                     }
-                    CounterEvent::Request(nodes) => {
+                    CounterEvent::Request(id, nodes) => {
                         tracing::debug!(
                             "IbmadCountersService: Request received for {} nodes",
                             nodes.len()
                         );
+                        let epoch = self.cancel_epoch.load(AtomicOrdering::Relaxed);
                         let counters = self.get_counters(nodes);
+                        if self.cancel_epoch.load(AtomicOrdering::Relaxed) != epoch {
+                            tracing::debug!("IbmadCountersService: Request cancelled");
+                            continue;
+                        }
                         tracing::debug!(
                             "IbmadCountersService: Sending response with {} entries",
                             counters.len()
                         );
-                        if let Err(e) = self.ctr_ev_tx.send(CounterEvent::Response(counters)) {
+                        if let Err(e) = self.ctr_ev_tx.send(CounterEvent::Response(id, counters)) {
                             error!("Failed to send counters response: {e}");
                         }
                     }
+                    CounterEvent::Reset(lid_ports) => {
+                        let results = self.reset_counters(lid_ports);
+                        if let Err(e) = self.ctr_ev_tx.send(CounterEvent::ResetResponse(results)) {
+                            error!("Failed to send counters reset response: {e}");
+                        }
+                    }
                     _ => {
                         warn!("Received unexpected CounterEvent: {ev:?}");
                     }
                 },
                 Err(e) => {
                     error!("CountersService channel closed: {e}");
+                    self.close_ports();
                     return Ok(());
                 }
             }
         }
     }
+
+    /// Run `query` for each LID/port on the pool, skipping the rest once the request is
+    /// cancelled. Failed queries are reported with a placeholder so the UI can flag them.
+    fn query_each(
+        &self,
+        lid_ports: Vec<LidPort>,
+        query: impl Fn(&LidPort) -> Option<HashMap<String, u64>> + Sync,
+    ) -> CountersMap {
+        let cancel_epoch = Arc::clone(&self.cancel_epoch);
+        let epoch = cancel_epoch.load(AtomicOrdering::Relaxed);
+
+        self.pool.install(|| {
+            lid_ports
+                .into_par_iter()
+                // One LID/port per task: query latency varies a lot, so workers steal single
+                // queries instead of being stuck with the tail of a long chunk. This also
+                // spreads the ports of a single switch in the details popup over every worker.
+                .with_max_len(1)
+                .filter_map(|lp| {
+                    if cancel_epoch.load(AtomicOrdering::Relaxed) != epoch {
+                        return None;
+                    }
+                    let counters = query(&lp)
+                        .unwrap_or_else(|| HashMap::from([(QUERY_FAILED_KEY.to_string(), 1)]));
+                    Some((lp.key(), counters))
+                })
+                .collect()
+        })
+    }
+
+    /// Close the MAD port held by every pool worker.
+    fn close_ports(&self) {
+        self.pool
+            .broadcast(|_| PERF_PORT.with_borrow_mut(Option::take));
+    }
+}
+
+/// PortCounters and PortCountersExtended queries, which take the same arguments.
+type PerfQuery = fn(&mut mad::Port, u32, u32, u32, u16, u8, u16) -> io::Result<mad::PerfMad>;
+
+/// Counters MAD of a successful query, when it was sent and answered, and its retry count.
+type PerfSample = (mad::PerfMad, DateTime<Utc>, DateTime<Utc>, u32);
+
+/// Whether a counters MAD reports no data in either direction.
+fn data_is_zero(perf_mad: &mad::PerfMad) -> bool {
+    perf_mad.port_xmit_data() == 0 && perf_mad.port_rcv_data() == 0
+}
+
+/// Whether a failed query got no answer at all, rather than an error status from the node.
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Query the counters of one LID/port, or `None` if the query failed.
+///
+/// With `--counters-query auto`, a LID is sent the extended query first. If it answers with
+/// an error, or its first answer has zero data counters while PortCounters doesn't, it is
+/// switched to the 32-bit PortCounters. Either way the outcome is remembered in
+/// `lid_queries` so later updates send a single query and values don't jump between the two
+/// counter widths. A timeout decides nothing; the LID is tried with both again next time.
+fn query_counters(
+    port: &mut mad::Port,
+    agent_id: u32,
+    lp: &LidPort,
+    config: &AppConfig,
+    lid_queries: &Mutex<HashMap<u16, CountersQuery>>,
+) -> Option<HashMap<String, u64>> {
+    let extended: PerfQuery = mad::query_port_counters_extended;
+    let basic: PerfQuery = mad::query_port_counters;
+
+    let decided = match config.counters_query {
+        CountersQuery::Auto => lid_queries
+            .lock()
+            .ok()
+            .and_then(|queries| queries.get(&lp.lid).copied()),
+        forced => Some(forced),
+    };
+    let decide = |query| {
+        if let Ok(mut queries) = lid_queries.lock() {
+            queries.insert(lp.lid, query);
+        }
+    };
+
+    let (perf_mad, start, end, retries) = match decided {
+        Some(CountersQuery::Basic) => query_with_retries(port, agent_id, lp, config, basic),
+        Some(_) => query_with_retries(port, agent_id, lp, config, extended),
+        None => match query_with_retries(port, agent_id, lp, config, extended) {
+            Ok(sample) if !data_is_zero(&sample.0) => {
+                decide(CountersQuery::Extended);
+                Ok(sample)
+            }
+            Err(e) if is_timeout(&e) => Err(e),
+            extended_res => {
+                // Either an error status or zero data; see what PortCounters says
+                match query_with_retries(port, agent_id, lp, config, basic) {
+                    Ok(fallback) if extended_res.is_err() || !data_is_zero(&fallback.0) => {
+                        tracing::debug!(
+                            "LID {} doesn't answer PortCountersExtended, using PortCounters",
+                            lp.lid
+                        );
+                        decide(CountersQuery::Basic);
+                        Ok(fallback)
+                    }
+                    // An idle port reads zero either way; its extended answer is good
+                    Ok(_) => {
+                        decide(CountersQuery::Extended);
+                        extended_res
+                    }
+                    Err(_) => extended_res,
+                }
+            }
+        },
+    }
+    .ok()?;
+
+    let mut perfctrs: HashMap<String, u64> = HashMap::new();
+
+    // Counters
+    perfctrs.insert("xmt_bytes".to_string(), perf_mad.port_xmit_data());
+    perfctrs.insert("rcv_bytes".to_string(), perf_mad.port_rcv_data());
+    perfctrs.insert("xmit_waits".to_string(), perf_mad.port_xmit_wait());
+
+    // Errors
+    for (name, read) in PERF_ERROR_COUNTERS {
+        perfctrs.insert(name.to_string(), read(&perf_mad));
+    }
+
+    // Additional
+    perfctrs.insert("xmit_discards".to_string(), perf_mad.port_xmit_discards());
+    perfctrs.insert("xmit_pkts".to_string(), perf_mad.port_xmit_pkts());
+    perfctrs.insert("rcv_pkts".to_string(), perf_mad.port_rcv_pkts());
+
+    // Timestamps
+    perfctrs.insert(
+        "start_timestamp".to_string(),
+        start.timestamp_nanos_opt().unwrap_or(0) as u64,
+    );
+    perfctrs.insert(
+        "end_timestamp".to_string(),
+        end.timestamp_nanos_opt().unwrap_or(0) as u64,
+    );
+    if retries > 0 {
+        perfctrs.insert(QUERY_RETRIES_KEY.to_string(), retries as u64);
+    }
+
+    Some(perfctrs)
+}
+
+/// Send one counters query, retrying up to `--retries` times, or the last error if every
+/// attempt failed.
+fn query_with_retries(
+    port: &mut mad::Port,
+    agent_id: u32,
+    lp: &LidPort,
+    config: &AppConfig,
+    query: PerfQuery,
+) -> io::Result<PerfSample> {
+    // A pkey of 0 targets the default partition, so every LID is queried.
+    let pkey = config.pkey;
+
+    // Retry here rather than in the MAD layer so retries can be counted and backed off.
+    let mut retries = 0;
+    loop {
+        let start = Utc::now();
+        let res = query(
+            port,
+            agent_id,
+            config.timeout,
+            0,
+            lp.lid,
+            lp.number as u8,
+            pkey,
+        );
+        let end = Utc::now();
+
+        match res {
+            Ok(mad) => return Ok((mad, start, end, retries)),
+            Err(e) if retries < config.retries => {
+                retries += 1;
+                tracing::trace!(
+                    "Retrying counters query for LID {} Port {} ({retries}/{}): {e}",
+                    lp.lid,
+                    lp.number,
+                    config.retries
+                );
+                thread::sleep(config.retry_backoff(retries));
+            }
+            Err(e) => {
+                // Log the error but continue. With a partition configured, LIDs that are not
+                // members fail here too.
+                tracing::debug!(
+                    "Failed to query counters for LID {} Port {} (pkey 0x{:04x}): {e}",
+                    lp.lid,
+                    lp.number,
+                    pkey
+                );
+                return Err(e);
+            }
+        }
+    }
 }
 
 impl CountersService for IbmadCountersService {
-    fn get_counters(&self, lid_ports: Vec<LidPort>) -> HashMap<(u16, i32), HashMap<String, u64>> {
-        let hca_name = self.config.hca.clone();
-        let timeout = self.config.timeout;
-        let retries = self.config.retries;
-        let pkey = self.config.pkey;
-
-        // Get HCA (to create ports in threads)
-        let hca = match ibmad::ca::get_ca(&hca_name) {
+    fn get_counters(&self, lid_ports: Vec<LidPort>) -> CountersMap {
+        let config = &self.config;
+        let hca_name = &config.hca;
+
+        // Get HCA (to open ports in workers that don't have one yet)
+        let hca = match ibmad::ca::get_ca(hca_name) {
             Ok(ca) => ca,
             Err(e) => {
                 error!("Failed to get HCA '{}': {e}", hca_name);
@@ -299,107 +669,112 @@ impl CountersService for IbmadCountersService {
             }
         };
 
-        lid_ports
-            .into_par_iter()
-            .map_init(
-                || {
-                    // Init: open port and register agent per thread
-                    match ibmad::mad::open_port(&hca) {
-                        Ok(mut p) => {
-                            match ibmad::mad::register_agent(
-                                &mut p,
-                                ibmad::mad::IB_MGMT_CLASS_PERFORMANCE,
-                            ) {
-                                Ok(id) => Some((p, id)),
-                                Err(e) => {
-                                    error!("Failed to register perf agent in thread: {e}");
-                                    None
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to open MAD port in thread: {e}");
-                            None
+        let lid_queries = &self.lid_queries;
+        self.query_each(lid_ports, |lp| {
+            PERF_PORT.with_borrow_mut(|state| {
+                // Retried on every query until the port opens
+                if state.is_none() {
+                    *state = open_perf_port(&hca);
+                }
+                let (port, agent_id) = state.as_mut()?;
+                query_counters(port, *agent_id, lp, config, lid_queries)
+            })
+        })
+    }
+
+    fn reset_counters(&self, lid_ports: Vec<LidPort>) -> HashMap<CounterKey, bool> {
+        let config = &self.config;
+        let hca = match ibmad::ca::get_ca(&config.hca) {
+            Ok(ca) => ca,
+            Err(e) => {
+                error!("Failed to get HCA '{}': {e}", config.hca);
+                return lid_ports.into_iter().map(|lp| (lp.key(), false)).collect();
+            }
+        };
+        let pkey = config.pkey;
+
+        // Not retried: a reset that timed out may still have been applied
+        self.pool.install(|| {
+            lid_ports
+                .into_par_iter()
+                .map(|lp| {
+                    let reset = PERF_PORT.with_borrow_mut(|state| {
+                        if state.is_none() {
+                            *state = open_perf_port(&hca);
                         }
-                    }
-                },
-                |state, lp| {
-                    let (port, agent_id) = state.as_mut()?;
-
-                    let start = Utc::now();
-                    let res = mad::query_port_counters_extended(
-                        port,
-                        *agent_id,
-                        timeout,
-                        retries,
-                        lp.lid,
-                        lp.number as u8,
-                        pkey as u16
-                    );
-                    let end = Utc::now();
-
-                    let perf_mad = match res {
-                        Ok(mad) => mad,
-                        Err(e) => {
-                            // Log the error but continue
-                            tracing::debug!(
-                                "Failed to query counters for LID {} Port {}: {e}",
-                                lp.lid,
-                                lp.number
-                            );
-                            return None;
+                        let Some((port, agent_id)) = state.as_mut() else {
+                            return false;
+                        };
+                        match mad::reset_port_counters(
+                            port,
+                            *agent_id,
+                            config.timeout,
+                            0,
+                            lp.lid,
+                            lp.number as u8,
+                            pkey,
+                        ) {
+                            Ok(()) => true,
+                            Err(e) => {
+                                warn!(
+                                    "Failed to reset counters of LID {} Port {}: {e}",
+                                    lp.lid, lp.number
+                                );
+                                false
+                            }
                         }
-                    };
-
-                    let mut perfctrs: HashMap<String, u64> = HashMap::new();
-
-                    // Counters
-                    perfctrs.insert("xmt_bytes".to_string(), perf_mad.port_xmit_data());
-                    perfctrs.insert("rcv_bytes".to_string(), perf_mad.port_rcv_data());
-                    perfctrs.insert("xmit_waits".to_string(), perf_mad.port_xmit_wait());
+                    });
+                    (lp.key(), reset)
+                })
+                .collect()
+        })
+    }
+}
 
-                    // Errors
-                    perfctrs.insert("symbol_errors".to_string(), perf_mad.symbol_error_counter());
-                    perfctrs.insert(
-                        "link_recovers".to_string(),
-                        perf_mad.link_error_recovery_counter(),
-                    );
-                    perfctrs.insert("link_downed".to_string(), perf_mad.link_downed_counter());
-                    perfctrs.insert("rcv_errors".to_string(), perf_mad.port_rcv_errors());
-                    perfctrs.insert(
-                        "phys_rcv_errors".to_string(),
-                        perf_mad.port_rcv_remote_physical_errors(),
-                    );
-                    perfctrs.insert(
-                        "switch_rel_errors".to_string(),
-                        perf_mad.port_rcv_switch_relay_errors(),
-                    );
-                    perfctrs.insert(
-                        "excess_overrun_errors".to_string(),
-                        perf_mad.excessive_buffer_overrun_errors(),
-                    );
-                    perfctrs.insert("vl15dropped".to_string(), perf_mad.vl15_dropped());
-                    perfctrs.insert("qp1_drops".to_string(), perf_mad.qp1_dropped());
-
-                    // Additional
-                    perfctrs.insert("xmit_discards".to_string(), perf_mad.port_xmit_discards());
-                    perfctrs.insert("xmit_pkts".to_string(), perf_mad.port_xmit_pkts());
-                    perfctrs.insert("rcv_pkts".to_string(), perf_mad.port_rcv_pkts());
-
-                    // Timestamps
-                    perfctrs.insert(
-                        "start_timestamp".to_string(),
-                        start.timestamp_nanos_opt().unwrap_or(0) as u64,
-                    );
-                    perfctrs.insert(
-                        "end_timestamp".to_string(),
-                        end.timestamp_nanos_opt().unwrap_or(0) as u64,
-                    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn service(threads: usize) -> IbmadCountersService {
+        let (_, ev_ctr_rx) = mpsc::channel();
+        let (ctr_ev_tx, _) = mpsc::channel();
+        let config = AppConfig {
+            hca: "mlx5_0".into(),
+            threads,
+            ..AppConfig::default()
+        };
+        IbmadCountersService::new(ev_ctr_rx, ctr_ev_tx, config, Arc::new(AtomicU64::new(0)))
+            .unwrap()
+    }
 
-                    Some(((lp.lid, lp.number), perfctrs))
-                },
-            )
-            .filter_map(|x| x)
-            .collect()
+    #[test]
+    fn every_lid_port_is_queried_exactly_once() {
+        for (threads, count) in [(16, 17), (4, 1000), (1, 3)] {
+            let lid_ports: Vec<LidPort> = (1..=count)
+                .map(|lid| LidPort {
+                    lid,
+                    number: 255,
+                    hca: "mlx5_0".into(),
+                })
+                .collect();
+
+            let queried = Mutex::new(Vec::new());
+            let counters = service(threads).query_each(lid_ports, |lp| {
+                queried.lock().unwrap().push(lp.lid);
+                // Every other query fails, which still counts as queried
+                (lp.lid % 2 == 0).then(HashMap::new)
+            });
+
+            let mut queried = queried.into_inner().unwrap();
+            queried.sort_unstable();
+            assert_eq!(
+                queried,
+                (1..=count).collect::<Vec<_>>(),
+                "{threads} threads"
+            );
+            assert_eq!(counters.len(), count as usize);
+            assert!(counters[&("mlx5_0".to_string(), 1, 255)].contains_key(QUERY_FAILED_KEY));
+        }
     }
 }
@@ -1,13 +1,15 @@
 use std::{
     collections::HashMap,
     sync::mpsc::{Receiver, Sender},
-    time::Instant,
+    thread,
+    time::{Duration, Instant},
 };
 
 use super::ibmad::ERROR_COUNTERS;
 use crate::app::AppConfig;
 use chrono::Utc;
 use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
 
 pub enum ServiceType {
     RsMAD,
@@ -16,47 +18,241 @@ pub enum ServiceType {
 
 #[derive(Clone, Debug)]
 pub enum DiscoveryEvent {
-    Request,
-    Response(Vec<Node>),
-    Error,
+    /// Discover the fabric; everything sent back for the sweep carries the same request id
+    Request(RequestId),
+    Response(RequestId, Vec<Node>, DiscoveryStats),
+    /// Nodes from one HCA while others are still discovering (`--stream-discovery`)
+    Partial(Vec<Node>),
+    /// How far a discovery sweep in progress has got
+    Progress {
+        id: RequestId,
+        mads_sent: u64,
+        nodes_found: usize,
+    },
+    /// Problems that didn't stop discovery, e.g. skipped scope file lines; sent before the
+    /// response
+    Warnings(RequestId, Vec<String>),
+    Error(RequestId, String),
     Exit,
 }
 
+/// MAD statistics from a discovery sweep. Services that don't send MADs report zeros.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DiscoveryStats {
+    pub mads_sent: u64,
+    pub mad_errors: u64,
+    pub mad_timeouts: u64,
+}
+
+impl std::ops::AddAssign for DiscoveryStats {
+    fn add_assign(&mut self, other: Self) {
+        self.mads_sent += other.mads_sent;
+        self.mad_errors += other.mad_errors;
+        self.mad_timeouts += other.mad_timeouts;
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum CounterEvent {
-    Request(Vec<LidPort>),
-    Response(HashMap<(u16, i32), HashMap<String, u64>>),
-    Error,
+    /// Query these LID/ports; the response carries the same request id
+    Request(RequestId, Vec<LidPort>),
+    Response(RequestId, CountersMap),
+    /// Clear the counters of these LID/ports
+    Reset(Vec<LidPort>),
+    /// Whether each LID/port's counters were cleared
+    ResetResponse(HashMap<CounterKey, bool>),
+    Error(RequestId),
+    /// No response within the allowed time; raised by the event handler, not a service
+    Timeout(Duration),
+    /// Abandon the request in progress
+    Cancel,
     Exit,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Node {
     pub guid: u64,
     pub node_description: String,
     pub ports: Vec<Port>,
     pub lid: u16,
+    /// HCA the node was discovered through
+    #[serde(default)]
+    pub hca: String,
+    #[serde(default)]
+    pub node_type: NodeType,
 }
 
-#[derive(Clone, Debug)]
+impl Node {
+    /// Key of the counters of this node's `port`, as queried through its HCA.
+    pub fn counter_key(&self, port: i32) -> CounterKey {
+        (self.hca.clone(), self.lid, port)
+    }
+}
+
+/// Kind of a discovered node; HCAs are only listed with `--include-hcas`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum NodeType {
+    #[default]
+    Switch,
+    Hca,
+}
+
+impl NodeType {
+    pub fn name(self) -> &'static str {
+        match self {
+            NodeType::Switch => "Switch",
+            NodeType::Hca => "HCA",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Port {
     pub number: i32,
     pub remote_node_description: String,
+    /// GUID of the node at the other end of the link, if known
+    pub remote_guid: Option<u64>,
+    /// LID of the node at the other end of the link, if known
+    pub remote_lid: Option<u16>,
     pub link_state: String,
+    /// Physical port state (e.g. `LinkUp`, `Polling`, `Disabled`)
+    pub phys_state: String,
+    /// Active link width in lanes (1x, 2x, 4x, 8x, 12x), if known
+    pub link_width: Option<u8>,
+    /// Active per-lane link speed, if known
+    pub link_speed: Option<LinkSpeed>,
+}
+
+/// Summarized health of a port's link, used to style the details popup.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkStatus {
+    /// Link is up and the port is Active
+    Up,
+    /// Physical link is up but the logical port is not Active (Init/Armed)
+    Inactive,
+    /// Physical link is down
+    Down,
+    /// State was not reported (e.g. ports read from a scope file)
+    Unknown,
+}
+
+impl Port {
+    /// Classify the port from its logical and physical states.
+    pub fn link_status(&self) -> LinkStatus {
+        let known = |s: &str| !s.is_empty() && s != "Unknown";
+
+        if (known(&self.phys_state) && self.phys_state != "LinkUp") || self.link_state == "Down" {
+            LinkStatus::Down
+        } else if known(&self.link_state) && self.link_state != "Active" {
+            LinkStatus::Inactive
+        } else if known(&self.link_state) {
+            LinkStatus::Up
+        } else {
+            LinkStatus::Unknown
+        }
+    }
+
+    /// Negotiated line rate in Gbps (width × lane speed), if both are known.
+    pub fn line_rate_gbps(&self) -> Option<f64> {
+        Some(self.link_width? as f64 * self.link_speed?.lane_gbps())
+    }
+
+    /// Short link description such as `4x HDR`, or `-` when unknown.
+    pub fn link_label(&self) -> String {
+        match (self.link_width, self.link_speed) {
+            (Some(width), Some(speed)) => format!("{width}x {speed}"),
+            (Some(width), None) => format!("{width}x"),
+            (None, Some(speed)) => speed.to_string(),
+            (None, None) => "-".to_string(),
+        }
+    }
+}
+
+/// InfiniBand per-lane link speeds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LinkSpeed {
+    SDR,
+    DDR,
+    QDR,
+    FDR10,
+    FDR,
+    EDR,
+    HDR,
+    NDR,
+    XDR,
+}
+
+impl LinkSpeed {
+    /// Nominal (marketed) rate of a single lane in Gbps, e.g. 50 for HDR so 4x HDR is 200.
+    pub fn lane_gbps(&self) -> f64 {
+        match self {
+            LinkSpeed::SDR => 2.5,
+            LinkSpeed::DDR => 5.0,
+            LinkSpeed::QDR => 10.0,
+            LinkSpeed::FDR10 => 10.0,
+            LinkSpeed::FDR => 14.0,
+            LinkSpeed::EDR => 25.0,
+            LinkSpeed::HDR => 50.0,
+            LinkSpeed::NDR => 100.0,
+            LinkSpeed::XDR => 200.0,
+        }
+    }
+}
+
+impl std::fmt::Display for LinkSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct LidPort {
     pub lid: u16,
     pub number: i32,
+    /// HCA whose counters service should query this LID
+    pub hca: String,
 }
 
+impl LidPort {
+    /// Key of this LID/port's counters in a [`CountersMap`].
+    pub fn key(&self) -> CounterKey {
+        (self.hca.clone(), self.lid, self.number)
+    }
+}
+
+/// HCA, LID and port number of a LID/port's counters. LIDs are only unique within the subnet
+/// of one HCA, so the HCA the LID was queried through is part of the key.
+pub type CounterKey = (String, u16, i32);
+
+/// Numbers each discovery or counters request so late responses can be told apart from current ones
+pub type RequestId = u64;
+
+/// Counters of each queried LID/port.
+pub type CountersMap = HashMap<CounterKey, HashMap<String, u64>>;
+
+/// Key present (with value 1) in the counters of a LID/port whose query failed. Such
+/// entries carry no other counters.
+pub const QUERY_FAILED_KEY: &str = "query_failed";
+
+/// Whether `counters` is the placeholder for a failed query.
+pub fn is_query_failed(counters: &HashMap<String, u64>) -> bool {
+    counters.contains_key(QUERY_FAILED_KEY)
+}
+
+/// Key holding the number of retries a successful LID/port query needed; absent when the
+/// first attempt succeeded.
+pub const QUERY_RETRIES_KEY: &str = "query_retries";
+
 pub trait DiscoverService {
     fn get_nodes(&self) -> Vec<Node>;
 }
 
 pub trait CountersService {
-    fn get_counters(&self, nodes: Vec<LidPort>) -> HashMap<(u16, i32), HashMap<String, u64>>;
+    fn get_counters(&self, nodes: Vec<LidPort>) -> CountersMap;
+
+    /// Clear the counters of each LID/port; port 255 (`AGG_COUNTERS_PORT`) clears every port
+    /// of the node. Returns whether each reset succeeded.
+    fn reset_counters(&self, lid_ports: Vec<LidPort>) -> HashMap<CounterKey, bool>;
 }
 
 // Test services
@@ -66,18 +262,22 @@ pub struct TestDiscoverService {
     ev_disc_rx: Receiver<DiscoveryEvent>,
     disc_ev_tx: Sender<DiscoveryEvent>,
     ports_per_node: usize,
+    hca: String,
+    include_hcas: bool,
 }
 
 impl TestDiscoverService {
     pub fn new(
         ev_disc_rx: Receiver<DiscoveryEvent>,
         disc_ev_tx: Sender<DiscoveryEvent>,
-        _config: AppConfig,
+        config: AppConfig,
     ) -> Self {
         Self {
             ev_disc_rx,
             disc_ev_tx,
             ports_per_node: 64,
+            hca: config.hca,
+            include_hcas: config.include_hcas,
         }
     }
     pub fn run(self) -> color_eyre::Result<()> {
@@ -85,14 +285,31 @@ impl TestDiscoverService {
             match self.ev_disc_rx.recv() {
                 Ok(ev) => match ev {
                     DiscoveryEvent::Exit => return Ok(()),
-                    DiscoveryEvent::Request => {
-                        let _ = self
-                            .disc_ev_tx
-                            .send(DiscoveryEvent::Response(self.get_nodes()));
+                    DiscoveryEvent::Request(id) => {
+                        let nodes = self.get_nodes();
+
+                        // Pretend the sweep takes a moment so progress can be seen
+                        const STEPS: usize = 10;
+                        for step in 1..=STEPS {
+                            thread::sleep(Duration::from_millis(30));
+                            let nodes_found = nodes.len() * step / STEPS;
+                            let _ = self.disc_ev_tx.send(DiscoveryEvent::Progress {
+                                id,
+                                mads_sent: (nodes_found * self.ports_per_node) as u64,
+                                nodes_found,
+                            });
+                        }
+
+                        let _ = self.disc_ev_tx.send(DiscoveryEvent::Response(
+                            id,
+                            nodes,
+                            DiscoveryStats::default(),
+                        ));
                     }
                     _ => {}
                 },
-                Err(_e) => {}
+                // The app is gone; nothing more will be asked
+                Err(_) => return Ok(()),
             }
         }
     }
@@ -102,22 +319,61 @@ impl DiscoverService for TestDiscoverService {
     fn get_nodes(&self) -> Vec<Node> {
         let mut nodes = Vec::new();
 
-        // Create a handful of switches with sequential LIDs.
+        // Create a handful of switches with sequential LIDs. With `--include-hcas`, every
+        // tenth node is a single-port HCA so mixed fabrics can be tried out.
         for i in 1..=1600 {
+            let node_type = if self.include_hcas && i % 10 == 0 {
+                NodeType::Hca
+            } else {
+                NodeType::Switch
+            };
+            let port_count = match node_type {
+                NodeType::Hca => 1,
+                NodeType::Switch => self.ports_per_node,
+            };
             let mut ports: Vec<Port> = Vec::new();
-            for port_num in 0..self.ports_per_node {
+            for port_num in 0..port_count {
+                // Each port links to a neighbouring switch, except down ones.
+                let remote = (i as u64 + port_num as u64 % 8) % 1600 + 1;
+                // Mix in a few slower links so the LINK column has some variety.
+                let (link_width, link_speed) = match port_num % 16 {
+                    0 => (2, LinkSpeed::NDR),
+                    1 => (4, LinkSpeed::EDR),
+                    _ => (4, LinkSpeed::HDR),
+                };
+                // Mark a couple of ports as down or stuck in Init.
+                let (link_state, phys_state) = match port_num {
+                    3 => ("Down", "Polling"),
+                    7 => ("Init", "LinkUp"),
+                    _ => ("Active", "LinkUp"),
+                };
+                let linked = link_state != "Down";
                 ports.push(Port {
                     number: port_num as i32,
-                    remote_node_description: "".to_string(),
-                    link_state: "Active".to_string(),
+                    remote_node_description: if linked {
+                        format!("switch-{remote}")
+                    } else {
+                        String::new()
+                    },
+                    remote_guid: linked.then_some(remote),
+                    remote_lid: linked.then_some(16 + remote as u16),
+                    link_state: link_state.to_string(),
+                    phys_state: phys_state.to_string(),
+                    link_width: Some(link_width),
+                    link_speed: Some(link_speed),
                 });
             }
 
             nodes.push(Node {
                 guid: i as u64,
-                node_description: format!("switch-{i}"),
+                node_description: match node_type {
+                    NodeType::Hca => format!("host-{i} HCA-1"),
+                    NodeType::Switch => format!("switch-{i}"),
+                },
                 ports,
                 lid: 16 + i as u16,
+                hca: self.hca.clone(),
+                node_type,
             });
         }
 
@@ -149,23 +405,29 @@ impl TestCountersService {
             match self.ev_ctr_rx.recv() {
                 Ok(ev) => match ev {
                     CounterEvent::Exit => return Ok(()),
-                    CounterEvent::Request(lid_ports) => {
+                    CounterEvent::Request(id, lid_ports) => {
+                        let _ = self
+                            .ctr_ev_tx
+                            .send(CounterEvent::Response(id, self.get_counters(lid_ports)));
+                    }
+                    CounterEvent::Reset(lid_ports) => {
                         let _ = self
                             .ctr_ev_tx
-                            .send(CounterEvent::Response(self.get_counters(lid_ports)));
+                            .send(CounterEvent::ResetResponse(self.reset_counters(lid_ports)));
                     }
                     _ => {}
                 },
-                Err(_e) => {}
+                // The app is gone; nothing more will be asked
+                Err(_) => return Ok(()),
             }
         }
     }
 }
 
 impl CountersService for TestCountersService {
-    fn get_counters(&self, lid_ports: Vec<LidPort>) -> HashMap<(u16, i32), HashMap<String, u64>> {
+    fn get_counters(&self, lid_ports: Vec<LidPort>) -> CountersMap {
         let mut rng = StdRng::from_entropy();
-        let mut counters: HashMap<(u16, i32), HashMap<String, u64>> = HashMap::new();
+        let mut counters: CountersMap = HashMap::new();
 
         // Calculate a base value using the elapsed time since service start.
         let elapsed = self.start.elapsed().as_secs();
@@ -208,9 +470,76 @@ impl CountersService for TestCountersService {
                 })
                 .collect();
 
-            counters.insert((lp.lid, lp.number), node_counters);
+            counters.insert(lp.key(), node_counters);
         }
 
         counters
     }
+
+    /// Simulated counters follow the clock and can't be cleared; every reset is reported
+    /// as done so the prompt can be tried without a fabric.
+    fn reset_counters(&self, lid_ports: Vec<LidPort>) -> HashMap<CounterKey, bool> {
+        lid_ports.into_iter().map(|lp| (lp.key(), true)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        record::Recorder,
+        services::{ibmad::PERF_ERROR_COUNTERS, replay::ReplayCountersService},
+    };
+    use std::sync::mpsc;
+
+    #[test]
+    fn every_counters_service_reports_each_error_counter() {
+        let lp = LidPort {
+            lid: 5,
+            number: 1,
+            hca: "mlx5_0".into(),
+        };
+
+        // ibmad reads one PerfMad field per error counter
+        let ibmad: Vec<&str> = PERF_ERROR_COUNTERS.iter().map(|&(name, _)| name).collect();
+        assert_eq!(ibmad, ERROR_COUNTERS);
+
+        let (_, ev_ctr_rx) = mpsc::channel();
+        let (ctr_ev_tx, _) = mpsc::channel();
+        let simulated = TestCountersService::new(ev_ctr_rx, ctr_ev_tx, AppConfig::default())
+            .get_counters(vec![lp.clone()]);
+
+        // Replay answers with what was recorded, here the simulated counters
+        let path = std::env::temp_dir().join(format!("ibtop-{}-errors.jsonl", std::process::id()));
+        let path_str = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+        Recorder::open(&path_str)
+            .unwrap()
+            .record_counters(&simulated)
+            .unwrap();
+        let (req_tx, ev_ctr_rx) = mpsc::channel();
+        let (ctr_ev_tx, resp_rx) = mpsc::channel();
+        req_tx
+            .send(CounterEvent::Request(1, vec![lp.clone()]))
+            .unwrap();
+        req_tx.send(CounterEvent::Exit).unwrap();
+        let config = AppConfig {
+            replay_file: Some(path_str),
+            ..AppConfig::default()
+        };
+        ReplayCountersService::new(ev_ctr_rx, ctr_ev_tx, config)
+            .run()
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        let Ok(CounterEvent::Response(1, replayed)) = resp_rx.try_recv() else {
+            panic!("expected a replayed response");
+        };
+
+        for (service, counters) in [("test", &simulated), ("replay", &replayed)] {
+            let ctrs = &counters[&lp.key()];
+            for name in ERROR_COUNTERS {
+                assert!(ctrs.contains_key(name), "{service} service lacks {name}");
+            }
+        }
+    }
 }
@@ -1,2 +1,4 @@
 pub mod ibmad;
 pub mod lib;
+pub mod replay;
+pub mod scope;
@@ -0,0 +1,244 @@
+use super::lib::{CounterEvent, CountersMap, DiscoveryEvent, DiscoveryStats, LidPort, Node};
+use crate::{
+    app::AppConfig,
+    record::{RecordEntry, read_recording},
+};
+use chrono::{DateTime, Utc};
+use std::{
+    sync::mpsc::{Receiver, Sender},
+    thread,
+    time::Instant,
+};
+use tracing::{error, warn};
+
+/// A recorded counters response and when it was recorded.
+type CounterSample = (DateTime<Utc>, CountersMap);
+
+/// Load a recording, describing any failure for the status line.
+fn load_recording(config: &AppConfig) -> Result<Vec<RecordEntry>, String> {
+    let path = match &config.replay_file {
+        Some(p) => p,
+        None => return Err("no replay file provided (use --replay-file)".to_string()),
+    };
+
+    read_recording(path).map_err(|e| format!("cannot read replay file '{path}': {e}"))
+}
+
+// Replay Discovery Service
+//
+// Answers each discovery request with the next node set from the `--replay-file` recording,
+// repeating the last one once the recording is exhausted.
+pub struct ReplayDiscoveryService {
+    ev_disc_rx: Receiver<DiscoveryEvent>,
+    disc_ev_tx: Sender<DiscoveryEvent>,
+    config: AppConfig,
+}
+
+impl ReplayDiscoveryService {
+    pub fn new(
+        ev_disc_rx: Receiver<DiscoveryEvent>,
+        disc_ev_tx: Sender<DiscoveryEvent>,
+        config: AppConfig,
+    ) -> Self {
+        Self {
+            ev_disc_rx,
+            disc_ev_tx,
+            config,
+        }
+    }
+
+    pub fn run(self) -> color_eyre::Result<()> {
+        let node_sets: Result<Vec<Vec<Node>>, String> =
+            load_recording(&self.config).map(|entries| {
+                entries
+                    .into_iter()
+                    .filter_map(|entry| match entry {
+                        RecordEntry::Nodes { nodes, .. } => Some(nodes),
+                        RecordEntry::Counters { .. } => None,
+                    })
+                    .collect()
+            });
+        let mut next = 0;
+
+        loop {
+            match self.ev_disc_rx.recv() {
+                Ok(ev) => match ev {
+                    DiscoveryEvent::Exit => return Ok(()),
+                    DiscoveryEvent::Request(id) => {
+                        let response = match &node_sets {
+                            Ok(sets) if sets.is_empty() => DiscoveryEvent::Error(
+                                id,
+                                "replay file contains no discovery results".to_string(),
+                            ),
+                            Ok(sets) => {
+                                let nodes = sets[next.min(sets.len() - 1)].clone();
+                                next += 1;
+                                DiscoveryEvent::Response(id, nodes, DiscoveryStats::default())
+                            }
+                            Err(msg) => DiscoveryEvent::Error(id, msg.clone()),
+                        };
+                        if let Err(e) = self.disc_ev_tx.send(response) {
+                            error!("Failed to send discovery response: {e}");
+                        }
+                    }
+                    _ => {
+                        warn!("Received unexpected DiscoveryEvent: {ev:?}");
+                    }
+                },
+                Err(e) => {
+                    error!("ReplayDiscoveryService channel closed: {e}");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+// Replay Counters Service
+//
+// Answers each counters request with the next recorded sample, restricted to the requested
+// LID/ports. Unless `--replay-fast` is set, a response is held back until as much time has
+// passed since the previous one as separated the two samples when they were recorded.
+pub struct ReplayCountersService {
+    ev_ctr_rx: Receiver<CounterEvent>,
+    ctr_ev_tx: Sender<CounterEvent>,
+    config: AppConfig,
+}
+
+impl ReplayCountersService {
+    pub fn new(
+        ev_ctr_rx: Receiver<CounterEvent>,
+        ctr_ev_tx: Sender<CounterEvent>,
+        config: AppConfig,
+    ) -> Self {
+        Self {
+            ev_ctr_rx,
+            ctr_ev_tx,
+            config,
+        }
+    }
+
+    pub fn run(self) -> color_eyre::Result<()> {
+        let samples: Vec<CounterSample> = match load_recording(&self.config) {
+            Ok(entries) => entries
+                .into_iter()
+                .filter_map(|entry| match entry {
+                    RecordEntry::Counters {
+                        timestamp,
+                        counters,
+                    } => Some((
+                        timestamp,
+                        counters
+                            .into_iter()
+                            .map(|c| ((c.hca, c.lid, c.port), c.counters))
+                            .collect(),
+                    )),
+                    RecordEntry::Nodes { .. } => None,
+                })
+                .collect(),
+            Err(msg) => {
+                error!("{msg}");
+                Vec::new()
+            }
+        };
+        let mut samples = samples.into_iter();
+        let mut previous: Option<(DateTime<Utc>, Instant)> = None;
+
+        loop {
+            match self.ev_ctr_rx.recv() {
+                Ok(ev) => match ev {
+                    CounterEvent::Exit => return Ok(()),
+                    CounterEvent::Request(id, lid_ports) => {
+                        let Some((timestamp, counters)) = samples.next() else {
+                            warn!("Replay finished, no more counter samples");
+                            let _ = self.ctr_ev_tx.send(CounterEvent::Error(id));
+                            continue;
+                        };
+
+                        if !self.config.replay_fast
+                            && let Some((prev_timestamp, prev_sent)) = previous
+                            && let Ok(gap) = (timestamp - prev_timestamp).to_std()
+                        {
+                            thread::sleep(gap.saturating_sub(prev_sent.elapsed()));
+                        }
+                        previous = Some((timestamp, Instant::now()));
+
+                        let response =
+                            CounterEvent::Response(id, select_counters(counters, &lid_ports));
+                        if let Err(e) = self.ctr_ev_tx.send(response) {
+                            error!("Failed to send counters response: {e}");
+                        }
+                    }
+                    // A recording can't be changed
+                    CounterEvent::Reset(lid_ports) => {
+                        let results = lid_ports.into_iter().map(|lp| (lp.key(), false)).collect();
+                        let _ = self.ctr_ev_tx.send(CounterEvent::ResetResponse(results));
+                    }
+                    _ => {
+                        warn!("Received unexpected CounterEvent: {ev:?}");
+                    }
+                },
+                Err(e) => {
+                    error!("ReplayCountersService channel closed: {e}");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Keep only the recorded counters for the requested LID/ports. Recordings made before
+/// counters carried their HCA match on LID/port alone.
+fn select_counters(mut counters: CountersMap, lid_ports: &[LidPort]) -> CountersMap {
+    lid_ports
+        .iter()
+        .filter_map(|lp| {
+            let ctrs = counters
+                .remove(&lp.key())
+                .or_else(|| counters.remove(&(String::new(), lp.lid, lp.number)))?;
+            Some((lp.key(), ctrs))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn lid_port(lid: u16) -> LidPort {
+        LidPort {
+            lid,
+            number: 1,
+            hca: "mlx5_0".into(),
+        }
+    }
+
+    #[test]
+    fn only_requested_lid_ports_are_replayed() {
+        let recorded: CountersMap = [5, 6, 7]
+            .into_iter()
+            .map(|lid| {
+                (
+                    lid_port(lid).key(),
+                    HashMap::from([("rcv_bytes".into(), lid as u64)]),
+                )
+            })
+            .collect();
+        let selected = select_counters(recorded, &[lid_port(5), lid_port(7), lid_port(9)]);
+
+        let mut lids: Vec<u16> = selected.keys().map(|(_, lid, _)| *lid).collect();
+        lids.sort_unstable();
+        assert_eq!(lids, [5, 7]);
+    }
+
+    #[test]
+    fn counters_recorded_without_hca_match_any() {
+        let recorded: CountersMap = HashMap::from([(
+            (String::new(), 5, 1),
+            HashMap::from([("rcv_bytes".into(), 1)]),
+        )]);
+        let selected = select_counters(recorded, &[lid_port(5)]);
+        assert!(selected.contains_key(&lid_port(5).key()));
+    }
+}
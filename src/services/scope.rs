@@ -0,0 +1,93 @@
+use super::lib::{DiscoverService, DiscoveryEvent, DiscoveryStats, Node};
+use crate::{
+    app::AppConfig,
+    scope::{ScopeIssue, read_scope_file},
+};
+use std::sync::mpsc::{Receiver, Sender};
+use tracing::{error, warn};
+
+// Scope Discovery Service
+//
+// Instead of walking the fabric, the node set is read from the file passed on `--scope-file`.
+pub struct ScopeDiscoveryService {
+    ev_disc_rx: Receiver<DiscoveryEvent>,
+    disc_ev_tx: Sender<DiscoveryEvent>,
+    config: AppConfig,
+}
+
+impl ScopeDiscoveryService {
+    pub fn new(
+        ev_disc_rx: Receiver<DiscoveryEvent>,
+        disc_ev_tx: Sender<DiscoveryEvent>,
+        config: AppConfig,
+    ) -> Self {
+        Self {
+            ev_disc_rx,
+            disc_ev_tx,
+            config,
+        }
+    }
+
+    pub fn run(self) -> color_eyre::Result<()> {
+        loop {
+            match self.ev_disc_rx.recv() {
+                Ok(ev) => match ev {
+                    DiscoveryEvent::Exit => return Ok(()),
+                    DiscoveryEvent::Request(id) => {
+                        let response = match self.load_scope_file() {
+                            Ok((nodes, issues)) => {
+                                if !issues.is_empty() {
+                                    let warnings =
+                                        issues.iter().map(ScopeIssue::to_string).collect();
+                                    let _ = self
+                                        .disc_ev_tx
+                                        .send(DiscoveryEvent::Warnings(id, warnings));
+                                }
+                                DiscoveryEvent::Response(id, nodes, DiscoveryStats::default())
+                            }
+                            Err(msg) => DiscoveryEvent::Error(id, msg),
+                        };
+                        if let Err(e) = self.disc_ev_tx.send(response) {
+                            error!("Failed to send discovery response: {e}");
+                        }
+                    }
+                    _ => {
+                        warn!("Received unexpected DiscoveryEvent: {ev:?}");
+                    }
+                },
+                Err(e) => {
+                    error!("ScopeDiscoveryService channel closed: {e}");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Read the configured scope file and the lines it skipped, describing any failure for
+    /// the status line.
+    fn load_scope_file(&self) -> Result<(Vec<Node>, Vec<ScopeIssue>), String> {
+        let path = match &self.config.scope_file {
+            Some(p) => p,
+            None => return Err("no scope file provided (use --scope-file)".to_string()),
+        };
+
+        let (mut nodes, issues) =
+            read_scope_file(path).map_err(|e| format!("cannot read scope file '{path}': {e}"))?;
+        for node in &mut nodes {
+            node.hca = self.config.hca.clone();
+        }
+        Ok((nodes, issues))
+    }
+}
+
+impl DiscoverService for ScopeDiscoveryService {
+    fn get_nodes(&self) -> Vec<Node> {
+        match self.load_scope_file() {
+            Ok((nodes, _)) => nodes,
+            Err(e) => {
+                error!("{e}");
+                Vec::new()
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{CounterMode, DEFAULT_METRIC_COUNTER, MainColumn},
+    services::lib::NodeType,
+    ui::forms::SearchMode,
+};
+
+/// View settings saved on quit and restored on the next start (unless `--no-persist`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistedState {
+    pub sort_column: i32,
+    pub sort_ascending: bool,
+    pub secondary_sort_column: i32,
+    pub secondary_sort_ascending: bool,
+    pub counter_mode: CounterMode,
+    pub auto_update: bool,
+    pub auto_update_interval: usize,
+    pub search: String,
+    pub search_mode: SearchMode,
+    pub node_type_filter: Option<NodeType>,
+    pub columns: Option<Vec<MainColumn>>,
+    pub metric_counter: String,
+}
+
+impl Default for PersistedState {
+    fn default() -> Self {
+        Self {
+            sort_column: 0,
+            sort_ascending: false,
+            secondary_sort_column: 0,
+            secondary_sort_ascending: false,
+            counter_mode: CounterMode::Whole,
+            auto_update: false,
+            auto_update_interval: 2,
+            search: String::new(),
+            search_mode: SearchMode::Regex,
+            node_type_filter: None,
+            columns: None,
+            metric_counter: DEFAULT_METRIC_COUNTER.to_string(),
+        }
+    }
+}
+
+/// ibtop's configuration directory: `$XDG_CONFIG_HOME/ibtop`, falling back to
+/// `~/.config/ibtop`.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+
+    Some(config_dir.join("ibtop"))
+}
+
+/// Location of the state file: `state.toml` in [`config_dir`].
+pub fn state_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("state.toml"))
+}
+
+/// Load the saved state. Missing or corrupt files yield `None` so callers keep their defaults.
+pub fn load_state() -> Option<PersistedState> {
+    let path = state_path()?;
+    let contents = fs::read_to_string(&path).ok()?;
+    match toml::from_str(&contents) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            tracing::warn!("Ignoring corrupt state file {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Write the state file, creating its directory if needed.
+pub fn save_state(state: &PersistedState) -> io::Result<()> {
+    let path = state_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let contents = toml::to_string(state).map_err(io::Error::other)?;
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_survives_a_round_trip() {
+        let state = PersistedState {
+            sort_column: 4,
+            sort_ascending: true,
+            counter_mode: CounterMode::Rate,
+            auto_update: true,
+            search: "spine|leaf".into(),
+            search_mode: SearchMode::Fuzzy,
+            node_type_filter: Some(NodeType::Hca),
+            columns: Some(vec![MainColumn::Lid, MainColumn::Node]),
+            ..PersistedState::default()
+        };
+        let contents = toml::to_string(&state).unwrap();
+        assert_eq!(toml::from_str::<PersistedState>(&contents).unwrap(), state);
+    }
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        let state: PersistedState = toml::from_str("sort_column = 2\n").unwrap();
+        assert_eq!(
+            state,
+            PersistedState {
+                sort_column: 2,
+                ..PersistedState::default()
+            }
+        );
+    }
+}
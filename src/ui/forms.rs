@@ -1,36 +1,209 @@
-use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::{
     buffer::Buffer,
-    layout::{Constraint, Offset, Rect},
-    style::{Color, Modifier, Style},
-    widgets::{Block, Borders, Cell, Paragraph, Row, Table, Widget},
+    layout::{Offset, Rect},
+    style::{Color, Stylize},
+    text::Line,
+    widgets::{Block, Borders, Paragraph, Widget},
 };
 
-use crate::ui::helpers::compute_column_widths;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// How the search value is matched against node descriptions, GUIDs and LIDs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// The value is a regular expression
+    #[default]
+    Regex,
+    /// The value is matched literally as a substring
+    Plain,
+    /// The value's characters must appear in order; rows are ranked by match quality
+    Fuzzy,
+}
+
+impl SearchMode {
+    /// The mode `Tab` switches to next.
+    pub fn toggle(self) -> Self {
+        match self {
+            SearchMode::Regex => SearchMode::Plain,
+            SearchMode::Plain => SearchMode::Fuzzy,
+            SearchMode::Fuzzy => SearchMode::Regex,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SearchMode::Regex => "regex",
+            SearchMode::Plain => "plain",
+            SearchMode::Fuzzy => "fuzzy",
+        }
+    }
+}
+
+/// Score how well `query` fuzzy-matches `candidate`, ignoring case, or `None` if the query's
+/// characters don't all appear in order.
+///
+/// Each matched character scores a point, with bonuses for runs of consecutive characters
+/// and for matches at the start of a word, and a small penalty for skipped characters.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+
+    let mut score = 0;
+    let mut pos = 0;
+    let mut last_match: Option<usize> = None;
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let skipped = candidate.get(pos..)?.iter().position(|&c| c == q)?;
+        let idx = pos + skipped;
+
+        score += 1;
+        if last_match.is_some_and(|last| last + 1 == idx) {
+            score += 5;
+        }
+        if idx == 0 || !candidate[idx - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= skipped.min(5) as i64;
+
+        last_match = Some(idx);
+        pos = idx + 1;
+    }
+    Some(score)
+}
+
+/// What the search value is matched against, picked by a `desc:`, `guid:` or `lid:` prefix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchField {
+    /// Description, hex GUID or decimal LID
+    #[default]
+    Any,
+    Description,
+    Guid,
+    Lid,
+}
+
+impl SearchField {
+    const PREFIXES: [(&'static str, SearchField); 3] = [
+        ("desc:", SearchField::Description),
+        ("guid:", SearchField::Guid),
+        ("lid:", SearchField::Lid),
+    ];
+
+    /// Split a field prefix off `value`, if it starts with one.
+    fn parse(value: &str) -> (Self, &str) {
+        Self::PREFIXES
+            .iter()
+            .find_map(|&(prefix, field)| {
+                value
+                    .get(..prefix.len())
+                    .filter(|p| p.eq_ignore_ascii_case(prefix))
+                    .map(|_| (field, &value[prefix.len()..]))
+            })
+            .unwrap_or((SearchField::Any, value))
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SearchField::Any => "any",
+            SearchField::Description => "desc",
+            SearchField::Guid => "guid",
+            SearchField::Lid => "lid",
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct SearchForm {
     pub label: &'static str,
     pub value: String,
+    pub mode: SearchMode,
+    /// Compiled filter, rebuilt only when the value or mode changes. While the value doesn't
+    /// compile, the last valid filter stays applied.
+    filter: Regex,
+    /// Field the last valid filter applies to
+    field: SearchField,
+    /// Why the current value doesn't compile
+    pub error: Option<String>,
 }
 
 impl SearchForm {
-    pub const fn new(label: &'static str) -> Self {
+    pub fn new(label: &'static str) -> Self {
         Self {
             label,
             value: String::new(),
+            mode: SearchMode::Regex,
+            filter: Regex::new("").unwrap(),
+            field: SearchField::Any,
+            error: None,
         }
     }
 
+    /// Case-insensitive regex for the last valid value.
+    pub fn regex(&self) -> &Regex {
+        &self.filter
+    }
+
+    /// Field the filter applies to, from the value's prefix.
+    pub fn field(&self) -> SearchField {
+        self.field
+    }
+
+    /// The query to rank rows by in fuzzy mode, without its field prefix. `None` in the
+    /// other modes or when the query is empty.
+    pub fn fuzzy_query(&self) -> Option<&str> {
+        if self.mode != SearchMode::Fuzzy {
+            return None;
+        }
+        Some(SearchField::parse(&self.value).1).filter(|query| !query.is_empty())
+    }
+
+    /// Recompile the value after it or the mode changed. If it doesn't compile, the previous
+    /// filter stays in effect and `error` describes the problem.
+    pub fn update_filter(&mut self) {
+        let (field, value) = SearchField::parse(&self.value);
+        // In plain mode the value is escaped so it is matched literally; fuzzy matching
+        // doesn't use the regex, so it matches everything.
+        let pattern = match self.mode {
+            SearchMode::Regex => value.to_string(),
+            SearchMode::Plain => regex::escape(value),
+            SearchMode::Fuzzy => String::new(),
+        };
+
+        match build_regex(&pattern) {
+            Ok(re) => {
+                self.filter = re;
+                self.field = field;
+                self.error = None;
+            }
+            Err(e) => {
+                // Parse errors span several lines; the last one names the problem.
+                let msg = e.to_string();
+                self.error = Some(msg.lines().last().unwrap_or_default().trim().to_string());
+            }
+        }
+    }
+
+    /// Empty the value, removing the filter.
+    pub fn clear(&mut self) {
+        self.value.clear();
+        self.update_filter();
+    }
+
     /// Handle input events for the string input.
     pub fn on_key_press(&mut self, event: KeyEvent) {
         match event.code {
+            KeyCode::Char('u') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.value.clear()
+            }
+            KeyCode::Tab => self.mode = self.mode.toggle(),
             KeyCode::Char(c) => self.value.push(c),
             KeyCode::Backspace => {
                 self.value.pop();
             }
-            _ => {}
+            _ => return,
         }
+        self.update_filter();
     }
 
     pub fn cursor_offset(&self) -> Offset {
@@ -39,9 +212,24 @@ impl SearchForm {
     }
 }
 
-impl Widget for &SearchForm {
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        let block = Block::new().title(self.label).borders(Borders::ALL);
+fn build_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+impl SearchForm {
+    /// Draw the input, titled with how many of `total` nodes the filter keeps (`matched`).
+    pub fn render(&self, area: Rect, buf: &mut Buffer, matched: usize, total: usize) {
+        let title = format!(
+            "{} ({}, {}, Tab = toggle) - {matched} / {total} matches",
+            self.label,
+            self.mode.label(),
+            self.field.label()
+        );
+        let mut block = Block::new().title(title).borders(Borders::ALL);
+        block = match &self.error {
+            Some(error) => block.title_bottom(Line::from(format!(" {error} ")).fg(Color::Red)),
+            None => block.title_bottom(" desc:, guid: or lid: to match one field, Ctrl-U = clear "),
+        };
 
         let paragraph = Paragraph::new(self.value.clone()).block(block);
 
@@ -49,67 +237,44 @@ impl Widget for &SearchForm {
     }
 }
 
-// Description Form
-#[derive(Debug)]
-pub struct NodeDetailsForm {
-    pub label: &'static str,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl NodeDetailsForm {
-    pub const fn new(label: &'static str) -> Self {
-        Self { label }
+    fn type_str(form: &mut SearchForm, s: &str) {
+        for c in s.chars() {
+            form.on_key_press(KeyEvent::from(KeyCode::Char(c)));
+        }
     }
 
-    /// Handle input events for the string input.
-    pub fn on_key_press(&mut self, _event: KeyEvent) {}
+    #[test]
+    fn broken_regex_keeps_the_last_valid_filter() {
+        let mut form = SearchForm::new("Search");
+        type_str(&mut form, "leaf");
+        assert_eq!(form.regex().as_str(), "leaf");
+        assert!(form.error.is_none());
 
-    pub fn cursor_offset(&self) -> Offset {
-        Offset { x: 0, y: 0 }
-    }
-}
+        type_str(&mut form, "[");
+        assert_eq!(form.regex().as_str(), "leaf");
+        assert!(form.error.is_some());
 
-impl Widget for &NodeDetailsForm {
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        let block = Block::new().title(self.label).borders(Borders::ALL);
-
-        let inner_area = block.inner(area);
-
-        let column_ratios = [0.04, 0.32, 0.04, 0.12, 0.12, 0.12, 0.12, 0.12];
-        let widths = compute_column_widths(inner_area.width, &column_ratios);
-
-        let header_cells = vec![
-            Cell::from(format!("LID")),
-            Cell::from(format!("NODE")),
-            Cell::from(format!("PT")),
-            Cell::from(format!("RECV_BW")),
-            Cell::from(format!("SEND_BW")),
-            Cell::from(format!("BW_LOSS")),
-            Cell::from(format!("ERR_CNT")),
-            Cell::from(format!("ERR_STR")),
-        ];
-
-        let header = Row::new(header_cells).style(
-            Style::default()
-                .fg(Color::Black)
-                .bg(Color::White)
-                .add_modifier(Modifier::BOLD),
-        );
-
-        let constraints = [
-            Constraint::Length(widths[0] as u16),
-            Constraint::Length(widths[1] as u16),
-            Constraint::Length(widths[2] as u16),
-            Constraint::Length(widths[3] as u16),
-            Constraint::Length(widths[4] as u16),
-            Constraint::Length(widths[5] as u16),
-            Constraint::Length(widths[6] as u16),
-            Constraint::Length(widths[7] as u16),
-        ];
+        form.on_key_press(KeyEvent::from(KeyCode::Backspace));
+        assert!(form.error.is_none());
+    }
 
-        let table = Table::new(vec![header.clone()], constraints).header(header);
+    #[test]
+    fn filter_is_only_rebuilt_on_edits() {
+        let mut form = SearchForm::new("Search");
+        type_str(&mut form, "leaf");
 
-        table.render(inner_area, buf);
+        // Reading the filter doesn't compile the value again
+        form.value = "spine".into();
+        assert_eq!(form.regex().as_str(), "leaf");
+        // Neither do keys that don't edit it
+        form.on_key_press(KeyEvent::from(KeyCode::Left));
+        assert_eq!(form.regex().as_str(), "leaf");
 
-        block.render(area, buf);
+        form.on_key_press(KeyEvent::from(KeyCode::Char('s')));
+        assert_eq!(form.regex().as_str(), "spines");
     }
 }
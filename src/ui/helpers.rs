@@ -2,19 +2,33 @@ use std::collections::HashMap;
 
 use ratatui::layout::Rect;
 
-use crate::{app::CounterMode, services};
+use crate::{
+    app::{CounterMode, RateUnits},
+    services::lib::{LinkStatus, Port},
+};
 
+/// Truncate `s` to at most `max_width` characters, appending an ellipsis when truncated.
 pub(crate) fn truncate_fit(s: &str, max_width: usize) -> String {
-    // Use character boundaries to avoid panics on UTF-8 slicing.
-    // This does not perfectly match terminal cell width, but is safe and sufficient.
-    let char_count = s.chars().count();
-    if char_count > max_width {
-        let keep = max_width.saturating_sub(1);
-        let truncated: String = s.chars().take(keep).collect();
-        format!("{truncated}…")
-    } else {
-        s.to_string()
+    // Slice on char boundaries to avoid panics on multibyte UTF-8 (e.g. vendor names).
+    // Width is counted in chars, which does not perfectly match terminal cell width
+    // but is safe and sufficient.
+    if max_width == 0 {
+        return String::new();
+    }
+
+    let keep = max_width - 1;
+    let mut chars = s.char_indices();
+    let cut = match chars.nth(keep) {
+        Some((idx, _)) => idx,
+        None => return s.to_string(),
+    };
+
+    // Exactly `max_width` chars fit without truncation.
+    if chars.next().is_none() {
+        return s.to_string();
     }
+
+    format!("{}…", &s[..cut])
 }
 
 /// Calculate column widths based on ratios.
@@ -52,61 +66,314 @@ pub(crate) fn centered_rect_percent(
     (x, y, popup_width, popup_height)
 }
 
+/// Key under which the delta calculation stores the interval between two samples, in ns.
+pub(crate) const SAMPLE_INTERVAL_KEY: &str = "sample_interval_ns";
+
+/// Suffix of the key the delta calculation sets (to 1) when a counter wrapped between samples.
+pub(crate) const WRAPPED_KEY_SUFFIX: &str = "_wrapped";
+
+/// Whether `counter` wrapped around between the two samples its delta was computed from.
+pub(crate) fn counter_wrapped(perfcounters: &HashMap<String, u64>, counter: &str) -> bool {
+    perfcounters.contains_key(&format!("{counter}{WRAPPED_KEY_SUFFIX}"))
+}
+
+/// Shortest interval between two samples a rate is computed over, in ns.
+///
+/// Responses closer together than this come from clock skew or a duplicated sample, and
+/// dividing by them turns ordinary deltas into absurd rates.
+pub(crate) const MIN_SAMPLE_INTERVAL_NS: u64 = 1_000_000;
+
+/// Largest rate in Gbps taken at face value. Even the aggregate of a fully loaded 128-port
+/// XDR switch stays well below it, so anything above is a bad sample and shown as 0.
+pub(crate) const MAX_PLAUSIBLE_GBPS: f64 = 1e6;
+
+/// Seconds between the two samples a delta was computed from, if known and at least
+/// [`MIN_SAMPLE_INTERVAL_NS`].
+pub(crate) fn sample_interval_secs(perfcounters: &HashMap<String, u64>) -> Option<f64> {
+    perfcounters
+        .get(SAMPLE_INTERVAL_KEY)
+        .filter(|&&ns| ns >= MIN_SAMPLE_INTERVAL_NS)
+        .map(|&ns| ns as f64 / 1e9)
+}
+
+/// Divisor turning a counter value into a per-second rate for the given mode.
+///
+/// Rate values are divided by the true interval between the two samples; other modes show
+/// totals. Returns `None` when a rate has no known interval (e.g. the first sample).
+fn rate_divisor(perfcounters: &HashMap<String, u64>, counter_mode: &CounterMode) -> Option<f64> {
+    match counter_mode {
+        CounterMode::Rate => sample_interval_secs(perfcounters),
+        _ => Some(1.0),
+    }
+}
+
+/// Scale `counter` into Gbps at `bits_per_unit`, as a rate in Rate mode and a total otherwise.
+///
+/// Returns 0.0 when the counter or the interval is missing, when the result is not finite,
+/// or when a rate exceeds [`MAX_PLAUSIBLE_GBPS`].
+fn counter_gbps(
+    perfcounters: &HashMap<String, u64>,
+    counter: &str,
+    counter_mode: &CounterMode,
+    bits_per_unit: f64,
+) -> f64 {
+    let Some(time_delta) = rate_divisor(perfcounters, counter_mode) else {
+        return 0.0;
+    };
+    let Some(&val) = perfcounters.get(counter) else {
+        return 0.0;
+    };
+
+    let gbps = val as f64 * bits_per_unit / 1e9 / time_delta;
+    let implausible = *counter_mode == CounterMode::Rate && gbps > MAX_PLAUSIBLE_GBPS;
+    if gbps.is_finite() && !implausible {
+        gbps
+    } else {
+        0.0
+    }
+}
+
 /// Compute receive/send bandwidth in Gbps based on a performance counter.
+///
+/// Data counters count 4-byte words, so a value is scaled by 4 bytes × 8 bits.
 pub(crate) fn get_bw(
     perfcounters: &HashMap<String, u64>,
     counter: &str,
     counter_mode: &CounterMode,
 ) -> f64 {
-    let mut time_delta = *perfcounters.get("end_timestamp").unwrap_or(&1) as f64;
-    if let CounterMode::Delta = counter_mode {
-        time_delta /= 1e9;
-    } else {
-        time_delta = 1.0;
+    counter_gbps(perfcounters, counter, counter_mode, 4.0 * 8.0)
+}
+
+/// Format a bandwidth given in Gbps with a unit scaled to its magnitude, e.g. `812.40 Mbps`.
+///
+/// Rate values are rates (`Gbps`, `GB/s`); other modes show totals (`Gb`, `GB`).
+pub(crate) fn format_bw(gbps: f64, units: RateUnits, counter_mode: &CounterMode) -> String {
+    const PREFIXES: [&str; 5] = ["", "K", "M", "G", "T"];
+
+    let (mut value, base, rate_suffix) = match units {
+        RateUnits::Bits => (gbps * 1e9, "b", "ps"),
+        RateUnits::Bytes => (gbps * 1e9 / 8.0, "B", "/s"),
+    };
+
+    let mut prefix = 0;
+    while value.abs() >= 1000.0 && prefix < PREFIXES.len() - 1 {
+        value /= 1000.0;
+        prefix += 1;
     }
 
-    perfcounters
-        .get(counter)
-        .map(|&val| (val as f64 * 4.0 / 1e9 * 8.0) / time_delta)
-        .unwrap_or(0.0)
+    let suffix = match counter_mode {
+        CounterMode::Rate => rate_suffix,
+        _ => "",
+    };
+    format!("{value:.2} {}{base}{suffix}", PREFIXES[prefix])
+}
+
+/// Format an integer with comma thousands separators, e.g. `1,234,567`.
+pub(crate) fn group_thousands(n: u128) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Format a GUID the way `ibnetdiscover` prints it: `0x` and 16 zero-padded hex digits.
+pub(crate) fn format_guid(guid: u64) -> String {
+    format!("0x{guid:016x}")
+}
+
+/// Total line rate in Gbps across a node's linked ports.
+///
+/// `override_gbps` replaces the negotiated per-port rate for fabrics where it can't be read.
+/// Returns `None` when no ports are linked or any linked port's rate is unknown.
+pub(crate) fn get_line_rate(ports: &[Port], override_gbps: Option<f64>) -> Option<f64> {
+    let linked: Vec<&Port> = ports
+        .iter()
+        .filter(|p| p.link_status() != LinkStatus::Down)
+        .collect();
+    if linked.is_empty() {
+        return None;
+    }
+
+    match override_gbps {
+        Some(rate) => Some(rate * linked.len() as f64),
+        None => linked.iter().map(|p| p.line_rate_gbps()).sum(),
+    }
 }
 
-/// Compute bandwidth loss in Gbps based on a performance counter.
+/// Express a bandwidth in Gbps as a percentage of `line_rate` (Gbps).
+pub(crate) fn get_line_rate_percent(bw: f64, line_rate: Option<f64>) -> Option<f64> {
+    line_rate
+        .filter(|&rate| rate > 0.0)
+        .map(|rate| bw / rate * 100.0)
+}
+
+/// Render `values` as a one-line sparkline of block characters, scaled to the largest value.
+///
+/// Only the most recent `max_width` values are drawn.
+pub(crate) fn sparkline(values: &[f64], max_width: usize) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let values = &values[values.len().saturating_sub(max_width)..];
+    let max = values.iter().copied().fold(0.0, f64::max);
+
+    values
+        .iter()
+        .map(|&v| {
+            if max <= 0.0 {
+                BARS[0]
+            } else {
+                let level = (v / max * (BARS.len() - 1) as f64).round() as usize;
+                BARS[level.min(BARS.len() - 1)]
+            }
+        })
+        .collect()
+}
+
+/// Bits of transmit opportunity one `xmit_waits` tick is taken to cost.
+///
+/// PortXmitWait counts ticks in which a port had data to send but no flow-control credits to
+/// send it with. The tick length is hardware specific, so BW_LOSS is only an estimate: each
+/// tick is treated as one lost 64-bit transfer. The raw tick count can be shown in the METRIC
+/// column instead.
+pub(crate) const BITS_PER_XMIT_WAIT: f64 = 64.0;
+
+/// Estimate bandwidth lost to transmit waits in Gbps from a wait counter, using
+/// [`BITS_PER_XMIT_WAIT`]. Like [`get_bw`], Rate values are rates and other modes totals.
 pub(crate) fn get_bw_loss(
     perfcounters: &HashMap<String, u64>,
     counter: &str,
     counter_mode: &CounterMode,
 ) -> f64 {
-    let mut time_delta = *perfcounters.get("end_timestamp").unwrap_or(&1) as f64;
-    if let CounterMode::Delta = counter_mode {
-        time_delta /= 1e9;
-    } else {
-        time_delta = 1.0;
-    }
+    counter_gbps(perfcounters, counter, counter_mode, BITS_PER_XMIT_WAIT)
+}
 
-    perfcounters
-        .get(counter)
-        .map(|&val| val as f64 * 64.0 / 1e9 / time_delta)
-        .unwrap_or(0.0)
+/// Whether the METRIC column shows `counter` as a bandwidth rather than a count.
+pub(crate) fn metric_is_bw(counter: &str) -> bool {
+    matches!(counter, "rcv_bytes" | "xmt_bytes")
 }
 
-/// Count all error counters and return the sum.
-pub(crate) fn count_errors(perfcounters: &HashMap<String, u64>) -> u128 {
-    services::ibmad::ERROR_COUNTERS
+/// Value of `counter` for the METRIC column: Gbps for data counters, as in the
+/// RECV_BW/SEND_BW columns, and the plain count for everything else. `xmit_waits` is shown
+/// as its raw tick count, next to the BW_LOSS estimate derived from it.
+pub(crate) fn get_metric(
+    perfcounters: &HashMap<String, u64>,
+    counter: &str,
+    counter_mode: &CounterMode,
+) -> f64 {
+    match counter {
+        c if metric_is_bw(c) => get_bw(perfcounters, c, counter_mode),
+        c => perfcounters.get(c).copied().unwrap_or(0) as f64,
+    }
+}
+
+/// Sum the given error counters.
+pub(crate) fn count_errors(perfcounters: &HashMap<String, u64>, error_counters: &[String]) -> u128 {
+    error_counters
         .iter()
-        .filter_map(|&err_ctr| perfcounters.get(err_ctr))
+        .filter_map(|err_ctr| perfcounters.get(err_ctr))
         .map(|&val| val as u128)
         .sum()
 }
 
-/// Get a comma separated string of error counter names with non-zero values.
-pub(crate) fn get_error_strings(perfcounters: &HashMap<String, u64>) -> String {
-    let errors: Vec<String> = services::ibmad::ERROR_COUNTERS
+/// Get a comma separated string of the given error counters' names with non-zero values.
+pub(crate) fn get_error_strings(
+    perfcounters: &HashMap<String, u64>,
+    error_counters: &[String],
+) -> String {
+    let errors: Vec<String> = error_counters
         .iter()
-        .filter_map(|&err_ctr| perfcounters.get_key_value(err_ctr))
+        .filter_map(|err_ctr| perfcounters.get_key_value(err_ctr))
         .filter(|e| *e.1 > 0)
         .map(|e| e.0.to_string())
         .collect();
 
     errors.join(",")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A delta of `words` data words taken over `interval_ns`, if given.
+    fn delta(words: u64, interval_ns: Option<u64>) -> HashMap<String, u64> {
+        let mut ctrs = HashMap::from([("rcv_bytes".to_string(), words)]);
+        if let Some(ns) = interval_ns {
+            ctrs.insert(SAMPLE_INTERVAL_KEY.to_string(), ns);
+        }
+        ctrs
+    }
+
+    #[test]
+    fn truncate_fit_cuts_on_char_boundaries() {
+        assert_eq!(truncate_fit("switch-Ω-Δ-Σ", 9), "switch-Ω…");
+        assert_eq!(truncate_fit("Ω-Δ", 2), "Ω…");
+        // No ellipsis when the whole string fits
+        assert_eq!(truncate_fit("switch-Ω-Δ-Σ", 12), "switch-Ω-Δ-Σ");
+        assert_eq!(truncate_fit("Ω", 1), "Ω");
+        assert_eq!(truncate_fit("Ω", 0), "");
+    }
+
+    #[test]
+    fn guid_is_zero_padded_hex() {
+        assert_eq!(format_guid(0x0002c90300a1b2c3), "0x0002c90300a1b2c3");
+        assert_eq!(format_guid(10), "0x000000000000000a");
+        assert_eq!(format_guid(u64::MAX), "0xffffffffffffffff");
+    }
+
+    #[test]
+    fn rate_needs_a_known_interval() {
+        assert_eq!(
+            get_bw(&delta(1_000, None), "rcv_bytes", &CounterMode::Rate),
+            0.0
+        );
+        assert_eq!(
+            get_bw(&delta(1_000, Some(0)), "rcv_bytes", &CounterMode::Rate),
+            0.0
+        );
+    }
+
+    #[test]
+    fn rate_ignores_intervals_below_the_minimum() {
+        let short = delta(1_000, Some(MIN_SAMPLE_INTERVAL_NS - 1));
+        assert_eq!(sample_interval_secs(&short), None);
+        assert_eq!(get_bw(&short, "rcv_bytes", &CounterMode::Rate), 0.0);
+
+        let shortest = delta(1_000, Some(MIN_SAMPLE_INTERVAL_NS));
+        assert_eq!(sample_interval_secs(&shortest), Some(0.001));
+        assert!(get_bw(&shortest, "rcv_bytes", &CounterMode::Rate) > 0.0);
+    }
+
+    #[test]
+    fn implausible_rate_is_capped() {
+        let jump = delta(u64::MAX, Some(1_000_000_000));
+        assert_eq!(get_bw(&jump, "rcv_bytes", &CounterMode::Rate), 0.0);
+        // A total isn't a rate, so it isn't capped
+        assert!(get_bw(&jump, "rcv_bytes", &CounterMode::Delta) > MAX_PLAUSIBLE_GBPS);
+    }
+
+    #[test]
+    fn delta_is_raw_and_rate_is_per_second() {
+        // 250M words is 8 Gb, taken over 2 seconds
+        let ctrs = delta(250_000_000, Some(2_000_000_000));
+        assert!((get_bw(&ctrs, "rcv_bytes", &CounterMode::Delta) - 8.0).abs() < 1e-9);
+        assert!((get_bw(&ctrs, "rcv_bytes", &CounterMode::Rate) - 4.0).abs() < 1e-9);
+
+        assert_eq!(
+            format_bw(8.0, RateUnits::Bits, &CounterMode::Delta),
+            "8.00 Gb"
+        );
+        assert_eq!(
+            format_bw(4.0, RateUnits::Bits, &CounterMode::Rate),
+            "4.00 Gbps"
+        );
+        assert_eq!(
+            format_bw(4.0, RateUnits::Bytes, &CounterMode::Rate),
+            "500.00 MB/s"
+        );
+    }
+}
@@ -1,3 +1,4 @@
 pub mod forms;
 pub mod helpers;
+pub mod theme;
 pub mod view;
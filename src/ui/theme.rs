@@ -0,0 +1,92 @@
+use std::fs;
+
+use ratatui::style::Color;
+use serde::Deserialize;
+
+/// Colors used throughout the UI, loaded with `--theme`.
+///
+/// A theme file is TOML naming any subset of the fields; missing ones keep the dark defaults.
+/// Colors accept names (`light-blue`), indexes (`42`) and hex RGB (`#202020`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    /// Header, footer and legend labels
+    pub label_fg: Color,
+    /// Table header and totals rows
+    pub header_fg: Color,
+    pub header_bg: Color,
+    /// Selected table row
+    pub selection_bg: Color,
+    /// Every other unselected table row
+    pub zebra_bg: Color,
+    /// Errors: down links, rows over the error threshold, alert counts
+    pub error_fg: Color,
+    /// Text drawn on top of `error_fg` backgrounds
+    pub error_text_fg: Color,
+    /// Warnings: failed queries, flashing alert rows, active auto-update
+    pub warning_fg: Color,
+    /// Links that are up but not Active
+    pub dim_fg: Color,
+    /// Bandwidth trend sparklines
+    pub accent_fg: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    /// The default theme, for dark terminal backgrounds.
+    pub fn dark() -> Self {
+        Self {
+            label_fg: Color::Green,
+            header_fg: Color::Black,
+            header_bg: Color::White,
+            selection_bg: Color::LightBlue,
+            zebra_bg: Color::Rgb(32, 32, 32),
+            error_fg: Color::Red,
+            error_text_fg: Color::White,
+            warning_fg: Color::Yellow,
+            dim_fg: Color::DarkGray,
+            accent_fg: Color::Cyan,
+        }
+    }
+
+    /// A theme for light terminal backgrounds.
+    pub fn light() -> Self {
+        Self {
+            label_fg: Color::Blue,
+            header_fg: Color::White,
+            header_bg: Color::DarkGray,
+            selection_bg: Color::LightCyan,
+            zebra_bg: Color::Rgb(230, 230, 230),
+            error_fg: Color::Red,
+            error_text_fg: Color::White,
+            warning_fg: Color::Rgb(176, 112, 0),
+            dim_fg: Color::Gray,
+            accent_fg: Color::Magenta,
+        }
+    }
+
+    /// Resolve `--theme`: a built-in name (`dark`, `light`) or the path of a TOML theme file.
+    ///
+    /// Unreadable or invalid files fall back to the default theme.
+    pub fn load(theme: Option<&str>) -> Self {
+        match theme {
+            None | Some("dark") => Self::dark(),
+            Some("light") => Self::light(),
+            Some(path) => match fs::read_to_string(path) {
+                Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                    tracing::warn!("Ignoring invalid theme file {path}: {e}");
+                    Self::default()
+                }),
+                Err(e) => {
+                    tracing::warn!("Cannot read theme file {path}: {e}");
+                    Self::default()
+                }
+            },
+        }
+    }
+}
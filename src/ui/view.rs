@@ -1,26 +1,48 @@
 use chrono::prelude::*;
 use ratatui::{
     buffer::Buffer,
-    layout::{Constraint, Layout, Rect},
-    style::{Color, Modifier, Style, Stylize},
+    layout::{Constraint, Flex, Layout, Rect},
+    style::{Modifier, Style, Stylize},
+    symbols::Marker,
     text::{Line, Span},
-    widgets::{Block, BorderType, Borders, Cell, Clear, Paragraph, Row, Table, Widget},
+    widgets::{
+        Axis, Block, BorderType, Borders, Cell, Chart, Clear, Dataset, GraphType, Paragraph, Row,
+        Scrollbar, ScrollbarOrientation, ScrollbarState, Table, Widget, Wrap,
+    },
 };
 
 use super::helpers::{
     centered_rect_percent, centered_rect_percent_w_lines_h, compute_column_widths, count_errors,
-    get_bw, get_bw_loss, get_error_strings, truncate_fit,
+    counter_wrapped, format_bw, format_guid, get_bw, get_bw_loss, get_error_strings,
+    get_line_rate_percent, get_metric, group_thousands, metric_is_bw, sparkline, truncate_fit,
 };
-use crate::app::{
-    AGG_COUNTERS_PORT, App, DETAILS_POPUP_PERCENT_HEIGHT, DETAILS_POPUP_PERCENT_WIDTH,
-    MainNodeInfo, Popup, SEARCH_POPUP_LINES_HEIGHT, SEARCH_POPUP_PERCENT_WIDTH,
+use crate::{
+    app::{
+        AGG_COUNTERS_PORT, ALERT_FLASH_TICKS, ALERTS_POPUP_PERCENT_HEIGHT,
+        ALERTS_POPUP_PERCENT_WIDTH, App, CounterMode, DETAILS_POPUP_PERCENT_HEIGHT,
+        DETAILS_POPUP_PERCENT_WIDTH, DetailsPortInfo, DetailsPorts, METRIC_COUNTERS, MainColumn,
+        MainNodeInfo, Popup, SEARCH_POPUP_LINES_HEIGHT, SEARCH_POPUP_PERCENT_WIDTH, SortColumn,
+        counter_reset_label,
+    },
+    logging::LOG_LINES,
+    services::lib::{LinkStatus, Port, is_query_failed},
 };
 
-// Column ratios for the main table layout
-const MAIN_TABLE_COLUMN_RATIOS: [f64; 8] = [0.04, 0.32, 0.04, 0.12, 0.12, 0.12, 0.12, 0.12];
-
 // Column ratios for the details popup table layout
-const DETAILS_TABLE_COLUMN_RATIOS: [f64; 9] = [0.0, 0.04, 0.06, 0.28, 0.12, 0.12, 0.12, 0.10, 0.16];
+const DETAILS_TABLE_COLUMN_RATIOS: [f64; 10] =
+    [0.0, 0.04, 0.06, 0.08, 0.20, 0.12, 0.12, 0.12, 0.10, 0.16];
+
+// Narrowest column for an `--extra-counters` counter in the details popup
+const EXTRA_COUNTER_MIN_WIDTH: usize = 12;
+
+// Width of the column visibility popup
+const COLUMNS_POPUP_PERCENT_WIDTH: u16 = 30;
+
+// Width of the counter reset prompt
+const RESET_PROMPT_PERCENT_WIDTH: u16 = 50;
+
+// Column ratios for the bandwidth alerts popup table layout
+const ALERTS_TABLE_COLUMN_RATIOS: [f64; 5] = [0.30, 0.08, 0.36, 0.12, 0.12];
 
 impl Widget for &App {
     // Renders the user interface widgets.
@@ -33,7 +55,7 @@ impl Widget for &App {
         let layout = Layout::vertical([
             Constraint::Length(3),       // Header
             Constraint::Percentage(100), // Node Table
-            Constraint::Length(3),       // Footer
+            Constraint::Length(4),       // Footer
         ])
         .split(area);
 
@@ -55,26 +77,245 @@ impl Widget for &App {
             Popup::Details => {
                 self.render_details_popup(area, buf);
             }
+            Popup::Alerts => {
+                self.render_alerts_popup(area, buf);
+            }
+            Popup::Columns => {
+                self.render_columns_popup(area, buf);
+            }
+            Popup::Warnings => {
+                self.render_warnings_popup(area, buf);
+            }
+            Popup::Metric => {
+                self.render_metric_popup(area, buf);
+            }
+            Popup::Counters => {
+                self.render_details_popup(area, buf);
+                self.render_counters_popup(area, buf);
+            }
+            Popup::Log => {
+                self.render_log_popup(area, buf);
+            }
+            Popup::ConfirmReset => {
+                if self.reset_from_details {
+                    self.render_details_popup(area, buf);
+                }
+                self.render_reset_prompt(area, buf);
+            }
+        }
+    }
+}
+
+/// Header title of a main table column.
+fn column_title(col: MainColumn) -> &'static str {
+    match col {
+        MainColumn::Lid => "LID",
+        MainColumn::Guid => "GUID",
+        MainColumn::Hca => "HCA",
+        MainColumn::Node => "NODE",
+        MainColumn::Type => "TYPE",
+        MainColumn::Pt => "PT",
+        MainColumn::RecvBw => "RECV_BW",
+        MainColumn::SendBw => "SEND_BW",
+        MainColumn::RecvTrend => "RECV_TREND",
+        MainColumn::Util => "R/S%",
+        MainColumn::BwLoss => "BW_LOSS",
+        MainColumn::ErrCnt => "ERR_CNT",
+        MainColumn::ErrStr => "ERR_STR",
+        MainColumn::Metric => "METRIC",
+    }
+}
+
+/// What a main table column shows in `counter_mode`, for the columns popup.
+fn column_description(col: MainColumn, counter_mode: &CounterMode) -> &'static str {
+    let rates = *counter_mode == CounterMode::Rate;
+    match col {
+        MainColumn::Lid => "LID of the node",
+        MainColumn::Guid => "Node GUID",
+        MainColumn::Hca => "HCA the node was discovered through",
+        MainColumn::Node => "Node description, or its --name-map name",
+        MainColumn::Type => "Switch or HCA",
+        MainColumn::Pt => "Number of linked ports",
+        MainColumn::RecvBw if rates => "Receive bandwidth, from rcv_bytes",
+        MainColumn::RecvBw => "Data received, from rcv_bytes",
+        MainColumn::SendBw if rates => "Send bandwidth, from xmt_bytes",
+        MainColumn::SendBw => "Data sent, from xmt_bytes",
+        MainColumn::RecvTrend => "Recent receive bandwidth samples",
+        MainColumn::Util => "Recv/send bandwidth as a percentage of line rate (Rate only)",
+        MainColumn::BwLoss if rates => {
+            "Estimated bandwidth lost waiting for credits: xmit_waits ticks/s × 64 bits"
+        }
+        MainColumn::BwLoss => {
+            "Estimated data not sent while waiting for credits: xmit_waits ticks × 64 bits"
         }
+        MainColumn::ErrCnt => "Sum of the error counters",
+        MainColumn::ErrStr => "Error counters with non-zero values",
+        MainColumn::Metric => "The counter picked with m",
     }
 }
 
+/// Relative width of a main table column; the visible columns' ratios are scaled to fill the
+/// table.
+fn column_ratio(col: MainColumn) -> f64 {
+    match col {
+        MainColumn::Lid => 0.04,
+        MainColumn::Guid => 0.0,
+        MainColumn::Hca => 0.06,
+        MainColumn::Node => 0.20,
+        MainColumn::Type => 0.06,
+        MainColumn::Pt => 0.04,
+        MainColumn::RecvBw => 0.10,
+        MainColumn::SendBw => 0.10,
+        MainColumn::RecvTrend => 0.10,
+        MainColumn::Util => 0.08,
+        MainColumn::BwLoss => 0.10,
+        MainColumn::ErrCnt => 0.10,
+        MainColumn::ErrStr => 0.14,
+        MainColumn::Metric => 0.10,
+    }
+}
+
+/// Width of a main table column that doesn't scale with the table, such as the GUID column
+/// that always needs its 18 characters (`0x` and 16 hex digits).
+fn column_fixed_width(col: MainColumn) -> Option<u16> {
+    match col {
+        MainColumn::Guid => Some(18),
+        _ => None,
+    }
+}
+
+/// Draw a scrollbar on the right edge of a table's rows (below its header row) showing
+/// which of `len` rows are visible. Nothing is drawn when every row fits.
+fn render_scrollbar(area: Rect, len: usize, offset: usize, visible_rows: usize, buf: &mut Buffer) {
+    if len <= visible_rows {
+        return;
+    }
+
+    let rows_area = Rect {
+        y: area.y + 1,
+        height: area.height.saturating_sub(1),
+        ..area
+    };
+    let mut state = ScrollbarState::new(len.saturating_sub(visible_rows))
+        .position(offset)
+        .viewport_content_length(visible_rows);
+
+    // Imported here only; in scope module-wide it makes `Table::render` ambiguous.
+    use ratatui::widgets::StatefulWidget;
+    Scrollbar::new(ScrollbarOrientation::VerticalRight)
+        .begin_symbol(None)
+        .end_symbol(None)
+        .render(rows_area, buf, &mut state);
+}
+
 impl App {
     /// Returns the sort indicator symbol for a given column.
     ///
     /// # Arguments
-    /// * `col_idx` - The column index to get the sort indicator for
+    /// * `column` - The column to get the sort indicator for
     ///
     /// # Returns
     /// A string containing the sort indicator ("▲" for ascending, "▼" for descending, or empty)
-    fn get_sort_indicator(&self, col_idx: i32) -> &'static str {
-        if self.sort_column == col_idx {
+    fn get_sort_indicator(&self, column: SortColumn) -> &'static str {
+        if self.sort_column == column {
             if self.sort_ascending { "▲" } else { "▼" }
         } else {
             ""
         }
     }
 
+    /// Rows of the details popup table for the selected node, in display order: ports sorted
+    /// by `popup_sort_column`, then the node total if listed.
+    pub(crate) fn details_rows(&self) -> Vec<DetailsPortInfo> {
+        let Some(n) = self
+            .selected_node
+            .as_ref()
+            .and_then(|selected| self.nodes.iter().find(|n| n.guid == selected.guid))
+        else {
+            return Vec::new();
+        };
+
+        // The node total has no port of its own to describe
+        let ports: Vec<(i32, Option<&Port>)> = match self.details_ports {
+            DetailsPorts::TotalOnly => Vec::new(),
+            _ => n.ports.iter().map(|p| (p.number, Some(p))).collect(),
+        };
+        let total = match self.details_ports {
+            DetailsPorts::PerPort => None,
+            _ => Some((AGG_COUNTERS_PORT, None)),
+        };
+
+        let mut rows: Vec<DetailsPortInfo> = ports
+            .into_iter()
+            .chain(total)
+            .map(|(port, p)| {
+                let ctrs = self.display_counters.get(&n.counter_key(port));
+                let recv_bw = ctrs.map_or(0.0, |c| get_bw(c, "rcv_bytes", &self.counter_mode));
+                let xmt_bw = ctrs.map_or(0.0, |c| get_bw(c, "xmt_bytes", &self.counter_mode));
+                let xmit_waits =
+                    ctrs.map_or(0.0, |c| get_bw_loss(c, "xmit_waits", &self.counter_mode));
+                let error_count = ctrs.map_or(0, |c| count_errors(c, &self.error_counters));
+                let error_strings = ctrs.map_or(String::new(), |c| {
+                    get_error_strings(c, &self.error_string_counters)
+                });
+                DetailsPortInfo {
+                    port,
+                    link_state: p.map(|p| p.link_state.clone()).unwrap_or_default(),
+                    link_status: p.map_or(LinkStatus::Unknown, Port::link_status),
+                    link: p.map(Port::link_label).unwrap_or_default(),
+                    remote_description: p
+                        .map(|p| p.remote_node_description.clone())
+                        .unwrap_or_default(),
+                    recv_bw,
+                    send_bw: xmt_bw,
+                    bw_loss: xmit_waits,
+                    xmit_waits_wrapped: ctrs.is_some_and(|c| counter_wrapped(c, "xmit_waits")),
+                    error_count,
+                    error_strings,
+                    query_failed: ctrs.is_some_and(is_query_failed),
+                }
+            })
+            .collect();
+
+        // The total row stays last; ties keep port order
+        rows.sort_by(|a, b| {
+            let ordering = self.popup_sort_column.compare(a, b);
+            let ordering = if self.popup_sort_ascending {
+                ordering
+            } else {
+                ordering.reverse()
+            };
+            (a.port == AGG_COUNTERS_PORT)
+                .cmp(&(b.port == AGG_COUNTERS_PORT))
+                .then(ordering)
+                .then(a.port.cmp(&b.port))
+        });
+        rows
+    }
+
+    /// Auto-update state for the header: a spinner while a request is in flight, otherwise the
+    /// countdown to the next automatic update.
+    fn auto_update_text(&self) -> String {
+        const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+        if self.pending_counter_update {
+            let frame = SPINNER[self.tick / 3 % SPINNER.len()];
+            format!(" {frame} updating")
+        } else if self.auto_update {
+            let remaining = self
+                .auto_update_period()
+                .saturating_sub(self.last_auto_update.elapsed());
+            format!(" next in {}s", remaining.as_millis().div_ceil(1000))
+        } else {
+            String::new()
+        }
+    }
+
+    /// Format a bandwidth in Gbps for display using the configured `--units`.
+    fn format_bw(&self, gbps: f64) -> String {
+        format_bw(gbps, self.config.units, &self.counter_mode)
+    }
+
     /// Render the top header section with three columns showing application status and metadata.
     fn render_header(&self, area: Rect, buf: &mut Buffer) {
         let utc: DateTime<Utc> = Utc::now();
@@ -87,65 +328,144 @@ impl App {
         .split(area);
 
         // Left Header
+        let mut title = vec!["ibtop".fg(self.theme.label_fg)];
+        if self.paused {
+            title.push(Span::from("  PAUSED").fg(self.theme.error_fg).bold());
+        }
         let header_left_text = vec![
-            Line::from("ibtop".green()),
+            Line::from(title),
             Line::from(vec![
-                Span::from("HCA:    ".green()),
+                Span::from("HCA:    ").fg(self.theme.label_fg),
                 Span::from(&self.config.hca),
             ]),
             Line::from(vec![
-                Span::from("Status: ".green()),
+                Span::from("Status: ").fg(self.theme.label_fg),
                 Span::from(format!("{}", self.status)),
             ]),
         ];
 
         Paragraph::new(header_left_text).render(header_layout[0], buf);
 
-        let last_update_ts = match self.last_counter_update {
-            Some(ts) => ts.to_rfc3339_opts(SecondsFormat::Secs, false),
-            None => "".to_string(),
+        let last_update_ts = match (self.last_counter_update, self.sample_period) {
+            (Some(ts), Some(period)) => format!(
+                "{} (every {:.1}s)",
+                ts.to_rfc3339_opts(SecondsFormat::Secs, false),
+                period.num_milliseconds() as f64 / 1000.0
+            ),
+            (Some(ts), None) => ts.to_rfc3339_opts(SecondsFormat::Secs, false),
+            (None, _) => "".to_string(),
         };
 
         // Middle Header
         let header_mid_text = vec![
             Line::from(vec![
-                Span::from("Timestamp: ".green()),
+                Span::from("Timestamp: ").fg(self.theme.label_fg),
                 Span::from(utc.to_rfc3339_opts(SecondsFormat::Secs, false)),
             ]),
             Line::from(vec![
-                Span::from("Counters Update: ".green()),
+                Span::from("Counters Update: ").fg(self.theme.label_fg),
                 Span::from(last_update_ts),
+                Span::from(self.auto_update_text()).fg(self.theme.accent_fg),
+                // Only worth the space once some rows have gone stale
+                match self.oldest_sample_age(utc) {
+                    Some(age) if age > self.stale_after() => {
+                        Span::from(format!(", oldest sample: {}s ago", age.num_seconds()))
+                            .fg(self.theme.warning_fg)
+                    }
+                    _ => Span::from(""),
+                },
             ]),
             Line::from(vec![
-                Span::from("Node Count: ".green()),
+                Span::from("Node Count: ").fg(self.theme.label_fg),
                 Span::from(format!("{}", self.nodes.len())),
+                match (
+                    self.discovery_progress,
+                    self.discovery_start_time,
+                    self.discovery_stats,
+                ) {
+                    (Some((mads_sent, nodes_found)), _, _) => Span::from(format!(
+                        " (discovering: {} nodes found, {} MADs sent)",
+                        group_thousands(nodes_found as u128),
+                        group_thousands(mads_sent.into())
+                    ))
+                    .fg(self.theme.accent_fg),
+                    // Sweeps that can't report progress until they finish
+                    (None, Some(start), _) => Span::from(format!(
+                        " (discovering for {}s)",
+                        (utc - start).num_seconds()
+                    ))
+                    .fg(self.theme.accent_fg),
+                    (None, None, Some(stats)) => {
+                        let text = format!(
+                            " (MADs: {} sent, {} errors, {} timeouts)",
+                            group_thousands(stats.mads_sent.into()),
+                            group_thousands(stats.mad_errors.into()),
+                            group_thousands(stats.mad_timeouts.into())
+                        );
+                        // Flag sweeps that hit errors or timeouts
+                        if stats.mad_errors > 0 || stats.mad_timeouts > 0 {
+                            Span::from(text).fg(self.theme.warning_fg)
+                        } else {
+                            Span::from(text)
+                        }
+                    }
+                    (None, None, None) => Span::from(""),
+                },
             ]),
         ];
 
         Paragraph::new(header_mid_text).render(header_layout[1], buf);
 
         // Right Header: show sort and active filter
-        let sort_name = match self.sort_column {
-            1 => "LID",
-            2 => "NODE",
-            3 => "PT",
-            4 => "RECV_BW",
-            5 => "SEND_BW",
-            6 => "BW_LOSS",
-            7 => "ERR_CNT",
-            8 => "ERR_STR",
-            _ => "None",
-        };
-        let sort_text = if self.sort_column >= 1 {
-            format!("{}{}", sort_name, self.get_sort_indicator(self.sort_column))
+        let mut sort_text = if self.sort_column != SortColumn::None {
+            format!(
+                "{}{}",
+                self.sort_column.name(),
+                self.get_sort_indicator(self.sort_column)
+            )
         } else {
             "None".to_string()
         };
+        if self.secondary_sort_column != SortColumn::None {
+            let indicator = if self.secondary_sort_ascending {
+                "▲"
+            } else {
+                "▼"
+            };
+            sort_text.push_str(&format!(
+                ", {}{}",
+                self.secondary_sort_column.name(),
+                indicator
+            ));
+        }
         let header_right_text = vec![
-            Line::from(vec![Span::from("Sort: ".green()), Span::from(sort_text)]),
             Line::from(vec![
-                Span::from("Filter: ".green()),
-                Span::from(self.search_form.value.clone()),
+                Span::from("Sort: ").fg(self.theme.label_fg),
+                Span::from(sort_text),
+            ]),
+            Line::from(vec![
+                Span::from("Filter: ").fg(self.theme.label_fg),
+                if self.search_form.value.is_empty() {
+                    Span::from("None")
+                } else {
+                    Span::from(self.search_form.value.clone())
+                },
+                match self.node_type_filter {
+                    Some(node_type) => {
+                        Span::from(format!(" [{} only]", node_type.name())).fg(self.theme.accent_fg)
+                    }
+                    None => Span::from(""),
+                },
+            ]),
+            Line::from(vec![
+                "Alerts: ".fg(self.theme.label_fg),
+                if self.bw_alert_count > 0 {
+                    Span::from(format!("{}", self.bw_alert_count))
+                        .fg(self.theme.error_fg)
+                        .bold()
+                } else {
+                    Span::from("0")
+                },
             ]),
         ];
 
@@ -154,84 +474,58 @@ impl App {
 
     /// Render the main node table section that shows node information and performance metrics.
     ///
-    /// Displays columns for: LID, NODE, PORTS, RECV_BW, SEND_BW, BW_LOSS, ERRORS.
-    /// Supports filtering by search term and sorting by any column.
+    /// Displays the columns in `self.columns` (LID, NODE, PORTS, RECV_BW, SEND_BW, RECV_TREND,
+    /// BW_LOSS, ERRORS, ...), sized by their ratios over the visible subset.
+    /// Supports filtering by search term and sorting by any column. A pinned row at the
+    /// bottom totals bandwidth and errors across all filtered nodes.
     fn render_nodes_table(&self, area: Rect, buf: &mut Buffer) {
-        // Expensive: compile filter + compute derived metrics + sort.
-        let re = regex::RegexBuilder::new(&self.search_form.value)
-            .case_insensitive(true)
-            .build()
-            .unwrap_or_else(|_| regex::Regex::new("").unwrap());
-
-        let mut node_info: Vec<MainNodeInfo> = self
-            .nodes
-            .iter()
-            .filter(|n| re.is_match(&n.node_description))
-            .map(|n| {
-                let counters = self.display_counters.get(&(n.lid, AGG_COUNTERS_PORT));
-                let recv_bw = counters
-                    .map_or(0.0, |ctrs| get_bw(ctrs, "rcv_bytes", &self.counter_mode));
-                let xmt_bw = counters
-                    .map_or(0.0, |ctrs| get_bw(ctrs, "xmt_bytes", &self.counter_mode));
-                let xmit_waits = counters.map_or(0.0, |ctrs| {
-                    get_bw_loss(ctrs, "xmit_waits", &self.counter_mode)
-                });
-                let error_count = counters.map_or(0, |ctrs| count_errors(ctrs));
-                let error_strings =
-                    counters.map_or("".to_string(), |ctrs| get_error_strings(ctrs));
-
-                (
-                    n.guid,
-                    n.lid,
-                    n.node_description.clone(),
-                    n.ports.len() as u16,
-                    recv_bw,
-                    xmt_bw,
-                    xmit_waits,
-                    error_count,
-                    error_strings,
-                )
-            })
-            .collect();
+        // Filtered and sorted rows, rebuilt only when their inputs change.
+        let node_info = self.visible_nodes();
+        // Ages are measured against the render time
+        let now = Utc::now();
+        let stale_after = self.stale_after();
 
-        node_info.sort_by(|a, b| {
-            let ordering = match self.sort_column {
-                1 => a.1.cmp(&b.1), // LID
-                2 => a.2.cmp(&b.2), // Description
-                3 => a.3.cmp(&b.3), // Port count
-                4 => a.4.partial_cmp(&b.4).unwrap_or(std::cmp::Ordering::Equal), // Receive BW
-                5 => a.5.partial_cmp(&b.5).unwrap_or(std::cmp::Ordering::Equal), // Transmit BW
-                6 => a.6.partial_cmp(&b.6).unwrap_or(std::cmp::Ordering::Equal), // Xmit waits
-                7 => a.7.cmp(&b.7), // Error count
-                8 => a.8.cmp(&b.8), // Error strings
-                _ => std::cmp::Ordering::Equal,
-            };
+        let table_layout = Layout::vertical([
+            Constraint::Min(0),    // Node rows
+            Constraint::Length(1), // Totals
+        ])
+        .split(area);
+        let (area, totals_area) = (table_layout[0], table_layout[1]);
 
-            if self.sort_ascending {
-                ordering
-            } else {
-                ordering.reverse()
+        let columns = &self.columns;
+        let ratios: Vec<f64> = columns.iter().map(|&c| column_ratio(c)).collect();
+        // Spread the width left after fixed-width columns over the other visible columns
+        let fixed_width: u16 = columns.iter().filter_map(|&c| column_fixed_width(c)).sum();
+        let ratio_sum: f64 = ratios.iter().sum::<f64>().max(f64::EPSILON);
+        let ratios: Vec<f64> = ratios.iter().map(|r| r / ratio_sum).collect();
+        let mut widths = compute_column_widths(area.width.saturating_sub(fixed_width), &ratios);
+        for (width, &col) in widths.iter_mut().zip(columns) {
+            if let Some(fixed) = column_fixed_width(col) {
+                *width = fixed as usize;
             }
-        });
+        }
 
-        let available_width = area.width;
-        let widths = compute_column_widths(available_width, &MAIN_TABLE_COLUMN_RATIOS);
-
-        let header_cells = vec![
-            Cell::from(format!("LID{}", self.get_sort_indicator(1))),
-            Cell::from(format!("NODE{}", self.get_sort_indicator(2))),
-            Cell::from(format!("PT{}", self.get_sort_indicator(3))),
-            Cell::from(format!("RECV_BW{}", self.get_sort_indicator(4))),
-            Cell::from(format!("SEND_BW{}", self.get_sort_indicator(5))),
-            Cell::from(format!("BW_LOSS{}", self.get_sort_indicator(6))),
-            Cell::from(format!("ERR_CNT{}", self.get_sort_indicator(7))),
-            Cell::from(format!("ERR_STR{}", self.get_sort_indicator(8))),
-        ];
+        let header_cells: Vec<Cell> = columns
+            .iter()
+            .map(|&col| {
+                // The metric column is titled by the counter it shows
+                let title = match col {
+                    MainColumn::Metric => self.metric_counter.to_uppercase(),
+                    _ => column_title(col).to_string(),
+                };
+                match col.sort_column() {
+                    Some(sort_idx) => {
+                        Cell::from(format!("{title}{}", self.get_sort_indicator(sort_idx)))
+                    }
+                    None => Cell::from(title),
+                }
+            })
+            .collect();
 
         let header = Row::new(header_cells).style(
             Style::default()
-                .fg(Color::Black)
-                .bg(Color::White)
+                .fg(self.theme.header_fg)
+                .bg(self.theme.header_bg)
                 .add_modifier(Modifier::BOLD),
         );
 
@@ -249,58 +543,177 @@ impl App {
             .skip(offset)
             .take(visible_rows)
             .map(
-                |(idx, (_guid, lid, desc, ports, r_bw, x_bw, waits, errs, err_str))| {
-                    let mut row = Row::new(vec![
-                        Cell::from(format!("{}", lid)),
-                        Cell::from(truncate_fit(desc, widths[1])),
-                        Cell::from(format!("{}", ports)),
-                        Cell::from(format!("{:.2}", r_bw)),
-                        Cell::from(format!("{:.2}", x_bw)),
-                        Cell::from(format!("{:.2}", waits)),
-                        Cell::from(format!("{}", errs)),
-                        Cell::from(truncate_fit(err_str, widths[7])),
-                    ]);
-                    // Zebra striping for readability (non-selected rows)
+                |(
+                    idx,
+                    MainNodeInfo {
+                        guid,
+                        lid,
+                        name,
+                        ports,
+                        recv_bw,
+                        send_bw,
+                        bw_loss,
+                        error_count,
+                        error_strings,
+                        hca,
+                        line_rate,
+                        metric,
+                        node_type,
+                    },
+                )| {
+                    let cells: Vec<Cell> = columns
+                        .iter()
+                        .zip(&widths)
+                        .map(|(&col, &width)| match col {
+                            MainColumn::Lid => Cell::from(format!("{}", lid)),
+                            MainColumn::Guid => Cell::from(format_guid(*guid)),
+                            MainColumn::Hca => Cell::from(truncate_fit(hca, width)),
+                            MainColumn::Node => Cell::from(truncate_fit(name, width)),
+                            MainColumn::Type => Cell::from(node_type.name()),
+                            MainColumn::Pt => Cell::from(format!("{}", ports)),
+                            MainColumn::RecvBw => Cell::from(self.format_bw(*recv_bw)),
+                            MainColumn::SendBw => Cell::from(self.format_bw(*send_bw)),
+                            MainColumn::RecvTrend => {
+                                let trend = self
+                                    .bw_history
+                                    .get(guid)
+                                    .map(|h| {
+                                        let samples: Vec<f64> =
+                                            h.iter().map(|&(_, r, _)| r).collect();
+                                        sparkline(&samples, width)
+                                    })
+                                    .unwrap_or_default();
+                                Cell::from(trend).fg(self.theme.accent_fg)
+                            }
+                            // Utilization is only meaningful for per-second rates.
+                            MainColumn::Util => Cell::from(match self.counter_mode {
+                                CounterMode::Rate => match (
+                                    get_line_rate_percent(*recv_bw, *line_rate),
+                                    get_line_rate_percent(*send_bw, *line_rate),
+                                ) {
+                                    (Some(r), Some(x)) => format!("{r:.0}/{x:.0}"),
+                                    _ => "-".to_string(),
+                                },
+                                _ => "-".to_string(),
+                            }),
+                            MainColumn::BwLoss => Cell::from(self.format_bw(*bw_loss)),
+                            MainColumn::ErrCnt => Cell::from(group_thousands(*error_count)),
+                            MainColumn::ErrStr => Cell::from(truncate_fit(error_strings, width)),
+                            MainColumn::Metric if metric_is_bw(&self.metric_counter) => {
+                                Cell::from(self.format_bw(*metric))
+                            }
+                            MainColumn::Metric => Cell::from(group_thousands(*metric as u128)),
+                        })
+                        .collect();
+                    let mut row = Row::new(cells);
+                    let over_threshold =
+                        self.error_highlight && *error_count > self.error_threshold;
+                    // Rows above the bandwidth alert threshold flash twice a second
+                    let flash = (self.tick / ALERT_FLASH_TICKS).is_multiple_of(2)
+                        && (self.bw_alert_active.contains(&(*guid, "RECV_BW"))
+                            || self.bw_alert_active.contains(&(*guid, "SEND_BW")));
+                    // Zebra striping for readability (non-selected rows); rows whose counters
+                    // are older than the stale threshold are dimmed
+                    let mut style = Style::default();
                     if selected_idx != idx && idx % 2 == 1 {
-                        row = row.style(Style::default().bg(Color::Rgb(32, 32, 32)));
+                        style = style.bg(self.theme.zebra_bg);
+                    }
+                    if self
+                        .counters_age(hca, *lid, now)
+                        .is_some_and(|age| age > stale_after)
+                    {
+                        style = style.fg(self.theme.dim_fg);
+                    }
+                    row = row.style(style);
+                    // Rows over the error threshold are red regardless of striping
+                    if selected_idx != idx && over_threshold {
+                        row = row.style(
+                            Style::default()
+                                .fg(self.theme.error_text_fg)
+                                .bg(self.theme.error_fg),
+                        );
+                    }
+                    if selected_idx != idx && flash {
+                        row = row.style(
+                            Style::default()
+                                .fg(self.theme.header_fg)
+                                .bg(self.theme.warning_fg),
+                        );
                     }
                     // Highlight the selected row
                     if selected_idx == idx {
-                        row = row.style(Style::default().bg(Color::LightBlue));
+                        let mut style = Style::default().bg(self.theme.selection_bg);
+                        if over_threshold {
+                            style = style.fg(self.theme.error_fg).add_modifier(Modifier::BOLD);
+                        }
+                        row = row.style(style);
                     }
                     row
                 },
             )
             .collect::<Vec<_>>();
 
+        // Messages go in the NODE column, or the first one when it's hidden
+        let label_col = columns
+            .iter()
+            .position(|&c| c == MainColumn::Node)
+            .unwrap_or(0);
+
         // If no rows match, show a friendly message row
         if rows.is_empty() {
-            rows.push(Row::new(vec![
-                Cell::from(""),
-                Cell::from("No matching nodes"),
-                Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
-            ]));
+            let mut cells = vec![Cell::from(""); widths.len()];
+            cells[label_col] = Cell::from("No matching nodes");
+            rows.push(Row::new(cells));
         }
 
-        let constraints = [
-            Constraint::Length(widths[0] as u16),
-            Constraint::Length(widths[1] as u16),
-            Constraint::Length(widths[2] as u16),
-            Constraint::Length(widths[3] as u16),
-            Constraint::Length(widths[4] as u16),
-            Constraint::Length(widths[5] as u16),
-            Constraint::Length(widths[6] as u16),
-            Constraint::Length(widths[7] as u16),
-        ];
+        let constraints: Vec<Constraint> = widths
+            .iter()
+            .map(|&w| Constraint::Length(w as u16))
+            .collect();
 
-        Table::new(rows, constraints)
+        // Totals across every filtered node, not just the visible rows
+        let (total_recv, total_xmt, total_errs) =
+            node_info
+                .iter()
+                .fold((0.0, 0.0, 0u128), |(recv, xmt, errs), row| {
+                    (
+                        recv + row.recv_bw,
+                        xmt + row.send_bw,
+                        errs + row.error_count,
+                    )
+                });
+        let mut totals_cells: Vec<Cell> = columns
+            .iter()
+            .map(|&col| match col {
+                MainColumn::RecvBw => Cell::from(self.format_bw(total_recv)),
+                MainColumn::SendBw => Cell::from(self.format_bw(total_xmt)),
+                MainColumn::ErrCnt => Cell::from(group_thousands(total_errs)),
+                _ => Cell::from(""),
+            })
+            .collect();
+        totals_cells[label_col] = Cell::from(format!("TOTAL ({} nodes)", node_info.len()));
+        let totals = Row::new(totals_cells).style(
+            Style::default()
+                .fg(self.theme.header_fg)
+                .bg(self.theme.header_bg)
+                .add_modifier(Modifier::BOLD),
+        );
+
+        // Remember where the table and its columns landed for mouse hit-testing; this mirrors
+        // the layout `Table` does internally.
+        self.table_area.set(area);
+        *self.column_areas.borrow_mut() = Layout::horizontal(constraints.clone())
+            .flex(Flex::Start)
+            .spacing(1)
+            .split(area)
+            .to_vec();
+
+        Table::new(rows, constraints.clone())
             .header(header)
             .render(area, buf);
+        render_scrollbar(area, node_info.len(), offset, visible_rows, buf);
+
+        Table::new([totals], constraints).render(totals_area, buf);
     }
 
     /// Render the footer section with keyboard shortcuts and application status.
@@ -320,8 +733,17 @@ impl App {
             .border_type(BorderType::Plain)
             .borders(Borders::TOP);
         let left_footer_text = vec![
-            Line::from(" d = Fabric Discovery".green()),
-            Line::from(" u = Update Counters".green()),
+            Line::from(" d = Fabric Discovery".fg(self.theme.label_fg)),
+            Line::from(" u = Update Counters".fg(self.theme.label_fg)),
+            Line::from(vec![
+                " E = Error Highlight: ".fg(self.theme.label_fg),
+                if self.error_highlight {
+                    Span::from(format!("ERR_CNT > {}", self.error_threshold))
+                        .fg(self.theme.error_fg)
+                } else {
+                    Span::from("off")
+                },
+            ]),
         ];
 
         Paragraph::new(left_footer_text)
@@ -333,15 +755,25 @@ impl App {
             .border_type(BorderType::Plain)
             .borders(Borders::TOP);
         let mid_footer_text = vec![
-            Line::from(if self.auto_update {
-                " U = Auto Update".yellow()
-            } else {
-                " U = Auto Update".green()
-            }),
             Line::from(vec![
-                Span::from(format!(" W/D/B = Whole/Delta/Baseline: ").green()),
+                if self.auto_update {
+                    " U = Auto Update".fg(self.theme.warning_fg)
+                } else {
+                    " U = Auto Update".fg(self.theme.label_fg)
+                },
+                if self.visible_only_updates {
+                    ", v = Visible Only".fg(self.theme.warning_fg)
+                } else {
+                    ", v = Visible Only".fg(self.theme.label_fg)
+                },
+            ]),
+            Line::from(vec![
+                Span::from(" W/D/P/B = Whole/Delta/Rate/Baseline: ").fg(self.theme.label_fg),
                 Span::from(format!("{:?}", self.counter_mode)),
             ]),
+            Line::from(
+                " a = Bandwidth Alerts, p = Pause, X = Clear Counters".fg(self.theme.label_fg),
+            ),
         ];
 
         Paragraph::new(mid_footer_text)
@@ -353,9 +785,11 @@ impl App {
             .border_type(BorderType::Plain)
             .borders(Borders::TOP);
         let right_footer_text = vec![
-            Line::from(" s = Sort".green()),
-            Line::from(" S = Sort Asc/Desc".green()),
-            //Line::from(" PgUp/PgDn/Home/End = Navigate".green()),
+            Line::from(" s/S = Sort / Asc-Desc, j/k/gg/G = Move".fg(self.theme.label_fg)),
+            Line::from(" Alt+s/S = Secondary Sort, y = Copy, T = Type".fg(self.theme.label_fg)),
+            Line::from(
+                " / = Search, ^U = Clear, Enter = Details, c = Columns".fg(self.theme.label_fg),
+            ),
         ];
 
         Paragraph::new(right_footer_text)
@@ -378,14 +812,16 @@ impl App {
         let rect = Rect::new(popup_info.0, popup_info.1, popup_info.2, popup_info.3);
 
         Clear.render(rect, buf);
-        self.search_form.render(rect, buf);
+        // Rows are cached, so counting them on every keystroke is cheap
+        self.search_form
+            .render(rect, buf, self.filtered_len(), self.nodes.len());
     }
 
     fn render_details_popup(&self, area: Rect, buf: &mut Buffer) {
         // Don't render details popup if no node is selected
-        if self.selected_node.is_none() {
+        let Some(node) = &self.selected_node else {
             return;
-        }
+        };
 
         let popup_info = centered_rect_percent(
             DETAILS_POPUP_PERCENT_WIDTH,
@@ -397,60 +833,58 @@ impl App {
 
         Clear.render(rect, buf);
 
-        let node = self.selected_node.clone().unwrap_or((
-            0,
-            0,
-            "".to_owned(),
-            0,
-            0.0,
-            0.0,
-            0.0,
-            0,
-            "".to_owned(),
-        ));
-
-        let title = format!(
-            "Details - Index: {}, GUID: 0x{:x}, Lid: {}, Desc: {}",
-            self.selected, node.0, node.1, node.2
+        let mut title = format!(
+            "Details - Index: {}, GUID: {}, Lid: {}, Desc: {}",
+            self.selected,
+            format_guid(node.guid),
+            node.lid,
+            node.name
         );
+        // The node's aggregate throughput, when the total row was queried successfully
+        if let Some(ctrs) = self
+            .display_counters
+            .get(&(node.hca.clone(), node.lid, AGG_COUNTERS_PORT))
+            .filter(|ctrs| !is_query_failed(ctrs))
+        {
+            title.push_str(&format!(
+                ", Recv: {}, Send: {}",
+                self.format_bw(get_bw(ctrs, "rcv_bytes", &self.counter_mode)),
+                self.format_bw(get_bw(ctrs, "xmt_bytes", &self.counter_mode))
+            ));
+        }
 
         let block = Block::new().title(title).borders(Borders::ALL);
 
-        let inner_area = block.inner(rect);
-        let widths = compute_column_widths(inner_area.width, &DETAILS_TABLE_COLUMN_RATIOS);
-
-        // Prepare node info (only for the selected node's ports).
-        let mut node_info: Vec<(i32, String, String, f64, f64, f64, u128, String)> = Vec::new();
-        if let Some(selected) = &self.selected_node {
-            if let Some(n) = self.nodes.iter().find(|n| n.guid == selected.0) {
-                let lid = n.lid;
-                for p in &n.ports {
-                    let port = p.number;
-                    let ctrs = self.display_counters.get(&(lid, port));
-                    let recv_bw = ctrs.map_or(0.0, |c| get_bw(c, "rcv_bytes", &self.counter_mode));
-                    let xmt_bw = ctrs.map_or(0.0, |c| get_bw(c, "xmt_bytes", &self.counter_mode));
-                    let xmit_waits =
-                        ctrs.map_or(0.0, |c| get_bw_loss(c, "xmit_waits", &self.counter_mode));
-                    let error_count = ctrs.map_or(0, |c| count_errors(c));
-                    let error_strings = ctrs.map_or(String::new(), |c| get_error_strings(c));
-                    node_info.push((
-                        port,
-                        p.link_state.clone(),
-                        p.remote_node_description.clone(),
-                        recv_bw,
-                        xmt_bw,
-                        xmit_waits,
-                        error_count,
-                        error_strings,
-                    ));
-                }
-            }
-        }
+        // Port table, optional chart and legend
+        let (table_pct, chart_pct) = if self.show_details_chart {
+            (60, 40)
+        } else {
+            (100, 0)
+        };
+        let popup_layout = Layout::vertical([
+            Constraint::Percentage(table_pct),
+            Constraint::Percentage(chart_pct),
+            Constraint::Length(1),
+        ])
+        .split(block.inner(rect));
+        let inner_area = popup_layout[0];
 
-        node_info.sort_by(|a, b| a.0.cmp(&b.0));
+        // `--extra-counters` columns get a fixed width each; the rest is spread by ratio
+        let extra_counters = self.config.extra_counters.as_deref().unwrap_or_default();
+        let extra_widths: Vec<usize> = extra_counters
+            .iter()
+            .map(|name| (name.len() + 1).max(EXTRA_COUNTER_MIN_WIDTH))
+            .collect();
+        let extra_total = extra_widths.iter().sum::<usize>().min(u16::MAX as usize) as u16;
+        let widths = compute_column_widths(
+            inner_area.width.saturating_sub(extra_total),
+            &DETAILS_TABLE_COLUMN_RATIOS,
+        );
+
+        let node_info = self.details_rows();
 
         let visible_rows = inner_area.height.saturating_sub(1) as usize;
-        self.visible_rows.set(visible_rows);
+        self.popup_visible_rows.set(visible_rows);
         let offset = self
             .popup_table_offset
             .min(node_info.len().saturating_sub(visible_rows));
@@ -461,76 +895,562 @@ impl App {
             .skip(offset)
             .take(visible_rows)
             .map(
-                |(idx, (port, state, node_desc, r_bw, x_bw, waits, errs, err_str))| {
-                    let mut row = Row::new(vec![
-                        Cell::from(format!("{}", port)),
-                        Cell::from(state.as_str()),
-                        Cell::from(truncate_fit(node_desc, widths[3])),
-                        Cell::from(format!("{:.2}", r_bw)),
-                        Cell::from(format!("{:.2}", x_bw)),
-                        Cell::from(format!("{:.2}", waits)),
-                        Cell::from(format!("{}", errs)),
-                        Cell::from(truncate_fit(err_str, widths[8])),
-                    ]);
+                |(
+                    idx,
+                    DetailsPortInfo {
+                        port,
+                        link_state,
+                        link_status,
+                        link,
+                        remote_description,
+                        recv_bw,
+                        send_bw,
+                        bw_loss,
+                        xmit_waits_wrapped,
+                        error_count,
+                        error_strings,
+                        query_failed,
+                    },
+                )| {
+                    let ctrs = self
+                        .display_counters
+                        .get(&(node.hca.clone(), node.lid, *port));
+                    let port = if *port == AGG_COUNTERS_PORT {
+                        "ALL".to_string()
+                    } else {
+                        port.to_string()
+                    };
+                    // Down ports skipped by `--skip-down-ports` have no counters to show
+                    let skipped = self.config.skip_down_ports && *link_status == LinkStatus::Down;
+                    let mut cells = if *query_failed || skipped {
+                        vec![
+                            Cell::from(port),
+                            Cell::from(link_state.as_str()),
+                            Cell::from(truncate_fit(link, widths[3])),
+                            Cell::from(truncate_fit(remote_description, widths[4])),
+                            Cell::from("-"),
+                            Cell::from("-"),
+                            Cell::from("-"),
+                            Cell::from("-"),
+                            Cell::from(if *query_failed {
+                                truncate_fit("query failed", widths[9])
+                            } else {
+                                String::new()
+                            }),
+                        ]
+                    } else {
+                        vec![
+                            Cell::from(port),
+                            Cell::from(link_state.as_str()),
+                            Cell::from(truncate_fit(link, widths[3])),
+                            Cell::from(truncate_fit(remote_description, widths[4])),
+                            Cell::from(self.format_bw(*recv_bw)),
+                            Cell::from(self.format_bw(*send_bw)),
+                            if *xmit_waits_wrapped {
+                                Cell::from(format!("{} ↻", self.format_bw(*bw_loss)))
+                            } else {
+                                Cell::from(self.format_bw(*bw_loss))
+                            },
+                            Cell::from(group_thousands(*error_count)),
+                            Cell::from(truncate_fit(error_strings, widths[9])),
+                        ]
+                    };
+                    cells.extend(extra_counters.iter().map(|counter| {
+                        match ctrs.filter(|_| !*query_failed && !skipped) {
+                            Some(ctrs) if metric_is_bw(counter) => Cell::from(
+                                self.format_bw(get_metric(ctrs, counter, &self.counter_mode)),
+                            ),
+                            Some(ctrs) => Cell::from(group_thousands(
+                                ctrs.get(counter).copied().unwrap_or(0) as u128,
+                            )),
+                            None => Cell::from("-"),
+                        }
+                    }));
+                    let row = Row::new(cells);
+                    // Red for down links, dim for links that are up but not Active, yellow
+                    // for ports whose counters couldn't be read
+                    let mut style = match link_status {
+                        LinkStatus::Down => Style::default().fg(self.theme.error_fg),
+                        LinkStatus::Inactive => Style::default().fg(self.theme.dim_fg),
+                        LinkStatus::Up | LinkStatus::Unknown if *query_failed => {
+                            Style::default().fg(self.theme.warning_fg)
+                        }
+                        LinkStatus::Up | LinkStatus::Unknown => Style::default(),
+                    };
                     // Zebra striping for readability (non-selected)
                     if self.popup_selected != idx && idx % 2 == 1 {
-                        row = row.style(Style::default().bg(Color::Rgb(32, 32, 32)));
+                        style = style.bg(self.theme.zebra_bg);
                     }
                     // Highlight the selected row in the popup
                     if self.popup_selected == idx {
-                        row = row.style(Style::default().bg(Color::LightBlue));
+                        style = style.bg(self.theme.selection_bg);
                     }
-                    row
+                    row.style(style)
                 },
             )
             .collect::<Vec<_>>();
 
+        if rows.is_empty() {
+            rows.push(Row::new(vec![Cell::from(""); 9 + extra_counters.len()]));
+        }
+
+        let mut header_cells: Vec<Cell> = [
+            "PT", "STATE", "LINK", "NODE", "RECV_BW", "SEND_BW", "BW_LOSS", "ERR_CNT", "ERR_STR",
+        ]
+        .into_iter()
+        .map(|title| {
+            if self.popup_sort_column.name() == title {
+                let indicator = if self.popup_sort_ascending {
+                    "▲"
+                } else {
+                    "▼"
+                };
+                Cell::from(format!("{title}{indicator}"))
+            } else {
+                Cell::from(title)
+            }
+        })
+        .collect();
+        header_cells.extend(
+            extra_counters
+                .iter()
+                .map(|counter| Cell::from(counter.to_uppercase())),
+        );
+
+        let header = Row::new(header_cells).style(
+            Style::default()
+                .fg(self.theme.header_fg)
+                .bg(self.theme.header_bg)
+                .add_modifier(Modifier::BOLD),
+        );
+
+        let constraints: Vec<Constraint> = widths[1..]
+            .iter()
+            .chain(&extra_widths)
+            .map(|&w| Constraint::Length(w as u16))
+            .collect();
+
+        let table = Table::new(rows, constraints).header(header);
+
+        table.render(inner_area, buf);
+        render_scrollbar(inner_area, node_info.len(), offset, visible_rows, buf);
+
+        let legend = Line::from(vec![
+            Span::from(" Legend: "),
+            Span::from("link down").fg(self.theme.error_fg),
+            Span::from(" | "),
+            Span::from("link up, port not Active").fg(self.theme.dim_fg),
+            Span::from(" | "),
+            Span::from("query failed").fg(self.theme.warning_fg),
+            Span::from(" | ↻ = counter wrapped"),
+            Span::from(format!(
+                " | c = chart, x = export CSV, f = follow link, r = raw counters, X = clear, s/S = sort, t = {}",
+                self.details_ports.label()
+            )),
+        ]);
+        Paragraph::new(legend).render(popup_layout[2], buf);
+
+        if self.show_details_chart {
+            self.render_bw_chart(node.guid, popup_layout[1], buf);
+        }
+
+        block.render(rect, buf);
+    }
+
+    fn render_alerts_popup(&self, area: Rect, buf: &mut Buffer) {
+        let popup_info = centered_rect_percent(
+            ALERTS_POPUP_PERCENT_WIDTH,
+            ALERTS_POPUP_PERCENT_HEIGHT,
+            area,
+        );
+
+        let rect = Rect::new(popup_info.0, popup_info.1, popup_info.2, popup_info.3);
+
+        Clear.render(rect, buf);
+
+        let title = match self.config.bw_alert_gbps {
+            Some(gbps) => format!(
+                "Bandwidth Alerts - {} total, threshold {:.2} Gbps (c = clear)",
+                self.bw_alert_count, gbps
+            ),
+            None => "Bandwidth Alerts - disabled (use --bw-alert-gbps)".to_string(),
+        };
+
+        let block = Block::new().title(title).borders(Borders::ALL);
+        let inner_area = block.inner(rect);
+        let widths = compute_column_widths(inner_area.width, &ALERTS_TABLE_COLUMN_RATIOS);
+
+        // Newest first; only as many as fit.
+        let visible_rows = inner_area.height.saturating_sub(1) as usize;
+        let mut rows = self
+            .bw_alerts
+            .iter()
+            .rev()
+            .take(visible_rows)
+            .map(|alert| {
+                Row::new(vec![
+                    Cell::from(alert.timestamp.to_rfc3339_opts(SecondsFormat::Secs, false)),
+                    Cell::from(format!("{}", alert.lid)),
+                    Cell::from(truncate_fit(&alert.node_description, widths[2])),
+                    Cell::from(alert.metric),
+                    Cell::from(format!("{:.2}", alert.gbps)),
+                ])
+            })
+            .collect::<Vec<_>>();
+
         if rows.is_empty() {
             rows.push(Row::new(vec![
                 Cell::from(""),
                 Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
-                Cell::from(""),
+                Cell::from("No alerts"),
             ]));
         }
 
-        let header_cells = vec![
-            Cell::from("PT"),
-            Cell::from("STATE"),
+        let header = Row::new(vec![
+            Cell::from("TIME"),
+            Cell::from("LID"),
             Cell::from("NODE"),
-            Cell::from("RECV_BW"),
-            Cell::from("SEND_BW"),
-            Cell::from("BW_LOSS"),
-            Cell::from("ERR_CNT"),
-            Cell::from("ERR_STR"),
-        ];
+            Cell::from("METRIC"),
+            Cell::from("GBPS"),
+        ])
+        .style(
+            Style::default()
+                .fg(self.theme.header_fg)
+                .bg(self.theme.header_bg)
+                .add_modifier(Modifier::BOLD),
+        );
 
-        let header = Row::new(header_cells).style(
+        let constraints: Vec<Constraint> = widths
+            .iter()
+            .map(|&w| Constraint::Length(w as u16))
+            .collect();
+
+        Table::new(rows, constraints)
+            .header(header)
+            .render(inner_area, buf);
+
+        block.render(rect, buf);
+    }
+
+    /// Every counter of the port picked in the details popup, as the current counter mode shows
+    /// it.
+    fn render_counters_popup(&self, area: Rect, buf: &mut Buffer) {
+        let popup_info = centered_rect_percent(
+            ALERTS_POPUP_PERCENT_WIDTH,
+            ALERTS_POPUP_PERCENT_HEIGHT,
+            area,
+        );
+        let rect = Rect::new(popup_info.0, popup_info.1, popup_info.2, popup_info.3);
+
+        Clear.render(rect, buf);
+
+        let (_, lid, port) = self.inspect_port;
+        let port_label = if port == AGG_COUNTERS_PORT {
+            "ALL".to_string()
+        } else {
+            port.to_string()
+        };
+        let title = format!(
+            "Counters - Lid: {lid}, Port: {port_label}, Mode: {:?} (Esc = back)",
+            self.counter_mode
+        );
+        let block = Block::new().title(title).borders(Borders::ALL);
+        let inner_area = block.inner(rect);
+
+        let mut counters: Vec<(&String, &u64)> = self
+            .display_counters
+            .get(&self.inspect_port)
+            .map(|c| c.iter().collect())
+            .unwrap_or_default();
+        counters.sort();
+
+        let visible_rows = inner_area.height.saturating_sub(1) as usize;
+        self.popup_visible_rows.set(visible_rows);
+        let offset = self
+            .inspect_offset
+            .min(counters.len().saturating_sub(visible_rows));
+
+        let mut rows = counters
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(visible_rows)
+            .map(|(idx, &(name, &value))| {
+                let value = if counter_wrapped(&self.display_counters[&self.inspect_port], name) {
+                    format!("{} ↻", group_thousands(value as u128))
+                } else {
+                    group_thousands(value as u128)
+                };
+                let row = Row::new(vec![Cell::from(name.as_str()), Cell::from(value)]);
+                if idx % 2 == 1 {
+                    row.style(Style::default().bg(self.theme.zebra_bg))
+                } else {
+                    row
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if rows.is_empty() {
+            rows.push(Row::new(vec![Cell::from(
+                "No counters yet, update counters to read them",
+            )]));
+        }
+
+        let header = Row::new(vec![Cell::from("COUNTER"), Cell::from("VALUE")]).style(
             Style::default()
-                .fg(Color::Black)
-                .bg(Color::White)
+                .fg(self.theme.header_fg)
+                .bg(self.theme.header_bg)
                 .add_modifier(Modifier::BOLD),
         );
 
-        let constraints = [
-            Constraint::Length(widths[1] as u16),
-            Constraint::Length(widths[2] as u16),
-            Constraint::Length(widths[3] as u16),
-            Constraint::Length(widths[4] as u16),
-            Constraint::Length(widths[5] as u16),
-            Constraint::Length(widths[6] as u16),
-            Constraint::Length(widths[7] as u16),
-            Constraint::Length(widths[8] as u16),
+        Table::new(
+            rows,
+            [Constraint::Percentage(60), Constraint::Percentage(40)],
+        )
+        .header(header)
+        .render(inner_area, buf);
+        render_scrollbar(inner_area, counters.len(), offset, visible_rows, buf);
+
+        block.render(rect, buf);
+    }
+
+    /// Plot the recorded recv/send bandwidth of the node with `guid` against elapsed seconds.
+    fn render_bw_chart(&self, guid: u64, area: Rect, buf: &mut Buffer) {
+        let block = Block::new().title("Bandwidth (Gbps)").borders(Borders::TOP);
+
+        let Some(history) = self.bw_history.get(&guid).filter(|h| !h.is_empty()) else {
+            Paragraph::new("No samples yet, update counters to record history")
+                .block(block)
+                .render(area, buf);
+            return;
+        };
+
+        let start = history[0].0;
+        let elapsed = |ts: DateTime<Utc>| (ts - start).num_milliseconds() as f64 / 1000.0;
+        let recv: Vec<(f64, f64)> = history.iter().map(|&(ts, r, _)| (elapsed(ts), r)).collect();
+        let send: Vec<(f64, f64)> = history.iter().map(|&(ts, _, x)| (elapsed(ts), x)).collect();
+
+        let max_x = recv.last().map_or(0.0, |&(x, _)| x).max(1.0);
+        let max_y = history
+            .iter()
+            .map(|&(_, r, x)| r.max(x))
+            .fold(0.0, f64::max)
+            .max(0.01)
+            * 1.1;
+
+        let datasets = vec![
+            Dataset::default()
+                .name("recv")
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(self.theme.label_fg))
+                .data(&recv),
+            Dataset::default()
+                .name("send")
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(self.theme.warning_fg))
+                .data(&send),
         ];
 
-        let table = Table::new(rows, constraints).header(header);
+        let x_axis = Axis::default()
+            .title("seconds")
+            .bounds([0.0, max_x])
+            .labels([
+                Span::from("0"),
+                Span::from(format!("{:.0}", max_x / 2.0)),
+                Span::from(format!("{:.0}", max_x)),
+            ]);
+        let y_axis = Axis::default().title("Gbps").bounds([0.0, max_y]).labels([
+            Span::from("0"),
+            Span::from(format!("{:.2}", max_y / 2.0)),
+            Span::from(format!("{:.2}", max_y)),
+        ]);
 
-        table.render(inner_area, buf);
+        Chart::new(datasets)
+            .block(block)
+            .x_axis(x_axis)
+            .y_axis(y_axis)
+            .render(area, buf);
+    }
+
+    fn render_columns_popup(&self, area: Rect, buf: &mut Buffer) {
+        // Room below the list for the selected column's description
+        const DESCRIPTION_LINES: u16 = 4;
+
+        let height = MainColumn::ALL.len() as u16 + 2 + DESCRIPTION_LINES;
+        let popup_info = centered_rect_percent_w_lines_h(COLUMNS_POPUP_PERCENT_WIDTH, height, area);
+        let rect = Rect::new(popup_info.0, popup_info.1, popup_info.2, popup_info.3);
+
+        Clear.render(rect, buf);
 
+        let lines: Vec<Line> = MainColumn::ALL
+            .iter()
+            .enumerate()
+            .map(|(idx, col)| {
+                let mark = if self.columns.contains(col) { "x" } else { " " };
+                let line = Line::from(format!(" [{mark}] {}", column_title(*col)));
+                if idx == self.columns_selected {
+                    line.style(Style::default().bg(self.theme.selection_bg))
+                } else {
+                    line
+                }
+            })
+            .collect();
+
+        let block = Block::new()
+            .title("Columns (Space = toggle)")
+            .borders(Borders::ALL);
+        let inner = block.inner(rect);
         block.render(rect, buf);
+
+        let layout = Layout::vertical([
+            Constraint::Length(MainColumn::ALL.len() as u16),
+            Constraint::Length(DESCRIPTION_LINES),
+        ])
+        .split(inner);
+        Paragraph::new(lines).render(layout[0], buf);
+
+        if let Some(&col) = MainColumn::ALL.get(self.columns_selected) {
+            let description = column_description(col, &self.counter_mode);
+            Paragraph::new(format!(" {description}"))
+                .fg(self.theme.dim_fg)
+                .wrap(Wrap { trim: false })
+                .block(Block::new().borders(Borders::TOP))
+                .render(layout[1], buf);
+        }
+    }
+
+    fn render_metric_popup(&self, area: Rect, buf: &mut Buffer) {
+        let height = METRIC_COUNTERS.len() as u16 + 2;
+        let popup_info = centered_rect_percent_w_lines_h(COLUMNS_POPUP_PERCENT_WIDTH, height, area);
+        let rect = Rect::new(popup_info.0, popup_info.1, popup_info.2, popup_info.3);
+
+        Clear.render(rect, buf);
+
+        let lines: Vec<Line> = METRIC_COUNTERS
+            .iter()
+            .enumerate()
+            .map(|(idx, &counter)| {
+                let mark = if counter == self.metric_counter {
+                    "*"
+                } else {
+                    " "
+                };
+                let line = Line::from(format!(" {mark} {counter}"));
+                if idx == self.metric_selected {
+                    line.style(Style::default().bg(self.theme.selection_bg))
+                } else {
+                    line
+                }
+            })
+            .collect();
+
+        Paragraph::new(lines)
+            .block(
+                Block::new()
+                    .title("Metric (Enter = show)")
+                    .borders(Borders::ALL),
+            )
+            .render(rect, buf);
+    }
+
+    /// Recent status lines, warnings and errors from ibtop and its services, newest first.
+    fn render_log_popup(&self, area: Rect, buf: &mut Buffer) {
+        let popup_info = centered_rect_percent(
+            ALERTS_POPUP_PERCENT_WIDTH,
+            ALERTS_POPUP_PERCENT_HEIGHT,
+            area,
+        );
+        let rect = Rect::new(popup_info.0, popup_info.1, popup_info.2, popup_info.3);
+
+        Clear.render(rect, buf);
+
+        let log = LOG_LINES.lock().map(|l| l.clone()).unwrap_or_default();
+        let lines: Vec<Line> = if log.is_empty() {
+            vec![Line::from(" No messages yet")]
+        } else {
+            log.iter()
+                .rev()
+                .take(rect.height.saturating_sub(2) as usize)
+                .map(|l| Line::from(format!(" {l}")))
+                .collect()
+        };
+
+        Paragraph::new(lines)
+            .block(
+                Block::new()
+                    .title(format!("Log - {} messages", log.len()))
+                    .borders(Borders::ALL),
+            )
+            .render(rect, buf);
+    }
+
+    fn render_reset_prompt(&self, area: Rect, buf: &mut Buffer) {
+        let Some(target) = &self.reset_target else {
+            return;
+        };
+
+        let popup_info = centered_rect_percent_w_lines_h(RESET_PROMPT_PERCENT_WIDTH, 6, area);
+        let rect = Rect::new(popup_info.0, popup_info.1, popup_info.2, popup_info.3);
+
+        Clear.render(rect, buf);
+
+        let name = self
+            .nodes
+            .iter()
+            .find(|n| n.lid == target.lid)
+            .map_or("", |n| self.node_name(n));
+        let lines = vec![
+            Line::from(format!(
+                " Clear the counters of {} ({name})?",
+                counter_reset_label(target.lid, target.number)
+            )),
+            Line::from(" Every counter restarts from zero; this can't be undone.")
+                .fg(self.theme.warning_fg),
+            Line::from(""),
+            Line::from(" y = Clear, n/Esc = Cancel".fg(self.theme.label_fg)),
+        ];
+
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(
+                Block::new()
+                    .title("Clear Counters")
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(self.theme.warning_fg)),
+            )
+            .render(rect, buf);
+    }
+
+    fn render_warnings_popup(&self, area: Rect, buf: &mut Buffer) {
+        let popup_info = centered_rect_percent(
+            ALERTS_POPUP_PERCENT_WIDTH,
+            ALERTS_POPUP_PERCENT_HEIGHT,
+            area,
+        );
+        let rect = Rect::new(popup_info.0, popup_info.1, popup_info.2, popup_info.3);
+
+        Clear.render(rect, buf);
+
+        let lines: Vec<Line> = if self.discovery_warnings.is_empty() {
+            vec![Line::from(" No warnings")]
+        } else {
+            self.discovery_warnings
+                .iter()
+                .map(|w| Line::from(format!(" {w}")))
+                .collect()
+        };
+
+        Paragraph::new(lines)
+            .block(
+                Block::new()
+                    .title(format!(
+                        "Discovery Warnings - {}",
+                        self.discovery_warnings.len()
+                    ))
+                    .borders(Borders::ALL),
+            )
+            .render(rect, buf);
     }
 }